@@ -0,0 +1,160 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+};
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use lumina_ecs::query::Query;
+use lumina_object::{entity::Entity, game_object::Component, transform::Transform};
+
+const ENTITY_COUNTS: [usize; 2] = [10_000, 100_000];
+
+struct Velocity(glam::Vec3);
+
+impl Component for Velocity {}
+
+/// How `Query` used to store its entities, each behind its own lock.
+type EntityMap = RwLock<HashMap<u32, Arc<RwLock<Entity>>>>;
+
+/// Every entity has a transform, every other one a velocity too.
+fn new_entity(index: usize) -> Entity {
+    let mut entity = Entity::new();
+    entity.add_component(Transform::default());
+
+    if index % 2 == 0 {
+        entity.add_component(Velocity(glam::Vec3::ONE));
+    }
+
+    entity
+}
+
+fn new_query(count: usize) -> Query {
+    let mut query = Query::new();
+
+    for index in 0..count {
+        query.spawn_entity_with_id(index as u128 + 1, new_entity(index)).unwrap();
+    }
+
+    query
+}
+
+fn new_entity_map(count: usize) -> EntityMap {
+    let entities = (0..count)
+        .map(|index| (index as u32 + 1, Arc::new(RwLock::new(new_entity(index)))))
+        .collect();
+
+    RwLock::new(entities)
+}
+
+/// Reads the transform of every entity.
+fn iterate(c: &mut Criterion) {
+    let mut group = c.benchmark_group("iterate_transforms");
+
+    for count in ENTITY_COUNTS {
+        let query = new_query(count);
+        let entity_map = new_entity_map(count);
+
+        group.bench_with_input(BenchmarkId::new("columns", count), &query, |b, query| {
+            b.iter(|| {
+                let transforms = query.column::<Transform>().unwrap();
+
+                transforms
+                    .values()
+                    .iter()
+                    .fold(glam::Vec3::ZERO, |sum, transform| sum + transform.translation)
+            })
+        });
+
+        group.bench_with_input(BenchmarkId::new("entity_map", count), &entity_map, |b, entity_map| {
+            b.iter(|| {
+                entity_map
+                    .read()
+                    .unwrap()
+                    .values()
+                    .fold(glam::Vec3::ZERO, |sum, entity| {
+                        sum + entity.read().unwrap().get_component::<Transform>().unwrap().translation
+                    })
+            })
+        });
+    }
+
+    group.finish();
+}
+
+/// Moves every entity having a velocity by it.
+fn join(c: &mut Criterion) {
+    let mut group = c.benchmark_group("move_by_velocity");
+
+    for count in ENTITY_COUNTS {
+        let query = new_query(count);
+        let entity_map = new_entity_map(count);
+
+        group.bench_with_input(BenchmarkId::new("columns", count), &query, |b, query| {
+            b.iter(|| {
+                let velocities = query.column::<Velocity>().unwrap();
+                let mut transforms = query.column_mut::<Transform>().unwrap();
+
+                for (id, velocity) in velocities.iter() {
+                    if let Some(transform) = transforms.get_mut(id) {
+                        transform.translation += velocity.0;
+                    }
+                }
+            })
+        });
+
+        group.bench_with_input(BenchmarkId::new("entity_map", count), &entity_map, |b, entity_map| {
+            b.iter(|| {
+                for entity in entity_map.read().unwrap().values() {
+                    let velocity = match entity.read().unwrap().get_component::<Velocity>() {
+                        Some(velocity) => velocity.0,
+                        None => continue,
+                    };
+
+                    if let Some(transform) = entity.write().unwrap().get_mut_component::<Transform>() {
+                        transform.translation += velocity;
+                    }
+                }
+            })
+        });
+    }
+
+    group.finish();
+}
+
+/// Spawns then kills every entity.
+fn spawn(c: &mut Criterion) {
+    let mut group = c.benchmark_group("spawn_and_kill");
+    group.sample_size(10);
+
+    for count in ENTITY_COUNTS {
+        group.bench_with_input(BenchmarkId::new("columns", count), &count, |b, count| {
+            b.iter(|| {
+                let mut query = new_query(*count);
+
+                for id in 1..=*count as u128 {
+                    query.remove_entity(id);
+                }
+
+                black_box(query.len())
+            })
+        });
+
+        group.bench_with_input(BenchmarkId::new("entity_map", count), &count, |b, count| {
+            b.iter(|| {
+                let entity_map = new_entity_map(*count);
+
+                for id in 1..=*count as u32 {
+                    entity_map.write().unwrap().remove(&id);
+                }
+
+                let len = entity_map.read().unwrap().len();
+                black_box(len)
+            })
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, iterate, join, spawn);
+criterion_main!(benches);
@@ -1,4 +1,5 @@
 use std::{
+    io::Cursor,
     rc::Rc,
     sync::{Arc, RwLock},
 };
@@ -7,13 +8,16 @@ use crate::{device::Device, ImageValue};
 use ash::vk;
 use image::io::Reader as ImageReader;
 use image::{DynamicImage, GenericImage, Rgba};
-use lumina_files::loader::Loader;
+use lumina_files::{file::LuminaFile, loader::Loader};
 use lumina_path::Path;
 
 #[derive(Debug,Clone)]
 pub struct Texture {
     pub texture_path: Path,
     pub pack_id: u32,
+    /// The file of a texture loaded from an archive, kept so the texture can
+    /// be saved again without the file it was packed from.
+    pub packed: Option<Arc<LuminaFile>>,
 }
 
 impl Texture {
@@ -24,11 +28,13 @@ impl Texture {
             Self {
                 texture_path: Path::default(),
                 pack_id: 0,
+                packed: None,
             }
         } else {
             Self {
                 texture_path: file_path.unwrap(),
                 pack_id: 0,
+                packed: None,
             }
         }
     }
@@ -41,6 +47,35 @@ impl Texture {
         Self {
             texture_path: file_path,
             pack_id: 0,
+            packed: None,
+        }
+    }
+
+    /// A texture read from `file`, an entry of the archive's `textures` directory.
+    pub fn new_packed(file: Arc<LuminaFile>) -> Self {
+        Self {
+            texture_path: Path::packed(&file.file_name),
+            pack_id: 0,
+            packed: Some(file),
+        }
+    }
+
+    /// Decodes the image at `texture_path` as RGBA8, the format textures are created with.
+    /// Scene loads decode their textures ahead with it, see `ComponentContext::textures`.
+    pub fn decode(texture_path: &str) -> image::ImageResult<DynamicImage> {
+        Ok(Texture::to_rgba8(image::open(texture_path)?))
+    }
+
+    /// Like [`Texture::decode`], for an image read from an archive.
+    pub fn decode_packed(file: &LuminaFile) -> image::ImageResult<DynamicImage> {
+        Ok(Texture::to_rgba8(image::load_from_memory(&file.file_content)?))
+    }
+
+    fn to_rgba8(texture: DynamicImage) -> DynamicImage {
+        if texture.color() != image::ColorType::Rgba8 {
+            DynamicImage::ImageRgba8(texture.to_rgba8())
+        } else {
+            texture
         }
     }
 
@@ -97,19 +132,14 @@ impl Texture {
     pub fn create_texture(&self) -> DynamicImage {
         if self.texture_path.is_raw_path() {
             if !self.texture_path.get_raw_path().is_empty() {
-                let texture = image::open(self.texture_path.get_raw_path()).unwrap();
-
-                if texture.color() != image::ColorType::Rgba8 {
-                    let texture_buffer = texture.to_rgba8();
-                    DynamicImage::ImageRgba8(texture_buffer)
-                } else {
-                    texture
-                }
+                Texture::decode(self.texture_path.get_raw_path()).unwrap()
             } else {
                 Texture::create_missing_texture(true)
             }
         } else {
-            if !self.texture_path.get_new_path().is_empty() {
+            if let Some(file) = &self.packed {
+                Texture::decode_packed(file).unwrap()
+            } else if !self.texture_path.get_new_path().is_empty() {
                 let texture = image::open(self.texture_path.get_raw_path()).unwrap();
 
                 if texture.color() != image::ColorType::Rgba8 {
@@ -137,7 +167,13 @@ impl Texture {
                 (64, 64)
             }
         } else {
-            if !self.texture_path.get_new_path().is_empty() {
+            if let Some(file) = &self.packed {
+                ImageReader::new(Cursor::new(&file.file_content))
+                    .with_guessed_format()
+                    .unwrap()
+                    .into_dimensions()
+                    .unwrap()
+            } else if !self.texture_path.get_new_path().is_empty() {
                 ImageReader::open(self.texture_path.get_new_path())
                     .unwrap()
                     .with_guessed_format()
@@ -157,6 +193,15 @@ impl Texture {
     pub fn get_raw_path(&self) -> String {
         self.texture_path.get_raw_path().to_string()
     }
+
+    /// What scenes refer to the texture with: its path on disk, or its entry
+    /// name for a texture loaded from an archive.
+    pub fn get_path(&self) -> String {
+        match self.texture_path.is_raw_path() {
+            true => self.get_raw_path(),
+            false => self.get_new_path(),
+        }
+    }
 }
 
 impl ImageValue for Texture {}
@@ -27,8 +27,6 @@ pub struct App {
     pub device: Arc<Device>,
     pub renderer: Arc<RwLock<Renderer>>,
     fps_manager: FPS,
-    keyboard_pool: Keyboard,
-    mouse_pool: Mouse,
     stage: Option<Stage>,
     start_tick: Instant,
     running: bool,
@@ -49,8 +47,6 @@ impl App {
             device,
             renderer,
             fps_manager,
-            keyboard_pool: Keyboard::new(),
-            mouse_pool: Mouse::new(),
             stage: None,
             start_tick: Instant::now(),
             running: true,
@@ -58,16 +54,25 @@ impl App {
         }
     }
 
-    pub fn switch_stage(&mut self, new_stage: Stage) {
+    /// Makes `new_stage` the running stage. The keyboard and the mouse are
+    /// resources of the stage, it gets new ones if it has none.
+    pub fn switch_stage(&mut self, mut new_stage: Stage) {
+        if !new_stage.resources.contains::<Keyboard>() {
+            new_stage.insert_resource(Keyboard::new());
+        }
+
+        if !new_stage.resources.contains::<Mouse>() {
+            new_stage.insert_resource(Mouse::new());
+        }
+
         self.stage = Some(new_stage);
         //self.stage.as_mut().unwrap().create(Rc::clone(&self.device),self.renderer.get_aspect_ratio(),&self.window,&self.renderer_bundle);
     }
 
     pub fn update(&mut self) {
-        /*self.stage
-        .as_mut()
-        .unwrap()
-        .update(Arc::clone(&self.resources_bundle),self.fps_manager._fps as f32);*/
+        if let Some(stage) = self.stage.as_mut() {
+            stage.update();
+        }
     }
 
     /*pub fn render(&mut self) {
@@ -0,0 +1,79 @@
+use std::sync::Arc;
+
+use lumina_files::scene::{read_record, LightRecord, ModelRecord, TransformRecord};
+use lumina_object::{registry::ComponentRegistry, transform::Transform};
+use lumina_pbr::light::Light;
+use lumina_render::model::Model;
+
+/// Registers the engine's own components under the names scenes are saved with.
+pub fn register_builtin_components(registry: &mut ComponentRegistry) {
+    registry.register::<Transform>(
+        "transform",
+        |transform, _| serde_json::to_value(transform.to_record()).unwrap(),
+        |value, _| {
+            let record: TransformRecord = read_record(value)?;
+            record.validate()?;
+
+            Ok(Transform::from_record(&record))
+        },
+    );
+
+    registry.register::<Light>(
+        "light",
+        |light, _| serde_json::to_value(light.to_record()).unwrap(),
+        |value, _| {
+            let record: LightRecord = read_record(value)?;
+            record.validate()?;
+
+            Ok(Light::from_record(&record))
+        },
+    );
+
+    registry.register::<Model>(
+        "model",
+        |model, context| {
+            let mut record = model.to_record(&mut context.meshes);
+
+            // Saved materials refer to their textures by the entry they are packed in
+            for (material, material_record) in model.materials.iter().zip(record.materials.iter_mut()) {
+                for (texture, name) in [
+                    (&material.ambient_texture, &mut material_record.ambient_texture),
+                    (&material.metallic_texture, &mut material_record.metallic_texture),
+                    (&material.normal_texture, &mut material_record.normal_texture),
+                ] {
+                    if name.is_empty() {
+                        continue;
+                    }
+
+                    let entry_name = match &texture.packed {
+                        Some(file) => Ok(context.textures.add(&file.file_name, file.file_content.clone())),
+                        None => context.textures.add_file(name),
+                    };
+
+                    match entry_name {
+                        Ok(entry_name) => *name = entry_name,
+                        Err(err) => {
+                            eprintln!("WARNING: Failed to read the texture {}, it won't be saved: {}", name, err)
+                        }
+                    }
+                }
+            }
+
+            serde_json::to_value(record).unwrap()
+        },
+        |value, context| {
+            let record: ModelRecord = read_record(value)?;
+            record.validate()?;
+
+            let mut model = Model::from_record(
+                Arc::clone(&context.device),
+                &record,
+                &context.meshes,
+                &context.packed_textures,
+            )?;
+            model.init_model_with(context.render_pass, context.light_count, &context.textures);
+
+            Ok(model)
+        },
+    );
+}
@@ -0,0 +1,100 @@
+use std::slice;
+
+use crate::resources::{Res, ResMut, Resources};
+
+/// A queue of events of type `T`, kept as a resource of the stage once
+/// added with [`crate::stage::Stage::add_event`].
+///
+/// Events are double buffered: what is sent during a frame is read during
+/// the next one, so every reader sees all of a frame's events whatever the
+/// order systems run in. Events are dropped after the frame they are read in.
+pub struct Events<T> {
+    /// Events sent during the last frame, the ones readers get.
+    previous: Vec<T>,
+    /// Events sent during this frame.
+    current: Vec<T>,
+}
+
+impl<T> Events<T> {
+    pub fn new() -> Self {
+        Self {
+            previous: Vec::new(),
+            current: Vec::new(),
+        }
+    }
+
+    pub fn send(&mut self, event: T) {
+        self.current.push(event);
+    }
+
+    /// Events sent during the last frame, in the order they were sent.
+    pub fn iter(&self) -> slice::Iter<'_, T> {
+        self.previous.iter()
+    }
+
+    /// Starts a new frame: events sent during the one ending become the
+    /// ones to read, those read during it are dropped.
+    pub fn update(&mut self) {
+        self.previous.clear();
+        std::mem::swap(&mut self.previous, &mut self.current);
+    }
+}
+
+impl<T> Default for Events<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Sends events of type `T`. A system sending them declares write access
+/// to the resource `Events<T>`.
+pub struct EventWriter<'a, T> {
+    events: ResMut<'a, Events<T>>,
+}
+
+impl<'a, T: Send + Sync + 'static> EventWriter<'a, T> {
+    /// `None` if `Events<T>` was never added.
+    pub fn new(resources: &'a Resources) -> Option<Self> {
+        Some(Self {
+            events: resources.get_mut()?,
+        })
+    }
+
+    pub fn send(&mut self, event: T) {
+        self.events.send(event);
+    }
+}
+
+/// Reads the events of type `T` sent during the last frame. A system
+/// reading them declares read access to the resource `Events<T>`.
+pub struct EventReader<'a, T> {
+    events: Res<'a, Events<T>>,
+}
+
+impl<'a, T: Send + Sync + 'static> EventReader<'a, T> {
+    /// `None` if `Events<T>` was never added.
+    pub fn new(resources: &'a Resources) -> Option<Self> {
+        Some(Self {
+            events: resources.get()?,
+        })
+    }
+
+    pub fn iter(&self) -> slice::Iter<'_, T> {
+        self.events.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.events.previous.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.previous.is_empty()
+    }
+}
+
+/// Starts a new frame for the events of type `T`, see [`Events::update`].
+pub(crate) fn update_events<T: Send + Sync + 'static>(resources: &Resources) {
+    if let Some(mut events) = resources.get_mut::<Events<T>>() {
+        events.update();
+    }
+}
@@ -0,0 +1,526 @@
+use std::{any::TypeId, marker::PhantomData, slice, sync::RwLockReadGuard, sync::RwLockWriteGuard};
+
+use lumina_files::id::EntityId;
+use lumina_object::{
+    game_object::Component,
+    storage::{ComponentColumn, SparseIndex, SparseSet},
+};
+
+/// A component type a typed query goes through, and whether it changes it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Access {
+    pub type_id: TypeId,
+    pub type_name: &'static str,
+    pub write: bool,
+}
+
+impl Access {
+    pub fn read<T: Component>() -> Self {
+        Self {
+            type_id: TypeId::of::<T>(),
+            type_name: std::any::type_name::<T>(),
+            write: false,
+        }
+    }
+
+    pub fn write<T: Component>() -> Self {
+        Self {
+            write: true,
+            ..Self::read::<T>()
+        }
+    }
+}
+
+/// Panics if `access` uses a component type mutably more than once, or both
+/// mutably and not. `query_name` is what the panic message names.
+pub(crate) fn check_access(access: &[Access], query_name: &str) {
+    for (index, first) in access.iter().enumerate() {
+        for second in access[index + 1..].iter() {
+            if first.type_id == second.type_id && (first.write || second.write) {
+                panic!(
+                    "{} borrows {} mutably along with another borrow of it",
+                    query_name, first.type_name
+                );
+            }
+        }
+    }
+}
+
+/// Columns locked for a typed query, by component type.
+pub struct FetchColumns<'a> {
+    columns: Vec<FetchColumn>,
+    column: PhantomData<&'a mut dyn ComponentColumn>,
+}
+
+struct FetchColumn {
+    type_id: TypeId,
+    column: *mut dyn ComponentColumn,
+    /// Whether the query fetches the components of the column, not only filters with them.
+    fetched: bool,
+}
+
+impl<'a> FetchColumns<'a> {
+    pub(crate) fn new() -> Self {
+        Self {
+            columns: Vec::new(),
+            column: PhantomData,
+        }
+    }
+
+    /// Adds a column, which must stay borrowed for `'a`: mutably if the
+    /// query writes to it, the column is then only written through this.
+    pub(crate) fn push(&mut self, type_id: TypeId, column: *mut dyn ComponentColumn, fetched: bool) {
+        self.columns.push(FetchColumn {
+            type_id,
+            column,
+            fetched,
+        });
+    }
+
+    fn get(&self, type_id: TypeId) -> Option<&FetchColumn> {
+        self.columns.iter().find(|column| column.type_id == type_id)
+    }
+}
+
+/// What a typed query gives for each entity: `&T`, `&mut T`, or a tuple of
+/// them. Entities missing one of the components are skipped.
+pub trait QueryData {
+    type Item<'a>;
+    type Fetch<'a>;
+
+    fn access(access: &mut Vec<Access>);
+
+    /// `None` if a component has no column, so no entity can match.
+    fn fetch<'a>(columns: &FetchColumns<'a>) -> Option<Self::Fetch<'a>>;
+
+    /// Rows of the smallest column the query needs, the only ones that can match.
+    fn rows<'a>(fetch: &Self::Fetch<'a>) -> &'a [usize];
+
+    /// # Safety
+    ///
+    /// Each row must be fetched once at most.
+    unsafe fn get<'a>(fetch: &mut Self::Fetch<'a>, row: usize) -> Option<Self::Item<'a>>;
+}
+
+impl<T: Component> QueryData for &T {
+    type Item<'a> = &'a T;
+    type Fetch<'a> = &'a SparseSet<T>;
+
+    fn access(access: &mut Vec<Access>) {
+        access.push(Access::read::<T>());
+    }
+
+    fn fetch<'a>(columns: &FetchColumns<'a>) -> Option<Self::Fetch<'a>> {
+        let column = columns.get(TypeId::of::<T>())?;
+        let column: &'a dyn ComponentColumn = unsafe { &*column.column };
+
+        column.as_any().downcast_ref()
+    }
+
+    fn rows<'a>(fetch: &Self::Fetch<'a>) -> &'a [usize] {
+        fetch.rows()
+    }
+
+    unsafe fn get<'a>(fetch: &mut Self::Fetch<'a>, row: usize) -> Option<Self::Item<'a>> {
+        fetch.get(row)
+    }
+}
+
+/// Components of a column fetched mutably, see [`QueryData`].
+pub struct FetchMut<'a, T> {
+    index: SparseIndex<'a>,
+    values: *mut T,
+    component: PhantomData<&'a mut T>,
+}
+
+impl<T: Component> QueryData for &mut T {
+    type Item<'a> = &'a mut T;
+    type Fetch<'a> = FetchMut<'a, T>;
+
+    fn access(access: &mut Vec<Access>) {
+        access.push(Access::write::<T>());
+    }
+
+    fn fetch<'a>(columns: &FetchColumns<'a>) -> Option<Self::Fetch<'a>> {
+        let column = columns.get(TypeId::of::<T>())?;
+        let column: &'a mut dyn ComponentColumn = unsafe { &mut *column.column };
+        let (index, values) = column.as_any_mut().downcast_mut::<SparseSet<T>>()?.split_mut();
+
+        Some(FetchMut {
+            index,
+            values: values.as_mut_ptr(),
+            component: PhantomData,
+        })
+    }
+
+    fn rows<'a>(fetch: &Self::Fetch<'a>) -> &'a [usize] {
+        fetch.index.rows()
+    }
+
+    unsafe fn get<'a>(fetch: &mut Self::Fetch<'a>, row: usize) -> Option<Self::Item<'a>> {
+        let position = fetch.index.position(row)?;
+
+        // Rows are fetched once, so no other reference to this component exists
+        Some(&mut *fetch.values.add(position))
+    }
+}
+
+macro_rules! impl_query_data_tuple {
+    ($($data:ident),+) => {
+        impl<$($data: QueryData),+> QueryData for ($($data,)+) {
+            type Item<'a> = ($($data::Item<'a>,)+);
+            type Fetch<'a> = ($($data::Fetch<'a>,)+);
+
+            fn access(access: &mut Vec<Access>) {
+                $($data::access(access);)+
+            }
+
+            fn fetch<'a>(columns: &FetchColumns<'a>) -> Option<Self::Fetch<'a>> {
+                Some(($($data::fetch(columns)?,)+))
+            }
+
+            #[allow(non_snake_case)]
+            fn rows<'a>(fetch: &Self::Fetch<'a>) -> &'a [usize] {
+                let ($($data,)+) = fetch;
+                let mut rows: Option<&'a [usize]> = None;
+
+                $(
+                    let data_rows = $data::rows($data);
+
+                    if rows.map_or(true, |rows| data_rows.len() < rows.len()) {
+                        rows = Some(data_rows);
+                    }
+                )+
+
+                rows.unwrap()
+            }
+
+            #[allow(non_snake_case)]
+            unsafe fn get<'a>(fetch: &mut Self::Fetch<'a>, row: usize) -> Option<Self::Item<'a>> {
+                let ($($data,)+) = fetch;
+
+                Some(($($data::get($data, row)?,)+))
+            }
+        }
+    };
+}
+
+impl_query_data_tuple!(A);
+impl_query_data_tuple!(A, B);
+impl_query_data_tuple!(A, B, C);
+impl_query_data_tuple!(A, B, C, D);
+impl_query_data_tuple!(A, B, C, D, E);
+impl_query_data_tuple!(A, B, C, D, E, F);
+
+/// Whether an entity has a component, as far as a filter is concerned.
+pub enum Presence<'a> {
+    /// The query fetches the component, so every entity it gives has it.
+    Fetched,
+    /// No entity has it.
+    Missing,
+    Column(&'a dyn ComponentColumn),
+}
+
+impl<'a> Presence<'a> {
+    fn of<T: Component>(columns: &FetchColumns<'a>) -> Self {
+        match columns.get(TypeId::of::<T>()) {
+            Some(column) if column.fetched => Presence::Fetched,
+            Some(column) => Presence::Column(unsafe { &*column.column }),
+            None => Presence::Missing,
+        }
+    }
+
+    fn has(&self, row: usize) -> bool {
+        match self {
+            Presence::Fetched => true,
+            Presence::Missing => false,
+            Presence::Column(column) => column.contains(row),
+        }
+    }
+}
+
+/// Which entities a typed query keeps: `()` for all of them, [`With`],
+/// [`Without`], or a tuple of filters all entities must pass.
+pub trait QueryFilter {
+    type Fetch<'a>;
+
+    /// Component types the filter looks at.
+    fn types(types: &mut Vec<Access>);
+
+    fn fetch<'a>(columns: &FetchColumns<'a>) -> Self::Fetch<'a>;
+
+    fn matches(fetch: &Self::Fetch<'_>, row: usize) -> bool;
+}
+
+/// Keeps the entities having a `T`, without fetching it.
+pub struct With<T>(PhantomData<T>);
+
+/// Keeps the entities not having a `T`.
+pub struct Without<T>(PhantomData<T>);
+
+impl QueryFilter for () {
+    type Fetch<'a> = ();
+
+    fn types(_: &mut Vec<Access>) {}
+
+    fn fetch<'a>(_: &FetchColumns<'a>) -> Self::Fetch<'a> {}
+
+    fn matches(_: &Self::Fetch<'_>, _: usize) -> bool {
+        true
+    }
+}
+
+impl<T: Component> QueryFilter for With<T> {
+    type Fetch<'a> = Presence<'a>;
+
+    fn types(types: &mut Vec<Access>) {
+        types.push(Access::read::<T>());
+    }
+
+    fn fetch<'a>(columns: &FetchColumns<'a>) -> Self::Fetch<'a> {
+        Presence::of::<T>(columns)
+    }
+
+    fn matches(fetch: &Self::Fetch<'_>, row: usize) -> bool {
+        fetch.has(row)
+    }
+}
+
+impl<T: Component> QueryFilter for Without<T> {
+    type Fetch<'a> = Presence<'a>;
+
+    fn types(types: &mut Vec<Access>) {
+        types.push(Access::read::<T>());
+    }
+
+    fn fetch<'a>(columns: &FetchColumns<'a>) -> Self::Fetch<'a> {
+        Presence::of::<T>(columns)
+    }
+
+    fn matches(fetch: &Self::Fetch<'_>, row: usize) -> bool {
+        !fetch.has(row)
+    }
+}
+
+macro_rules! impl_query_filter_tuple {
+    ($($filter:ident),+) => {
+        impl<$($filter: QueryFilter),+> QueryFilter for ($($filter,)+) {
+            type Fetch<'a> = ($($filter::Fetch<'a>,)+);
+
+            fn types(types: &mut Vec<Access>) {
+                $($filter::types(types);)+
+            }
+
+            fn fetch<'a>(columns: &FetchColumns<'a>) -> Self::Fetch<'a> {
+                ($($filter::fetch(columns),)+)
+            }
+
+            #[allow(non_snake_case)]
+            fn matches(fetch: &Self::Fetch<'_>, row: usize) -> bool {
+                let ($($filter,)+) = fetch;
+
+                $($filter::matches($filter, row))&&+
+            }
+        }
+    };
+}
+
+impl_query_filter_tuple!(A);
+impl_query_filter_tuple!(A, B);
+impl_query_filter_tuple!(A, B, C);
+impl_query_filter_tuple!(A, B, C, D);
+
+/// Goes through the entities matching a typed query, giving each one's id
+/// and components. See [`crate::query::Query::iter`].
+pub struct QueryIter<'a, D: QueryData, F: QueryFilter = ()> {
+    ids: &'a [EntityId],
+    rows: slice::Iter<'a, usize>,
+    data: Option<D::Fetch<'a>>,
+    filter: F::Fetch<'a>,
+}
+
+impl<'a, D: QueryData, F: QueryFilter> QueryIter<'a, D, F> {
+    /// `columns` has to hold every column `D` and `F` use, borrowed for `'a`.
+    pub(crate) fn new(ids: &'a [EntityId], columns: FetchColumns<'a>) -> Self {
+        let data = D::fetch(&columns);
+        let rows = data.as_ref().map_or(&[][..], |data| D::rows(data));
+
+        Self {
+            ids,
+            rows: rows.iter(),
+            filter: F::fetch(&columns),
+            data,
+        }
+    }
+}
+
+impl<'a, D: QueryData, F: QueryFilter> Iterator for QueryIter<'a, D, F> {
+    type Item = (EntityId, D::Item<'a>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let data = self.data.as_mut()?;
+
+        for row in self.rows.by_ref() {
+            if !F::matches(&self.filter, *row) {
+                continue;
+            }
+
+            // The rows of a column are all different
+            if let Some(item) = unsafe { D::get(data, *row) } {
+                return Some((self.ids[*row], item));
+            }
+        }
+
+        None
+    }
+}
+
+enum ColumnGuard<'q> {
+    Read(RwLockReadGuard<'q, Box<dyn ComponentColumn>>),
+    Write(RwLockWriteGuard<'q, Box<dyn ComponentColumn>>),
+}
+
+/// The columns of a typed query locked for as long as it lives, see
+/// [`crate::query::Query::borrow`].
+pub struct QueryBorrow<'q, D: QueryData, F: QueryFilter = ()> {
+    ids: &'q [EntityId],
+    guards: Vec<(TypeId, ColumnGuard<'q>, bool)>,
+    query: PhantomData<(D, F)>,
+}
+
+impl<'q, D: QueryData, F: QueryFilter> QueryBorrow<'q, D, F> {
+    pub(crate) fn new(ids: &'q [EntityId]) -> Self {
+        Self {
+            ids,
+            guards: Vec::new(),
+            query: PhantomData,
+        }
+    }
+
+    pub(crate) fn push_read(&mut self, type_id: TypeId, guard: RwLockReadGuard<'q, Box<dyn ComponentColumn>>, fetched: bool) {
+        self.guards.push((type_id, ColumnGuard::Read(guard), fetched));
+    }
+
+    pub(crate) fn push_write(&mut self, type_id: TypeId, guard: RwLockWriteGuard<'q, Box<dyn ComponentColumn>>) {
+        self.guards.push((type_id, ColumnGuard::Write(guard), true));
+    }
+
+    pub fn iter(&mut self) -> QueryIter<'_, D, F> {
+        let mut columns = FetchColumns::new();
+
+        for (type_id, guard, fetched) in self.guards.iter_mut() {
+            // Columns locked for reading are only ever read through
+            let column: *mut dyn ComponentColumn = match guard {
+                ColumnGuard::Read(guard) => &***guard as *const dyn ComponentColumn as *mut dyn ComponentColumn,
+                ColumnGuard::Write(guard) => &mut ***guard,
+            };
+
+            columns.push(*type_id, column, *fetched);
+        }
+
+        QueryIter::new(self.ids, columns)
+    }
+}
+
+impl<'b, 'q, D: QueryData, F: QueryFilter> IntoIterator for &'b mut QueryBorrow<'q, D, F> {
+    type Item = (EntityId, D::Item<'b>);
+    type IntoIter = QueryIter<'b, D, F>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::mpsc, thread};
+
+    use lumina_files::id::EntityId;
+    use lumina_object::{entity::Entity, game_object::Component};
+
+    use super::{With, Without};
+    use crate::query::Query;
+
+    struct Position(f32);
+    impl Component for Position {}
+
+    struct Frozen;
+    impl Component for Frozen {}
+
+    fn query() -> Query {
+        let mut query = Query::new();
+
+        for id in 1..=6 {
+            let mut entity = Entity::new();
+            entity.add_component(Position(id as f32));
+
+            if id % 2 == 0 {
+                entity.add_component(Frozen);
+            }
+
+            query.spawn_entity_with_id(id, entity).unwrap();
+        }
+
+        query
+    }
+
+    fn sorted(mut ids: Vec<EntityId>) -> Vec<EntityId> {
+        ids.sort();
+        ids
+    }
+
+    #[test]
+    #[should_panic(expected = "mutably along with another borrow")]
+    fn mutable_and_shared_borrow_of_a_type_panics() {
+        let mut query = query();
+
+        query.iter::<(&mut Position, &Position)>().for_each(drop);
+    }
+
+    #[test]
+    fn filters_pick_their_rows() {
+        let mut query = query();
+
+        let frozen = query.iter_filtered::<&Position, With<Frozen>>().map(|(id, _)| id).collect();
+        assert_eq!(sorted(frozen), vec![2, 4, 6]);
+
+        let moving = query.iter_filtered::<&Position, Without<Frozen>>().map(|(id, _)| id).collect();
+        assert_eq!(sorted(moving), vec![1, 3, 5]);
+
+        for (id, position) in query.iter_filtered::<&mut Position, Without<Frozen>>() {
+            position.0 = -(id as f32);
+        }
+
+        for (id, position) in query.iter::<&Position>() {
+            let expected = if id % 2 == 0 { id as f32 } else { -(id as f32) };
+            assert_eq!(position.0, expected);
+        }
+    }
+
+    #[test]
+    fn conflicting_borrows_across_threads_panic() {
+        let query = &query();
+
+        thread::scope(|scope| {
+            let (borrowed_sender, borrowed_receiver) = mpsc::channel();
+            let (done_sender, done_receiver) = mpsc::channel::<()>();
+
+            scope.spawn(move || {
+                let _positions = query.borrow::<&mut Position>();
+                borrowed_sender.send(()).unwrap();
+
+                // Returns once the other thread is gone
+                let _ = done_receiver.recv();
+            });
+
+            let reader = scope.spawn(move || {
+                let _done_sender = done_sender;
+                borrowed_receiver.recv().unwrap();
+
+                query.borrow::<&Position>().iter().count()
+            });
+
+            assert!(reader.join().is_err());
+        });
+    }
+}
@@ -0,0 +1,191 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    path::{Path, PathBuf},
+    sync::RwLock,
+    time::SystemTime,
+};
+
+use lumina_files::{
+    error::LuminaFileError, id::EntityId, key::LuminaKey, mesh::mesh_id_of_entry, scene::EntityRecord,
+};
+use serde_json::Value;
+
+use crate::streaming::{decode_scene, decode_text_scene, DecodedScene, LoadProgress};
+
+struct WatchedFile {
+    modified: Option<SystemTime>,
+    /// Strings through which components refer to the file, empty for the scene itself.
+    keys: BTreeSet<String>,
+}
+
+/// A scene loaded into a stage along with the files it was built from, see
+/// [`crate::stage::Stage::watch_scene`].
+pub struct SceneWatcher {
+    file_path: String,
+    files: BTreeMap<PathBuf, WatchedFile>,
+    /// Modification times the last [`SceneWatcher::poll`] replaced.
+    polled: BTreeMap<PathBuf, Option<SystemTime>>,
+    /// Records the stage's entities were last built from, by id.
+    pub(crate) records: BTreeMap<EntityId, EntityRecord>,
+}
+
+impl SceneWatcher {
+    pub(crate) fn new(file_path: &str, decoded: &DecodedScene) -> Self {
+        let mut watcher = Self {
+            file_path: file_path.to_string(),
+            files: BTreeMap::new(),
+            polled: BTreeMap::new(),
+            records: BTreeMap::new(),
+        };
+
+        let records = decoded
+            .records
+            .iter()
+            .map(|record| (record.id, record.clone()))
+            .collect();
+        watcher.set_scene(decoded, records);
+
+        watcher
+    }
+
+    pub fn file_path(&self) -> &str {
+        &self.file_path
+    }
+
+    /// Reads the watched scene again, a `.lumin` archive or a text scene.
+    pub(crate) fn read(&self, key: &LuminaKey, model_name: Option<&str>) -> Result<DecodedScene, LuminaFileError> {
+        read_scene(&self.file_path, key, model_name)
+    }
+
+    /// Checks the modification time of every watched file. Returns `None` if
+    /// none changed, the strings components refer to the changed files with
+    /// otherwise. Changes are only reported once, unless [`SceneWatcher::retry`] is called.
+    pub(crate) fn poll(&mut self) -> Option<BTreeSet<String>> {
+        let mut changed = None;
+
+        self.polled.clear();
+
+        for (path, file) in self.files.iter_mut() {
+            let modified = modified(path);
+
+            if modified != file.modified {
+                self.polled.insert(path.clone(), file.modified);
+                file.modified = modified;
+                changed
+                    .get_or_insert_with(BTreeSet::new)
+                    .extend(file.keys.iter().cloned());
+            }
+        }
+
+        changed
+    }
+
+    /// Puts back the modification times the last [`SceneWatcher::poll`]
+    /// replaced, so the files it reported are reported again on the next one.
+    pub(crate) fn retry(&mut self) {
+        for (path, modified) in std::mem::take(&mut self.polled) {
+            if let Some(file) = self.files.get_mut(&path) {
+                file.modified = modified;
+            }
+        }
+    }
+
+    /// Remembers `records` as what the stage's entities are built from, and
+    /// watches the files `decoded` uses from now on.
+    pub(crate) fn set_scene(&mut self, decoded: &DecodedScene, records: BTreeMap<EntityId, EntityRecord>) {
+        let mut dependencies: BTreeMap<PathBuf, BTreeSet<String>> = BTreeMap::new();
+
+        dependencies.insert(PathBuf::from(&self.file_path), BTreeSet::new());
+
+        let scene_dir = Path::new(&self.file_path).parent().unwrap_or(Path::new(""));
+
+        for (entry_path, asset) in decoded.assets.iter() {
+            let key = mesh_id_of_entry(entry_path).unwrap_or(entry_path);
+
+            dependencies
+                .entry(scene_dir.join(&asset.path))
+                .or_default()
+                .insert(key.to_string());
+        }
+
+        // Any string of a component naming a file, like texture and model paths, is a dependency
+        let mut strings = BTreeSet::new();
+
+        for record in records.values() {
+            record.components.values().for_each(|value| collect_strings(value, &mut strings));
+        }
+
+        for string in strings {
+            if Path::new(string).is_file() {
+                dependencies.entry(PathBuf::from(string)).or_default().insert(string.to_string());
+            }
+        }
+
+        let mut files = BTreeMap::new();
+
+        for (path, keys) in dependencies {
+            let modified = match self.files.remove(&path) {
+                Some(file) => file.modified,
+                None => modified(&path),
+            };
+
+            files.insert(path, WatchedFile { modified, keys });
+        }
+
+        self.files = files;
+        self.records = records;
+    }
+}
+
+/// Reads the scene at `file_path`, a `.lumin` archive or a text scene.
+pub(crate) fn read_scene(
+    file_path: &str,
+    key: &LuminaKey,
+    model_name: Option<&str>,
+) -> Result<DecodedScene, LuminaFileError> {
+    match Path::new(file_path).extension().map_or(false, |extension| extension == "lumin") {
+        true => decode_scene(file_path, key, model_name, &RwLock::new(LoadProgress::new())),
+        false => decode_text_scene(file_path),
+    }
+}
+
+/// Names of the components that differ between `old` and `new`, or whose
+/// value mentions one of `keys`.
+pub(crate) fn changed_components(
+    old: &EntityRecord,
+    new: &EntityRecord,
+    keys: &BTreeSet<String>,
+) -> BTreeSet<String> {
+    old.components
+        .keys()
+        .chain(new.components.keys())
+        .filter(|name| {
+            old.components.get(*name) != new.components.get(*name)
+                || new.components.get(*name).map_or(false, |value| mentions(value, keys))
+        })
+        .cloned()
+        .collect()
+}
+
+fn mentions(value: &Value, keys: &BTreeSet<String>) -> bool {
+    let mut strings = BTreeSet::new();
+    collect_strings(value, &mut strings);
+
+    strings.iter().any(|string| keys.contains(*string))
+}
+
+fn collect_strings<'a>(value: &'a Value, strings: &mut BTreeSet<&'a str>) {
+    match value {
+        Value::String(string) => {
+            strings.insert(string.as_str());
+        }
+        Value::Array(values) => values.iter().for_each(|value| collect_strings(value, strings)),
+        Value::Object(values) => values.values().for_each(|value| collect_strings(value, strings)),
+        _ => {}
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
@@ -1,6 +1,14 @@
 pub mod app;
+pub mod components;
 pub mod stage;
 pub mod query;
+pub mod fetch;
+pub mod schedule;
+pub mod thread_pool;
+pub mod resources;
+pub mod events;
+pub mod hot_reload;
+pub mod streaming;
     
 /*struct PointLight{
     position:glam::Vec4,
@@ -1,58 +1,593 @@
 use std::{
-    any::Any,
+    any::{Any, TypeId},
     collections::HashMap,
-    sync::{Arc, RwLock},
+    fmt,
+    marker::PhantomData,
+    ops::{Deref, DerefMut},
+    sync::{RwLock, RwLockReadGuard, RwLockWriteGuard, TryLockError},
 };
 
+use lumina_files::{id::EntityId, scene::SceneError};
+use crate::fetch::{check_access, Access, FetchColumns, QueryBorrow, QueryData, QueryFilter, QueryIter};
 use lumina_object::{
     entity::Entity,
-    game_object::{Component, GameObject},
+    game_object::{new_persistent_id, Component, GameObject},
+    prefab::{Prefab, PrefabInstance},
+    storage::{ComponentColumn, ComponentInfo, EntityView, SparseSet},
     transform::Transform,
 };
 
+/// The entities of a stage. Each component type has a column of its own
+/// where its components are packed together (see [`SparseSet`]), entities
+/// being a row shared by every column.
+///
+/// Columns are locked one by one, so different component types can be
+/// borrowed at the same time. Spawning, killing and adding components takes
+/// the query mutably.
+///
+/// Borrowing a column that is already borrowed mutably, or borrowing one
+/// mutably while it is borrowed at all, panics instead of waiting for it
+/// like a `RefCell` would, since the borrow that holds it is usually on the
+/// same thread and would never let go. [`Query::iter`] needs no lock at all.
+///
+/// Entities are reached through the [`GameObject`] handles spawning them
+/// returns, which stop working once their entity is killed, or through
+/// their persistent id.
 pub struct Query {
-    pub entities: Arc<RwLock<HashMap<u32, Arc<RwLock<Entity>>>>>,
+    /// Row of each entity, by persistent id.
+    rows: HashMap<EntityId, usize>,
+    /// Entity of each row, rows in `free_rows` belong to no entity.
+    ids: Vec<EntityId>,
+    /// Generation of each row, see [`GameObject`]. It goes up as the row is freed.
+    generations: Vec<u32>,
+    free_rows: Vec<usize>,
+    columns: HashMap<TypeId, RwLock<Box<dyn ComponentColumn>>>,
 }
 
 impl Query {
     pub fn new() -> Self {
         Self {
-            entities: Arc::new(RwLock::new(HashMap::new())),
+            rows: HashMap::new(),
+            ids: Vec::new(),
+            generations: Vec::new(),
+            free_rows: Vec::new(),
+            columns: HashMap::new(),
         }
     }
 
-    pub fn spawn(&self) -> GameObject {
-        let game_object = GameObject::create_game_object();
-        let entity = Arc::new(RwLock::new(Entity::new()));
-        entity.write().unwrap().add_component(Transform::default());
+    /// Spawns an entity with a transform, under a new persistent id.
+    pub fn spawn(&mut self) -> GameObject {
+        let mut entity = Entity::new();
+        entity.add_component(Transform::default());
 
-        self.entities.write().unwrap().insert(game_object.get_id(), entity.clone());
+        let id = self.new_id();
+        self.insert_entity(id, entity)
+    }
+
+    /// Spawns an entity without components under the persistent id `id`.
+    /// Fails if an entity already has the id, kill it first to replace it.
+    pub fn spawn_with_id(&mut self, id: EntityId) -> Result<GameObject, SceneError> {
+        self.spawn_entity_with_id(id, Entity::new())
+    }
+
+    /// Like [`Query::spawn_with_id`], with an entity that already has its components.
+    pub fn spawn_entity_with_id(&mut self, id: EntityId, entity: Entity) -> Result<GameObject, SceneError> {
+        if self.contains(id) {
+            return Err(SceneError::InvalidField {
+                entity: Some(id),
+                field: "id".to_string(),
+                message: "an entity already has this id".to_string(),
+            });
+        }
+
+        Ok(self.insert_entity(id, entity))
+    }
+
+    /// Spawns a new instance of `prefab`, its root first and then its children,
+    /// and returns the root. Nothing is spawned if a component fails to build.
+    pub fn instantiate(&mut self, prefab: &Prefab) -> Result<GameObject, SceneError> {
+        let entities = prefab.build()?;
+
+        let root_id = self.new_id();
+        let mut root = None;
+
+        for (path, mut entity) in entities {
+            let is_root = path.is_empty();
+            let id = match is_root {
+                true => root_id,
+                false => self.new_id(),
+            };
+
+            entity.add_component(PrefabInstance {
+                prefab: prefab.name().to_string(),
+                path,
+                instance: root_id,
+            });
+
+            let game_object = self.insert_entity(id, entity);
+
+            if is_root {
+                root = Some(game_object);
+            }
+        }
+
+        Ok(root.unwrap())
+    }
+
+    /// Every entity of the prefab instance whose root is `root`, the root included.
+    pub fn instance_entities(&self, root: &GameObject) -> Vec<EntityId> {
+        match self.column::<PrefabInstance>() {
+            Some(instances) => instances
+                .iter()
+                .filter(|(_, instance)| instance.instance == root.get_id())
+                .map(|(id, _)| id)
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Adds `component` to the entity of `game_object`, nothing happens if it was killed.
+    pub fn push<T: Component + 'static>(&mut self, game_object: &GameObject, component: T) {
+        if let Some(row) = self.row_of(game_object) {
+            self.insert_component(row, ComponentInfo::of::<T>(), Box::new(component));
+        }
+    }
+
+    pub fn kill(&mut self, game_object: &GameObject) {
+        if let Some(row) = self.row_of(game_object) {
+            self.remove_entity(self.ids[row]);
+        }
+    }
+
+    /// Kills the entity `id`, returning its components.
+    pub fn remove_entity(&mut self, id: EntityId) -> Option<Entity> {
+        let row = self.rows.remove(&id)?;
+        let entity = self.take_components(row);
+
+        self.generations[row] = self.generations[row].wrapping_add(1);
+        self.free_rows.push(row);
+
+        Some(entity)
+    }
+
+    /// Takes the components of the entity `id` out to change them with
+    /// `update`, then puts them back. Returns `None` if there is no such entity.
+    /// Handles to the entity stay valid.
+    pub fn update_entity<R>(&mut self, id: EntityId, update: impl FnOnce(&mut Entity) -> R) -> Option<R> {
+        let row = *self.rows.get(&id)?;
+
+        let mut entity = self.take_components(row);
+        let result = update(&mut entity);
+        self.insert_components(row, entity);
+
+        Some(result)
+    }
+
+    pub fn contains(&self, id: EntityId) -> bool {
+        self.rows.contains_key(&id)
+    }
+
+    /// Whether the entity of `game_object` is still there.
+    pub fn is_alive(&self, game_object: &GameObject) -> bool {
+        self.row_of(game_object).is_some()
+    }
+
+    /// A handle to the entity whose persistent id is `id`.
+    pub fn game_object(&self, id: EntityId) -> Option<GameObject> {
+        let row = *self.rows.get(&id)?;
+
+        Some(GameObject::new(row as u32, self.generations[row], id))
+    }
+
+    /// The persistent id of the entity of `game_object`, if it is still there.
+    pub fn persistent_id(&self, game_object: &GameObject) -> Option<EntityId> {
+        self.row_of(game_object).map(|row| self.ids[row])
+    }
+
+    /// Number of entities.
+    pub fn len(&self) -> usize {
+        self.rows.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    /// Ids of every entity, in no particular order.
+    pub fn ids(&self) -> impl Iterator<Item = EntityId> + '_ {
+        self.rows.keys().copied()
+    }
+
+    pub fn has<T: Component>(&self, game_object: &GameObject) -> bool {
+        match (self.row_of(game_object), self.columns.get(&TypeId::of::<T>())) {
+            (Some(row), Some(column)) => read_column(column, std::any::type_name::<T>()).contains(row),
+            _ => false,
+        }
+    }
+
+    /// The `T` of `game_object`, its column stays locked for reading while it is borrowed.
+    pub fn get<T: Component>(&self, game_object: &GameObject) -> Option<ComponentRef<'_, T>> {
+        let row = self.row_of(game_object)?;
+        let column = self.column::<T>()?;
+
+        match column.set().contains(row) {
+            true => Some(ComponentRef { column, row }),
+            false => None,
+        }
+    }
+
+    /// The `T` of `game_object`, its column stays locked for writing while it is borrowed.
+    pub fn get_mut<T: Component>(&self, game_object: &GameObject) -> Option<ComponentMut<'_, T>> {
+        let row = self.row_of(game_object)?;
+        let column = self.column_mut::<T>()?;
+
+        match column.set().contains(row) {
+            true => Some(ComponentMut { column, row }),
+            false => None,
+        }
+    }
+
+    /// Every `T`, locked for reading. `None` if no entity ever had one.
+    pub fn column<T: Component>(&self) -> Option<Column<'_, T>> {
+        let guard = read_column(self.columns.get(&TypeId::of::<T>())?, std::any::type_name::<T>());
+
+        Some(Column {
+            guard,
+            query: self,
+            component: PhantomData,
+        })
+    }
+
+    /// Every `T`, locked for writing. `None` if no entity ever had one.
+    pub fn column_mut<T: Component>(&self) -> Option<ColumnMut<'_, T>> {
+        let guard = write_column(self.columns.get(&TypeId::of::<T>())?, std::any::type_name::<T>());
+
+        Some(ColumnMut {
+            guard,
+            query: self,
+            component: PhantomData,
+        })
+    }
+
+    /// Locks every column for reading, to go through entities with all of
+    /// their components whatever their types.
+    pub fn entities(&self) -> EntitiesRead<'_> {
+        EntitiesRead {
+            query: self,
+            columns: self
+                .columns
+                .values()
+                .map(|column| read_column(column, "a component column"))
+                .collect(),
+        }
+    }
+
+    /// Every entity having the components `D` asks for, with them:
+    ///
+    /// ```ignore
+    /// for (id, (transform, model)) in query.iter::<(&Transform, &mut Model)>() {}
+    /// ```
+    ///
+    /// Taking the query mutably, no column is locked. Panics if `D` borrows
+    /// a component type mutably more than once.
+    pub fn iter<D: QueryData>(&mut self) -> QueryIter<'_, D> {
+        self.iter_filtered::<D, ()>()
+    }
+
+    /// Like [`Query::iter`], keeping only the entities `F` matches, like
+    /// `(With<Light>, Without<Model>)`.
+    pub fn iter_filtered<D: QueryData, F: QueryFilter>(&mut self) -> QueryIter<'_, D, F> {
+        let (data, filter) = query_access::<D, F>();
+        let mut columns = FetchColumns::new();
+
+        for (type_id, column) in self.columns.iter_mut() {
+            let fetched = data.iter().any(|access| access.type_id == *type_id);
+
+            if fetched || filter.iter().any(|access| access.type_id == *type_id) {
+                let column: &mut dyn ComponentColumn = &mut **column.get_mut().unwrap();
+                columns.push(*type_id, column, fetched);
+            }
+        }
+
+        QueryIter::new(&self.ids, columns)
+    }
+
+    /// Like [`Query::iter`] through a shared query, locking the columns `D`
+    /// and `F` use for as long as the borrow lives. Panics if one of them
+    /// is already borrowed in a way that conflicts.
+    pub fn borrow<D: QueryData>(&self) -> QueryBorrow<'_, D> {
+        self.borrow_filtered::<D, ()>()
+    }
+
+    pub fn borrow_filtered<D: QueryData, F: QueryFilter>(&self) -> QueryBorrow<'_, D, F> {
+        let (data, filter) = query_access::<D, F>();
+        let mut borrow = QueryBorrow::new(&self.ids);
+
+        for (type_id, column) in self.columns.iter() {
+            let mut access = data.iter().filter(|access| access.type_id == *type_id).peekable();
+
+            match access.peek().copied() {
+                Some(first) if access.any(|access| access.write) => {
+                    borrow.push_write(*type_id, write_column(column, first.type_name))
+                }
+                Some(first) => borrow.push_read(*type_id, read_column(column, first.type_name), true),
+                None => {
+                    if let Some(access) = filter.iter().find(|access| access.type_id == *type_id) {
+                        borrow.push_read(*type_id, read_column(column, access.type_name), false);
+                    }
+                }
+            }
+        }
+
+        borrow
+    }
+
+    /// Row of the entity of `game_object`, `None` if the entity was killed.
+    fn row_of(&self, game_object: &GameObject) -> Option<usize> {
+        let row = game_object.index() as usize;
+
+        match self.generations.get(row) {
+            Some(generation) if *generation == game_object.generation() => Some(row),
+            _ => None,
+        }
+    }
 
-        game_object
+    /// A persistent id no entity has.
+    fn new_id(&self) -> EntityId {
+        new_persistent_id(|id| self.rows.contains_key(&id))
     }
 
-    pub fn spawn_with_id(&self,id:u32) -> GameObject {
-        let game_object = GameObject::create_game_object_with_id(id);
-        let entity = Arc::new(RwLock::new(Entity::new()));
+    /// Stores `entity` as `id`, an id no entity has.
+    fn insert_entity(&mut self, id: EntityId, entity: Entity) -> GameObject {
+        let row = match self.free_rows.pop() {
+            Some(row) => {
+                self.ids[row] = id;
+                row
+            }
+            None => {
+                self.ids.push(id);
+                self.generations.push(0);
+                self.ids.len() - 1
+            }
+        };
 
-        self.entities.write().unwrap().insert(game_object.get_id(), entity.clone());
+        self.rows.insert(id, row);
+        self.insert_components(row, entity);
 
-        game_object
+        GameObject::new(row as u32, self.generations[row], id)
     }
 
+    fn take_components(&mut self, row: usize) -> Entity {
+        let mut entity = Entity::new();
+
+        for column in self.columns.values_mut() {
+            let column = column.get_mut().unwrap();
 
-    pub fn push<T: Component + 'static>(&self, game_object: &GameObject, component: T) {
-        if let Some(entity) = self.entities.read().unwrap().get(&game_object.get_id()) {
-            entity.write().unwrap().add_component(component);
+            if let Some(component) = column.remove_any(row) {
+                entity.insert_boxed(column.info(), component);
+            }
         }
+
+        entity
+    }
+
+    fn insert_components(&mut self, row: usize, entity: Entity) {
+        for (info, component) in entity.into_components() {
+            self.insert_component(row, info, component);
+        }
+    }
+
+    fn insert_component(
+        &mut self,
+        row: usize,
+        info: ComponentInfo,
+        component: Box<dyn Any + Send + Sync>,
+    ) {
+        self.columns
+            .entry(info.type_id)
+            .or_insert_with(|| RwLock::new(info.new_column()))
+            .get_mut()
+            .unwrap()
+            .insert_any(row, component);
+    }
+}
+
+/// What the data and the filter of a typed query access, checked for conflicts.
+fn query_access<D: QueryData, F: QueryFilter>() -> (Vec<Access>, Vec<Access>) {
+    let mut data = Vec::new();
+    D::access(&mut data);
+    check_access(&data, std::any::type_name::<D>());
+
+    let mut filter = Vec::new();
+    F::types(&mut filter);
+
+    (data, filter)
+}
+
+fn read_column<'a>(
+    column: &'a RwLock<Box<dyn ComponentColumn>>,
+    type_name: &str,
+) -> RwLockReadGuard<'a, Box<dyn ComponentColumn>> {
+    match column.try_read() {
+        Ok(guard) => guard,
+        Err(TryLockError::Poisoned(error)) => panic!("{}", error),
+        Err(TryLockError::WouldBlock) => panic!("{} is already borrowed mutably", type_name),
+    }
+}
+
+fn write_column<'a>(
+    column: &'a RwLock<Box<dyn ComponentColumn>>,
+    type_name: &str,
+) -> RwLockWriteGuard<'a, Box<dyn ComponentColumn>> {
+    match column.try_write() {
+        Ok(guard) => guard,
+        Err(TryLockError::Poisoned(error)) => panic!("{}", error),
+        Err(TryLockError::WouldBlock) => panic!("{} is already borrowed", type_name),
     }
+}
+
+impl fmt::Debug for Query {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let columns: Vec<(&str, usize)> = self
+            .columns
+            .values()
+            .map(|column| {
+                let column = read_column(column, "a component column");
+                (column.info().type_name, column.len())
+            })
+            .collect();
 
-    pub fn kill(&self, game_object: &GameObject) {
-        self.entities.write().unwrap().remove(&game_object.get_id());
+        f.debug_struct("Query")
+            .field("entities", &self.rows.len())
+            .field("columns", &columns)
+            .finish()
     }
+}
+
+/// The column of `T` locked for reading, see [`Query::column`].
+pub struct Column<'a, T> {
+    guard: RwLockReadGuard<'a, Box<dyn ComponentColumn>>,
+    query: &'a Query,
+    component: PhantomData<T>,
+}
 
-    pub fn query_entity(&self, game_object: &GameObject) -> Option<Arc<RwLock<Entity>>> {
-        self.entities.read().unwrap().get(&game_object.get_id()).cloned()
+impl<'a, T: Component> Column<'a, T> {
+    fn set(&self) -> &SparseSet<T> {
+        self.guard.as_any().downcast_ref().unwrap()
     }
 
+    pub fn len(&self) -> usize {
+        self.set().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.set().is_empty()
+    }
+
+    pub fn get(&self, id: EntityId) -> Option<&T> {
+        self.set().get(*self.query.rows.get(&id)?)
+    }
+
+    /// Every `T` along with the id of its entity, in storage order.
+    pub fn iter(&self) -> impl Iterator<Item = (EntityId, &T)> {
+        let ids = &self.query.ids;
+
+        self.set().iter().map(move |(row, component)| (ids[row], component))
+    }
+
+    /// Every `T` as a slice, in the same order as [`Column::iter`].
+    pub fn values(&self) -> &[T] {
+        self.set().values()
+    }
+}
+
+/// The column of `T` locked for writing, see [`Query::column_mut`].
+pub struct ColumnMut<'a, T> {
+    guard: RwLockWriteGuard<'a, Box<dyn ComponentColumn>>,
+    query: &'a Query,
+    component: PhantomData<T>,
+}
+
+impl<'a, T: Component> ColumnMut<'a, T> {
+    fn set(&self) -> &SparseSet<T> {
+        self.guard.as_any().downcast_ref().unwrap()
+    }
+
+    fn set_mut(&mut self) -> &mut SparseSet<T> {
+        self.guard.as_any_mut().downcast_mut().unwrap()
+    }
+
+    pub fn len(&self) -> usize {
+        self.set().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.set().is_empty()
+    }
+
+    pub fn get(&self, id: EntityId) -> Option<&T> {
+        self.set().get(*self.query.rows.get(&id)?)
+    }
+
+    pub fn get_mut(&mut self, id: EntityId) -> Option<&mut T> {
+        let row = *self.query.rows.get(&id)?;
+        self.set_mut().get_mut(row)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (EntityId, &T)> {
+        let ids = &self.query.ids;
+
+        self.set().iter().map(move |(row, component)| (ids[row], component))
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (EntityId, &mut T)> {
+        let query = self.query;
+        let ids = &query.ids;
+
+        self.set_mut()
+            .iter_mut()
+            .map(move |(row, component)| (ids[row], component))
+    }
+}
+
+/// A component borrowed from its column, see [`Query::get`].
+pub struct ComponentRef<'a, T> {
+    column: Column<'a, T>,
+    row: usize,
+}
+
+impl<'a, T: Component> Deref for ComponentRef<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.column.set().get(self.row).unwrap()
+    }
+}
+
+/// A component borrowed mutably from its column, see [`Query::get_mut`].
+pub struct ComponentMut<'a, T> {
+    column: ColumnMut<'a, T>,
+    row: usize,
+}
+
+impl<'a, T: Component> Deref for ComponentMut<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.column.set().get(self.row).unwrap()
+    }
+}
+
+impl<'a, T: Component> DerefMut for ComponentMut<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        let row = self.row;
+        self.column.set_mut().get_mut(row).unwrap()
+    }
+}
+
+/// Every column locked for reading, see [`Query::entities`].
+pub struct EntitiesRead<'a> {
+    query: &'a Query,
+    columns: Vec<RwLockReadGuard<'a, Box<dyn ComponentColumn>>>,
+}
+
+impl<'a> EntitiesRead<'a> {
+    /// The components of the entity `id`.
+    pub fn get(&self, id: EntityId) -> Option<EntityView<'_>> {
+        let row = *self.query.rows.get(&id)?;
+        let mut view = EntityView::new();
+
+        for column in self.columns.iter() {
+            if let Some(component) = column.get_any(row) {
+                view.push(column.info(), component);
+            }
+        }
+
+        Some(view)
+    }
+
+    /// Every entity with its components, in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = (EntityId, EntityView<'_>)> {
+        self.query.ids().map(|id| (id, self.get(id).unwrap()))
+    }
 }
@@ -0,0 +1,125 @@
+use std::{
+    any::{Any, TypeId},
+    collections::HashMap,
+    fmt,
+    marker::PhantomData,
+    ops::{Deref, DerefMut},
+    sync::{RwLock, RwLockReadGuard, RwLockWriteGuard, TryLockError},
+};
+
+type Resource = Box<dyn Any + Send + Sync>;
+
+/// Values a stage holds one of per type, like the camera or the keyboard,
+/// for systems to reach without them being passed around.
+///
+/// Resources are locked one by one like the columns of a
+/// [`crate::query::Query`], and borrowing one in a way that conflicts with
+/// a borrow already holding it panics the same way.
+pub struct Resources {
+    resources: HashMap<TypeId, RwLock<Resource>>,
+}
+
+impl Resources {
+    pub fn new() -> Self {
+        Self {
+            resources: HashMap::new(),
+        }
+    }
+
+    /// Stores `resource`, returning the one of the same type it replaces.
+    pub fn insert<T: Send + Sync + 'static>(&mut self, resource: T) -> Option<T> {
+        self.resources
+            .insert(TypeId::of::<T>(), RwLock::new(Box::new(resource)))
+            .map(|old| *old.into_inner().unwrap().downcast::<T>().unwrap())
+    }
+
+    pub fn remove<T: Send + Sync + 'static>(&mut self) -> Option<T> {
+        self.resources
+            .remove(&TypeId::of::<T>())
+            .map(|old| *old.into_inner().unwrap().downcast::<T>().unwrap())
+    }
+
+    pub fn contains<T: Send + Sync + 'static>(&self) -> bool {
+        self.resources.contains_key(&TypeId::of::<T>())
+    }
+
+    /// The `T` of the stage, locked for reading while it is borrowed.
+    pub fn get<T: Send + Sync + 'static>(&self) -> Option<Res<'_, T>> {
+        let guard = match self.resources.get(&TypeId::of::<T>())?.try_read() {
+            Ok(guard) => guard,
+            Err(TryLockError::Poisoned(error)) => panic!("{}", error),
+            Err(TryLockError::WouldBlock) => {
+                panic!("{} is already borrowed mutably", std::any::type_name::<T>())
+            }
+        };
+
+        Some(Res {
+            guard,
+            resource: PhantomData,
+        })
+    }
+
+    /// The `T` of the stage, locked for writing while it is borrowed.
+    pub fn get_mut<T: Send + Sync + 'static>(&self) -> Option<ResMut<'_, T>> {
+        let guard = match self.resources.get(&TypeId::of::<T>())?.try_write() {
+            Ok(guard) => guard,
+            Err(TryLockError::Poisoned(error)) => panic!("{}", error),
+            Err(TryLockError::WouldBlock) => {
+                panic!("{} is already borrowed", std::any::type_name::<T>())
+            }
+        };
+
+        Some(ResMut {
+            guard,
+            resource: PhantomData,
+        })
+    }
+}
+
+impl Default for Resources {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for Resources {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Resources")
+            .field("resources", &self.resources.len())
+            .finish()
+    }
+}
+
+/// A resource borrowed from a stage, see [`Resources::get`].
+pub struct Res<'a, T> {
+    guard: RwLockReadGuard<'a, Resource>,
+    resource: PhantomData<T>,
+}
+
+impl<'a, T: 'static> Deref for Res<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.guard.downcast_ref().unwrap()
+    }
+}
+
+/// A resource borrowed mutably from a stage, see [`Resources::get_mut`].
+pub struct ResMut<'a, T> {
+    guard: RwLockWriteGuard<'a, Resource>,
+    resource: PhantomData<T>,
+}
+
+impl<'a, T: 'static> Deref for ResMut<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.guard.downcast_ref().unwrap()
+    }
+}
+
+impl<'a, T: 'static> DerefMut for ResMut<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.guard.downcast_mut().unwrap()
+    }
+}
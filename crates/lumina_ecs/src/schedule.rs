@@ -0,0 +1,247 @@
+use std::{any::TypeId, collections::BTreeMap};
+
+use lumina_files::id::EntityId;
+use lumina_object::game_object::Component;
+
+use crate::{
+    fetch::{check_access, Access, QueryData},
+    query::Query,
+    resources::Resources,
+    thread_pool::{ScopedJob, ThreadPool},
+};
+
+/// When a system runs in a frame. Stages run one after the other in this order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum SystemStage {
+    PreUpdate,
+    Update,
+    PostUpdate,
+    /// Right before the stage is drawn, see [`crate::stage::Stage::render`].
+    Render,
+}
+
+/// A function run every frame on the entities and resources of a stage,
+/// along with the component and resource types it reads and writes.
+///
+/// The access a system declares is what lets it run alongside others, so
+/// it has to cover every column and resource it borrows: borrowing one it didn't declare
+/// can panic when another system holds it at the same time.
+pub struct System {
+    name: String,
+    access: Vec<Access>,
+    run: Box<dyn Fn(&Query, &Resources) + Send + Sync>,
+}
+
+impl System {
+    /// A system that declares no access yet, see [`System::read`] and [`System::write`].
+    pub fn new(name: &str, run: impl Fn(&Query, &Resources) + Send + Sync + 'static) -> Self {
+        Self {
+            name: name.to_string(),
+            access: Vec::new(),
+            run: Box::new(run),
+        }
+    }
+
+    /// A system running `run` on every entity having the components `D` asks
+    /// for, like [`Query::iter`]. Its access is the one of `D`.
+    pub fn for_each<D: QueryData + 'static>(
+        name: &str,
+        run: impl for<'a> Fn(EntityId, D::Item<'a>) + Send + Sync + 'static,
+    ) -> Self {
+        let mut access = Vec::new();
+        D::access(&mut access);
+        check_access(&access, name);
+
+        Self {
+            name: name.to_string(),
+            access,
+            run: Box::new(move |query, _| {
+                let mut borrow = query.borrow::<D>();
+
+                for (id, item) in borrow.iter() {
+                    run(id, item);
+                }
+            }),
+        }
+    }
+
+    pub fn read<T: Component>(mut self) -> Self {
+        self.access.push(Access::read::<T>());
+        self
+    }
+
+    pub fn write<T: Component>(mut self) -> Self {
+        self.access.push(Access::write::<T>());
+        self
+    }
+
+    /// Declares the system reads the resource `T`, see [`Resources`].
+    pub fn read_resource<T: Send + Sync + 'static>(mut self) -> Self {
+        self.access.push(resource_access::<T>(false));
+        self
+    }
+
+    /// Declares the system writes to the resource `T`. Sending events of type
+    /// `E` writes to the resource `Events<E>`.
+    pub fn write_resource<T: Send + Sync + 'static>(mut self) -> Self {
+        self.access.push(resource_access::<T>(true));
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn access(&self) -> &[Access] {
+        &self.access
+    }
+
+    /// Whether the two systems can't run at the same time, one of them
+    /// writing to something the other uses.
+    pub fn conflicts_with(&self, other: &System) -> bool {
+        self.access.iter().any(|access| {
+            other
+                .access
+                .iter()
+                .any(|other| access.type_id == other.type_id && (access.write || other.write))
+        })
+    }
+
+    pub fn run(&self, query: &Query, resources: &Resources) {
+        (self.run)(query, resources)
+    }
+}
+
+fn resource_access<T: 'static>(write: bool) -> Access {
+    Access {
+        type_id: TypeId::of::<T>(),
+        type_name: std::any::type_name::<T>(),
+        write,
+    }
+}
+
+/// The systems of a stage, grouped by [`SystemStage`].
+///
+/// Systems of a stage are split into batches run one after the other, the
+/// systems of a batch running in parallel. A system goes in the first batch
+/// after every system it conflicts with, so conflicting systems still run in
+/// the order they were added, and the others as early as they can.
+pub struct Schedule {
+    stages: BTreeMap<SystemStage, Vec<Vec<System>>>,
+}
+
+impl Schedule {
+    pub fn new() -> Self {
+        Self {
+            stages: BTreeMap::new(),
+        }
+    }
+
+    pub fn add_system(&mut self, stage: SystemStage, system: System) {
+        let batches = self.stages.entry(stage).or_default();
+
+        let first_batch = batches
+            .iter()
+            .rposition(|batch| batch.iter().any(|other| other.conflicts_with(&system)))
+            .map_or(0, |batch| batch + 1);
+
+        match batches.get_mut(first_batch) {
+            Some(batch) => batch.push(system),
+            None => batches.push(vec![system]),
+        }
+    }
+
+    /// Names of the systems of `stage`, batch by batch.
+    pub fn batches(&self, stage: SystemStage) -> Vec<Vec<&str>> {
+        self.stages.get(&stage).map_or(Vec::new(), |batches| {
+            batches
+                .iter()
+                .map(|batch| batch.iter().map(System::name).collect())
+                .collect()
+        })
+    }
+
+    /// Runs the systems of `stage` on `pool`.
+    pub fn run_stage(&self, stage: SystemStage, query: &Query, resources: &Resources, pool: &ThreadPool) {
+        let batches = match self.stages.get(&stage) {
+            Some(batches) => batches,
+            None => return,
+        };
+
+        for batch in batches {
+            let jobs: Vec<ScopedJob> = batch
+                .iter()
+                .map(|system| Box::new(move || system.run(query, resources)) as ScopedJob)
+                .collect();
+
+            pool.run_all(jobs);
+        }
+    }
+}
+
+impl Default for Schedule {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use lumina_object::game_object::Component;
+
+    use super::{Schedule, System, SystemStage};
+
+    struct Position;
+    impl Component for Position {}
+
+    struct Velocity;
+    impl Component for Velocity {}
+
+    struct Gravity;
+
+    fn system(name: &str) -> System {
+        System::new(name, |_, _| {})
+    }
+
+    #[test]
+    fn conflicting_systems_go_in_separate_batches() {
+        let mut schedule = Schedule::new();
+
+        schedule.add_system(SystemStage::Update, system("move").read::<Velocity>().write::<Position>());
+        schedule.add_system(SystemStage::Update, system("draw").read::<Position>());
+        schedule.add_system(SystemStage::Update, system("snap").write::<Position>());
+
+        assert_eq!(
+            schedule.batches(SystemStage::Update),
+            vec![vec!["move"], vec!["draw"], vec!["snap"]]
+        );
+    }
+
+    #[test]
+    fn systems_without_conflicts_share_a_batch() {
+        let mut schedule = Schedule::new();
+
+        schedule.add_system(SystemStage::Update, system("move").write::<Position>());
+        schedule.add_system(SystemStage::Update, system("fall").write::<Velocity>().read_resource::<Gravity>());
+        schedule.add_system(SystemStage::Update, system("draw").read::<Position>());
+        schedule.add_system(SystemStage::Update, system("wind").read::<Velocity>().read_resource::<Gravity>());
+        schedule.add_system(SystemStage::PostUpdate, system("snap").write::<Position>());
+
+        assert_eq!(
+            schedule.batches(SystemStage::Update),
+            vec![vec!["move", "fall"], vec!["draw", "wind"]]
+        );
+        assert_eq!(schedule.batches(SystemStage::PostUpdate), vec![vec!["snap"]]);
+        assert!(schedule.batches(SystemStage::Render).is_empty());
+    }
+
+    #[test]
+    fn resource_writes_conflict() {
+        let mut schedule = Schedule::new();
+
+        schedule.add_system(SystemStage::Update, system("tune").write_resource::<Gravity>());
+        schedule.add_system(SystemStage::Update, system("fall").read_resource::<Gravity>());
+
+        assert_eq!(schedule.batches(SystemStage::Update), vec![vec!["tune"], vec!["fall"]]);
+    }
+}
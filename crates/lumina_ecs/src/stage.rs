@@ -1,10 +1,10 @@
 use std::{
     any::TypeId,
     borrow::BorrowMut,
-    collections::HashMap,
-    fs::File,
-    io::Read,
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fs,
     ops::Deref,
+    path::Path,
     rc::Rc,
     sync::{Arc, Barrier, Mutex, RwLock},
     thread::{self, JoinHandle},
@@ -12,28 +12,43 @@ use std::{
 
 use ash::vk;
 use async_std::path;
-use lumina_core::{
-    device::Device, framebuffer::Framebuffer, image::Image, texture::Texture, window::Window,
-    Vertex3D,
-};
+use lumina_core::{device::Device, framebuffer::Framebuffer, image::Image, window::Window};
 use lumina_data::{buffer::Buffer, descriptor_manager::DescriptorManager};
 use lumina_files::{
-    loader::Loader,
-    saver::{LuminaFile, LuminaFileType, Saver},
+    asset::TEXTURE_DIRECTORY,
+    file::LuminaFileType,
+    id::{format_id, EntityId},
+    key::LuminaKey,
+    mesh::{mesh_entry, MeshEncoding},
+    prefab::{prefab_entry, PrefabFile, PrefabLibrary, PrefabNode},
+    saver::Saver,
+    scene::{AssetRecord, SceneError, SceneFile},
+    text::{asset_path, write_asset},
 };
 use lumina_graphic::shader::Shader;
 use lumina_object::{
     game_object::{Component, GameObject},
+    prefab::{Prefab, PrefabInstance},
+    registry::{ComponentContext, ComponentRegistry, SaveContext},
     transform::Transform,
 };
-use lumina_path::PATHS;
 use lumina_pbr::light::Light;
 use lumina_render::{
-    camera::Camera, mesh::Mesh, model::Model, model::PushConstantData, renderer::Renderer,
+    camera::Camera, model::Model, model::PushConstantData, renderer::Renderer,
 };
+
 use serde_json::Value;
 
-use crate::query::Query;
+use crate::{
+    components::register_builtin_components,
+    events::{update_events, EventWriter, Events},
+    hot_reload::{changed_components, read_scene, SceneWatcher},
+    query::Query,
+    resources::{Res, ResMut, Resources},
+    schedule::{Schedule, System, SystemStage},
+    streaming::{decode_scene, decode_text_scene, DecodedScene, LoadProgress, LoadStep, SceneLoad},
+    thread_pool::ThreadPool,
+};
 
 /*use lumina_object::{
     component_manager::{self, ComponentManager},
@@ -49,14 +64,217 @@ use rand::Rng;*/
 pub struct Stage {
     pub name: String,
     pub manager: Query,
+    pub registry: ComponentRegistry,
+    pub prefabs: PrefabLibrary,
+    pub schedule: Schedule,
+    pub resources: Resources,
+    /// Starts a new frame for each event type added with `add_event`.
+    event_updates: Vec<fn(&Resources)>,
+    /// Threads systems and per-frame work are split across.
+    pool: ThreadPool,
+    /// Entities spawned by each sub-scene loaded with `load_sub_scene` or `stream_scene`.
+    sub_scenes: HashMap<String, Vec<EntityId>>,
+    /// Scene loaded with `watch_scene`, whose edits `reload_changes` applies.
+    watcher: Option<SceneWatcher>,
+    archive_key: LuminaKey,
+    backup_count: usize,
+    mesh_encoding: MeshEncoding,
 }
 
 impl Stage {
     pub fn new(name: &str) -> Self {
+        let mut registry = ComponentRegistry::new();
+        register_builtin_components(&mut registry);
+
         Self {
             name: name.to_string(),
             manager: Query::new(),
+            registry,
+            prefabs: PrefabLibrary::new(),
+            schedule: Schedule::new(),
+            resources: Resources::new(),
+            event_updates: Vec::new(),
+            pool: ThreadPool::default(),
+            sub_scenes: HashMap::new(),
+            watcher: None,
+            archive_key: LuminaKey::default(),
+            backup_count: 0,
+            mesh_encoding: MeshEncoding::default(),
+        }
+    }
+
+    /// Makes `T` part of saved scenes under `name`, see [`ComponentRegistry::register`].
+    pub fn register_component<T: Component>(
+        &mut self,
+        name: &str,
+        serialize: fn(&T, &mut SaveContext) -> Value,
+        deserialize: fn(&Value, &ComponentContext) -> Result<T, SceneError>,
+    ) {
+        self.registry.register(name, serialize, deserialize);
+    }
+
+    /// Runs `system` every frame during `stage`, see [`Schedule`].
+    pub fn add_system(&mut self, stage: SystemStage, system: System) {
+        self.schedule.add_system(stage, system);
+    }
+
+    /// Starts a new frame for events, then runs the systems of every stage
+    /// up to the render one.
+    pub fn update(&mut self) {
+        for update_events in self.event_updates.iter() {
+            update_events(&self.resources);
         }
+
+        for stage in [SystemStage::PreUpdate, SystemStage::Update, SystemStage::PostUpdate] {
+            self.schedule.run_stage(stage, &self.manager, &self.resources, &self.pool);
+        }
+    }
+
+    /// Stores `resource` in the stage, replacing the `T` it had if any, see [`Resources`].
+    pub fn insert_resource<T: Send + Sync + 'static>(&mut self, resource: T) {
+        self.resources.insert(resource);
+    }
+
+    pub fn remove_resource<T: Send + Sync + 'static>(&mut self) -> Option<T> {
+        self.resources.remove()
+    }
+
+    pub fn resource<T: Send + Sync + 'static>(&self) -> Option<Res<'_, T>> {
+        self.resources.get()
+    }
+
+    pub fn resource_mut<T: Send + Sync + 'static>(&self) -> Option<ResMut<'_, T>> {
+        self.resources.get_mut()
+    }
+
+    /// Lets systems send and read events of type `T`, see [`Events`].
+    pub fn add_event<T: Send + Sync + 'static>(&mut self) {
+        if self.resources.contains::<Events<T>>() {
+            return;
+        }
+
+        self.resources.insert(Events::<T>::new());
+        self.event_updates.push(update_events::<T>);
+    }
+
+    /// Sends `event` from outside of systems, it is read during the next
+    /// update. `T` has to be added with [`Stage::add_event`] first.
+    pub fn send_event<T: Send + Sync + 'static>(&self, event: T) {
+        match EventWriter::new(&self.resources) {
+            Some(mut events) => events.send(event),
+            None => eprintln!(
+                "WARNING: {} isn't an event of stage {}",
+                std::any::type_name::<T>(),
+                self.name
+            ),
+        }
+    }
+
+    /// Sets the key used by `save_scene` and `load_scene` for the stage's `.lumin` archive.
+    pub fn set_archive_key(&mut self, key: LuminaKey) {
+        self.archive_key = key;
+    }
+
+    /// Sets how many previous versions of the scene archive are kept as `.bak1`, `.bak2`...
+    pub fn set_backup_count(&mut self, backup_count: usize) {
+        self.backup_count = backup_count;
+    }
+
+    /// Sets how meshes are written in saved scenes, [`MeshEncoding::Quantized`] trades precision for size.
+    pub fn set_mesh_encoding(&mut self, encoding: MeshEncoding) {
+        self.mesh_encoding = encoding;
+    }
+
+    /// Adds a prefab to the stage, it is saved along with the scene.
+    pub fn add_prefab(&mut self, prefab: PrefabFile) {
+        self.prefabs.insert(prefab);
+    }
+
+    /// Turns `root` and `children`, each with its name in the prefab, into the
+    /// prefab `name` and adds it to the stage. The entities themselves are left as they are.
+    pub fn save_prefab(&mut self, name: &str, root: &GameObject, children: &[(&str, &GameObject)]) {
+        let mut context = SaveContext::default();
+        context.meshes.set_encoding(self.mesh_encoding);
+        // Textures get the same entry names as in the other prefabs
+        context.textures = self.prefabs.textures.clone();
+
+        let mut prefab = PrefabFile::new(name);
+        let entities = self.manager.entities();
+
+        for (child_name, game_object) in [("", root)].into_iter().chain(children.iter().copied()) {
+            let entity = match self.manager.persistent_id(game_object).and_then(|id| entities.get(id)) {
+                Some(entity) => entity,
+                None => {
+                    eprintln!(
+                        "WARNING: Entity {} doesn't exist and won't be part of the prefab {}",
+                        format_id(game_object.get_id()),
+                        name
+                    );
+                    continue;
+                }
+            };
+
+            let (record, unregistered) =
+                self.registry
+                    .save_entity(game_object.get_id(), &entity, &mut context);
+
+            for type_name in unregistered {
+                eprintln!(
+                    "WARNING: {} of entity {} isn't a registered component and won't be part of the prefab {}",
+                    type_name,
+                    format_id(game_object.get_id()),
+                    name
+                );
+            }
+
+            match child_name.is_empty() {
+                true => prefab.root.components = record.components,
+                false => prefab.root.children.push(PrefabNode {
+                    name: child_name.to_string(),
+                    components: record.components,
+                    children: Vec::new(),
+                }),
+            }
+        }
+
+        drop(entities);
+
+        if let Err(err) = prefab.validate() {
+            eprintln!("ERROR: Failed to save the prefab {}: {}", name, err);
+            return;
+        }
+
+        self.prefabs.meshes.extend(&context.meshes);
+        self.prefabs.textures = context.textures;
+        self.prefabs.insert(prefab);
+    }
+
+    /// The prefab `name` of the stage, ready for [`Query::instantiate`].
+    pub fn prefab(&self, device: Arc<Device>, render_pass: vk::RenderPass, name: &str) -> Option<Prefab> {
+        let file = self.prefabs.get(name)?.clone();
+
+        let light_name = self.registry.name_of::<Light>().unwrap_or_default();
+        let prefab_lights = file
+            .nodes()
+            .iter()
+            .filter(|(_, node)| node.components.contains_key(light_name))
+            .count();
+
+        let context = ComponentContext {
+            device,
+            render_pass,
+            light_count: (self.light_count() + prefab_lights) as u64,
+            meshes: self.prefabs.meshes.clone(),
+            textures: HashMap::new(),
+            packed_textures: self
+                .prefabs
+                .textures
+                .iter()
+                .map(|(_, file)| (file.file_name.clone(), Arc::new(file.clone())))
+                .collect(),
+        };
+
+        Some(Prefab::new(file, &self.registry, context))
     }
 
     pub fn render(
@@ -66,8 +284,6 @@ impl Stage {
         command_buffer: vk::CommandBuffer,
         camera: Camera,
     ) {
-        let num_cpus = num_cpus::get();
-
         let frame_index = renderer.read().unwrap().get_frame_index();
 
         /*let raw_light_3: LightInfo = LightInfo {
@@ -111,383 +327,617 @@ impl Stage {
             _padding2: 0,
         };
 
+        self.schedule
+            .run_stage(SystemStage::Render, &self.manager, &self.resources, &self.pool);
+
         let raw_lights = self.get_raw_lights();
 
-        for (_, entity) in self.manager.entities.write().unwrap().iter_mut() {
-            let model_matrix = entity
-                .write()
-                .unwrap()
-                .get_mut_component::<Transform>()
-                .unwrap()
-                .get_mat4();
-
-            let normal_matrix = entity
-                .write()
-                .unwrap()
-                .get_mut_component::<Transform>()
-                .unwrap()
-                .get_normal_matrix();
-
-            let is = entity.read().unwrap().has_component::<Model>();
-
-            if is {
-                if let Some(cube) = entity.write().unwrap().get_mut_component::<Model>() {
-                    let push = PushConstantData {
-                        model_matrix,
-                        normal_matrix,
-                    };
-
-                    cube.render(
-                        command_buffer,
-                        &device,
-                        frame_index as u32,
-                        push,
-                        camera.get_matrix(),
-                        raw_lights.clone(),
-                        camera.get_position().to_array(),
-                    );
-                }
+        for (_, (transform, cube)) in self.manager.iter::<(&Transform, &mut Model)>() {
+            let push = PushConstantData {
+                model_matrix: transform.get_mat4(),
+                normal_matrix: transform.get_normal_matrix(),
+            };
+
+            cube.render(
+                command_buffer,
+                &device,
+                frame_index as u32,
+                push,
+                camera.get_matrix(),
+                raw_lights.clone(),
+                camera.get_position().to_array(),
+            );
+        }
+    }
+
+    /// The scene document of the stage, with entities sorted by id so saving
+    /// the same stage twice gives the same document, along with what its
+    /// components save next to it.
+    pub fn build_scene(&self) -> (SceneFile, SaveContext) {
+        let mut scene = SceneFile::new(&self.name);
+        let mut context = SaveContext::default();
+        context.meshes.set_encoding(self.mesh_encoding);
+        // The prefabs' textures are packed too, under the entry names the prefabs refer to
+        context.textures = self.prefabs.textures.clone();
+
+        for (id, entity) in self.manager.entities().iter() {
+            let (mut record, unregistered) = self.registry.save_entity(id, &entity, &mut context);
+
+            for type_name in unregistered {
+                eprintln!(
+                    "WARNING: {} of entity {} isn't a registered component and won't be saved",
+                    type_name,
+                    format_id(id)
+                );
             }
+
+            if record.prefab.is_some() && !self.prefabs.to_overrides(&mut record) {
+                eprintln!(
+                    "WARNING: Entity {} comes from a prefab the stage doesn't have, it is saved in full",
+                    format_id(id)
+                );
+                record.prefab = None;
+            }
+
+            scene.entities.push(record);
         }
+
+        context.meshes.extend(&self.prefabs.meshes);
+
+        scene.entities.sort_by_key(|entity| entity.id);
+
+        (scene, context)
     }
 
+    /// Saves the scene to `./<name>.lumin`.
     pub fn save_scene(&self) {
-        let num_cpus = num_cpus::get();
+        self.save_scene_to(&format!("./{}.lumin", self.name));
+    }
 
-        let saver = Arc::new(RwLock::new(Saver::new()));
+    /// Saves the scene to `file_path`. The previous archive at `file_path` is
+    /// only replaced once the new one has been fully written.
+    ///
+    /// Only the textures used by the stage's entities are packed, once each
+    /// however many models share them.
+    pub fn save_scene_to(&self, file_path: &str) {
+        let mut saver = Saver::new();
+
+        let (scene, context) = self.build_scene();
+
+        saver.scene = scene;
+        context.meshes.write_to(&mut saver);
+        // Archives always have a textures directory, textures are looked up in it
+        saver.create_directory(TEXTURE_DIRECTORY);
+        context.textures.write_to(&mut saver, TEXTURE_DIRECTORY);
+        self.prefabs.write_to(&mut saver);
+        saver.set_key(self.archive_key.clone());
+        saver.set_backup_count(self.backup_count);
+
+        if let Err(err) = saver.save_data_to(file_path) {
+            eprintln!("ERROR: Failed to save the scene {}: {}", self.name, err);
+        }
+    }
 
-        let mut light_count: u32 = 0;
+    pub fn load_scene(
+        &mut self,
+        device: Arc<Device>,
+        render_pass: vk::RenderPass,
+        file_path: &str,
+    ) {
+        let decoded = match decode_scene(
+            file_path,
+            &self.archive_key,
+            self.registry.name_of::<Model>(),
+            &RwLock::new(LoadProgress::new()),
+        ) {
+            Ok(decoded) => decoded,
+            Err(err) => {
+                eprintln!("ERROR: Failed to load the scene {}: {}", file_path, err);
+                return;
+            }
+        };
 
-        saver.write().unwrap().modify_project_name(&self.name);
+        if let Err(err) = self.spawn_scene(device, render_pass, decoded) {
+            eprintln!("ERROR: Failed to load the scene {}: {}", file_path, err);
+        }
+    }
+
+    /// Loads the archive at `file_path` next to the entities already in the
+    /// stage, as the sub-scene `sub_scene` that [`Stage::unload_sub_scene`]
+    /// removes again. Entities whose id is already used get a new one.
+    pub fn load_sub_scene(
+        &mut self,
+        device: Arc<Device>,
+        render_pass: vk::RenderPass,
+        file_path: &str,
+        sub_scene: &str,
+    ) {
+        let mut decoded = match decode_scene(
+            file_path,
+            &self.archive_key,
+            self.registry.name_of::<Model>(),
+            &RwLock::new(LoadProgress::new()),
+        ) {
+            Ok(decoded) => decoded,
+            Err(err) => {
+                eprintln!("ERROR: Failed to load the scene {}: {}", file_path, err);
+                return;
+            }
+        };
 
-        for (id, entity) in self.manager.entities.read().unwrap().iter() {
-            let mut saver_lock = saver.write().unwrap();
-            saver_lock.json["game_objects"]
-                .as_array_mut()
-                .unwrap()
-                .push(serde_json::json!(*id));
+        decoded.remap_ids(0, |id| self.manager.contains(id));
 
-            let is_light = entity.read().unwrap().has_component::<Light>();
-            let is_model: bool = entity.read().unwrap().has_component::<Model>();
-            let is_transform: bool = entity.read().unwrap().has_component::<Transform>();
+        match self.spawn_scene(device, render_pass, decoded) {
+            Ok(ids) => self.sub_scenes.entry(sub_scene.to_string()).or_default().extend(ids),
+            Err(err) => eprintln!("ERROR: Failed to load the scene {}: {}", file_path, err),
+        }
+    }
 
-            if is_light {
-                if let Some(light) = entity.read().unwrap().get_component::<Light>() {
-                    saver_lock.json["lights"]
-                        .as_array_mut()
-                        .unwrap()
-                        .push(light.convert_to_json(*id));
-                }
+    /// Like [`Stage::load_sub_scene`], but the archive is read and decoded on
+    /// a background thread. The returned handle reports the progress, and
+    /// has to be given to [`Stage::poll_scene_load`] until it is finished.
+    pub fn stream_scene(&self, file_path: &str, sub_scene: &str) -> SceneLoad {
+        SceneLoad::start(
+            file_path,
+            sub_scene,
+            self.archive_key.clone(),
+            self.registry.name_of::<Model>().map(str::to_string),
+        )
+    }
 
-                light_count += 1;
+    /// Moves `load` forward: once its archive is decoded, builds and spawns up
+    /// to `max_entities` of its entities, so large scenes can be spread over
+    /// frames. Returns true once the load is finished, or failed.
+    ///
+    /// Entities whose id is already used when their batch comes get a new one.
+    /// Entities spawned before a failure stay in the stage as part of the sub-scene.
+    pub fn poll_scene_load(
+        &mut self,
+        device: Arc<Device>,
+        render_pass: vk::RenderPass,
+        load: &mut SceneLoad,
+        max_entities: usize,
+    ) -> bool {
+        if load.is_finished() {
+            return true;
+        }
+
+        if load.decoded.is_none() {
+            if !load.is_decoded() {
+                return false;
             }
 
-            if is_model {
-                if let Some(model) = entity.read().unwrap().get_component::<Model>() {
-                    saver_lock.json["models"]
-                        .as_array_mut()
-                        .unwrap()
-                        .push(model.convert_to_json(*id));
+            match load.thread.take().unwrap().join() {
+                Ok(Ok(mut decoded)) => {
+                    load.context = Some(self.scene_context(device, render_pass, &mut decoded));
+                    load.decoded = Some(decoded);
+                }
+                Ok(Err(err)) => {
+                    load.fail(&err);
+                    return true;
+                }
+                Err(_) => {
+                    load.fail(&"the loading thread panicked");
+                    return true;
                 }
             }
+        }
+
+        let decoded = load.decoded.as_mut().unwrap();
+        let context = load.context.as_ref().unwrap();
 
-            if is_transform {
-                if let Some(transform) = entity.read().unwrap().get_component::<Transform>() {
-                    saver_lock.json["transforms"]
-                        .as_array_mut()
-                        .unwrap()
-                        .push(transform.convert_to_json(*id));
+        // Entities may have been spawned under ids of the scene since the last batch
+        if decoded.remap_ids(load.spawned, |id| self.manager.contains(id)) {
+            if let Some(mut instances) = self.manager.column_mut::<PrefabInstance>() {
+                for record in decoded.records[..load.spawned].iter() {
+                    if let (Some(link), Some(instance)) = (&record.prefab, instances.get_mut(record.id)) {
+                        instance.instance = link.instance;
+                    }
                 }
             }
         }
 
-        saver.write().unwrap().json["light_count"] = serde_json::json!(light_count);
+        let total = decoded.records.len();
+        let end = load.spawned.saturating_add(max_entities).min(total);
+        let mut failure = None;
+
+        for index in load.spawned..end {
+            let record = &decoded.records[index];
+
+            match self.registry.load_entity(record, index, context) {
+                Ok((entity, unknown)) => {
+                    for name in unknown {
+                        eprintln!(
+                            "WARNING: Unknown component {} on entity {}, it is kept as is",
+                            name, format_id(record.id)
+                        );
+                    }
 
-        let paths = Arc::new(unsafe { PATHS.clone() });
+                    self.manager.spawn_entity_with_id(record.id, entity).unwrap();
+                    self.sub_scenes
+                        .entry(load.sub_scene.clone())
+                        .or_default()
+                        .push(record.id);
+                }
+                Err(err) => {
+                    failure = Some(err);
+                    break;
+                }
+            }
+        }
 
-        saver.write().unwrap().create_directory("textures");
+        if let Some(err) = failure {
+            load.fail(&err);
+            return true;
+        }
 
-        let handles: Vec<_> = (0..num_cpus)
-            .map(|i| {
-                let paths_clone = paths.clone();
+        load.spawned = end;
 
-                let saver_clone = saver.clone();
+        if end < total {
+            LoadProgress::set(&load.progress, LoadStep::Spawning, end, total);
+            return false;
+        }
 
-                thread::spawn(move || {
-                    let size = paths_clone.len();
-                    let start = i * size / num_cpus;
-                    let end = if i == num_cpus - 1 {
-                        size
-                    } else {
-                        (i + 1) * size / num_cpus
-                    };
+        self.prefabs.merge(std::mem::take(&mut decoded.prefabs));
+        load.decoded = None;
+        load.context = None;
+        LoadProgress::set(&load.progress, LoadStep::Finished, total, total);
 
-                    for (raw_path, new_path) in paths_clone.iter().skip(start).take(end - start) {
-                        let mut file = File::open(raw_path).unwrap();
-                        let mut contents = Vec::new();
-                        file.read_to_end(&mut contents).unwrap();
+        true
+    }
 
-                        let file =
-                            LuminaFile::new(LuminaFileType::Jpg, new_path.to_string(), contents);
-                        saver_clone
-                            .write()
-                            .unwrap()
-                            .insert_file_into_directory("textures", file)
-                    }
-                })
-            })
-            .collect();
+    /// Kills every entity loaded by the sub-scene `sub_scene`.
+    pub fn unload_sub_scene(&mut self, sub_scene: &str) {
+        let ids = match self.sub_scenes.remove(sub_scene) {
+            Some(ids) => ids,
+            None => {
+                eprintln!("WARNING: No sub-scene {} to unload", sub_scene);
+                return;
+            }
+        };
 
-        for handle in handles {
-            handle.join().unwrap();
+        for id in ids {
+            self.manager.remove_entity(id);
         }
+    }
 
+    /// Ids of the entities loaded by the sub-scene `sub_scene`.
+    pub fn sub_scene_entities(&self, sub_scene: &str) -> &[EntityId] {
+        self.sub_scenes.get(sub_scene).map(|ids| ids.as_slice()).unwrap_or(&[])
+    }
 
-        saver.write().unwrap().save_data();
+    /// Loads the scene at `file_path`, a `.lumin` archive or a text scene, and
+    /// watches it along with the files its components use (textures, models,
+    /// the assets of a text scene) so [`Stage::reload_changes`] can apply their edits.
+    pub fn watch_scene(&mut self, device: Arc<Device>, render_pass: vk::RenderPass, file_path: &str) {
+        let decoded = match read_scene(file_path, &self.archive_key, self.registry.name_of::<Model>()) {
+            Ok(decoded) => decoded,
+            Err(err) => {
+                eprintln!("ERROR: Failed to load the scene {}: {}", file_path, err);
+                return;
+            }
+        };
+
+        let watcher = SceneWatcher::new(file_path, &decoded);
+
+        match self.spawn_scene(device, render_pass, decoded) {
+            Ok(_) => self.watcher = Some(watcher),
+            Err(err) => eprintln!("ERROR: Failed to load the scene {}: {}", file_path, err),
+        }
     }
 
-    pub fn load_scene(
+    /// Applies the edits made to the watched scene and its files since they
+    /// were last loaded: components that changed are rebuilt on their
+    /// existing entity, new entities are spawned and removed ones killed.
+    /// Entities and components that weren't touched keep their runtime state.
+    ///
+    /// Only checks modification times when nothing changed, so it can be
+    /// called every frame. Returns whether the scene was reloaded.
+    pub fn reload_changes(&mut self, device: Arc<Device>, render_pass: vk::RenderPass) -> bool {
+        let mut watcher = match self.watcher.take() {
+            Some(watcher) => watcher,
+            None => return false,
+        };
+
+        let reloaded = match watcher.poll() {
+            Some(changed_keys) => {
+                self.reload_scene(device, render_pass, &mut watcher, &changed_keys);
+                true
+            }
+            None => false,
+        };
+
+        self.watcher = Some(watcher);
+
+        reloaded
+    }
+
+    fn reload_scene(
         &mut self,
         device: Arc<Device>,
         render_pass: vk::RenderPass,
-        file_path: &str,
+        watcher: &mut SceneWatcher,
+        changed_keys: &BTreeSet<String>,
     ) {
-        let num_cpus = num_cpus::get();
-
-        let mut loader = Arc::new(RwLock::new(Loader::new()));
+        let mut decoded = match watcher.read(&self.archive_key, self.registry.name_of::<Model>()) {
+            Ok(decoded) => decoded,
+            Err(err) => {
+                eprintln!("ERROR: Failed to reload the scene {}: {}", watcher.file_path(), err);
+
+                // The file may be caught mid-write, it is read again on the next poll
+                watcher.retry();
+                return;
+            }
+        };
 
-        loader.write().unwrap().load_file(file_path.to_string());
+        let light_name = self.registry.name_of::<Light>().unwrap_or_default();
+        let model_name = self.registry.name_of::<Model>().unwrap_or_default();
 
-        let file_content = loader
-            .read()
-            .unwrap()
-            .directories
-            .get("gameData")
-            .unwrap()
-            .files
+        let old_lights = watcher
+            .records
+            .values()
+            .filter(|record| record.components.contains_key(light_name))
+            .count();
+        let new_lights = decoded
+            .records
             .iter()
-            .find(|file| "scene.json" == file.file_name)
-            .unwrap()
-            .file_content
-            .clone();
-
-        let json_string = String::from_utf8(file_content).unwrap();
-        let mut json: Value = serde_json::from_str(&json_string).unwrap();
-        //panic!("{:?}",serde_json::to_string_pretty(&json));
+            .filter(|record| record.components.contains_key(light_name))
+            .count();
 
-        let light_count = json["light_count"].as_u64().unwrap_or(0) as u32;
+        let mut context = self.scene_context(device, render_pass, &mut decoded);
+        context.light_count = context.light_count.saturating_sub(old_lights as u64);
 
-        let mut game_objects = HashMap::new();
+        let mut records = BTreeMap::new();
 
-        for game_object_id in json["game_objects"].as_array().unwrap().iter() {
-            let game_object = self
-                .manager
-                .spawn_with_id(game_object_id.as_u64().unwrap() as u32);
+        for (index, record) in decoded.records.iter().enumerate() {
+            let result = match (watcher.records.get(&record.id), self.manager.contains(record.id)) {
+                (Some(old), true) => {
+                    let mut changed = changed_components(old, record, changed_keys);
 
-            game_objects.insert(game_object_id.as_u64().unwrap() as u32, game_object);
-        }
+                    // Models are set up for a number of lights
+                    if old_lights != new_lights && record.components.contains_key(model_name) {
+                        changed.insert(model_name.to_string());
+                    }
 
-        for transform_json in json["transforms"].as_array().unwrap().iter() {
-            let transform = Transform {
-                translation: glam::vec3(
-                    transform_json["transform"].as_array().unwrap()[0]
-                        .as_f64()
-                        .unwrap() as f32,
-                    transform_json["transform"].as_array().unwrap()[1]
-                        .as_f64()
-                        .unwrap() as f32,
-                    transform_json["transform"].as_array().unwrap()[2]
-                        .as_f64()
-                        .unwrap() as f32,
-                ),
-                rotation: glam::vec3(
-                    transform_json["rotation"].as_array().unwrap()[0]
-                        .as_f64()
-                        .unwrap() as f32,
-                    transform_json["rotation"].as_array().unwrap()[1]
-                        .as_f64()
-                        .unwrap() as f32,
-                    transform_json["rotation"].as_array().unwrap()[2]
-                        .as_f64()
-                        .unwrap() as f32,
-                ),
-                scale: glam::vec3(
-                    transform_json["scale"].as_array().unwrap()[0]
-                        .as_f64()
-                        .unwrap() as f32,
-                    transform_json["scale"].as_array().unwrap()[1]
-                        .as_f64()
-                        .unwrap() as f32,
-                    transform_json["scale"].as_array().unwrap()[2]
-                        .as_f64()
-                        .unwrap() as f32,
-                ),
+                    match changed.is_empty() && old.prefab == record.prefab {
+                        true => Ok(()),
+                        false => {
+                            let registry = &self.registry;
+
+                            self.manager
+                                .update_entity(record.id, |entity| {
+                                    registry.update_entity(entity, record, index, &changed, &context)
+                                })
+                                .unwrap()
+                        }
+                    }
+                }
+                // Killed while the stage ran, it stays dead
+                (Some(_), false) => Ok(()),
+                // Spawned by something else since the scene was loaded
+                (None, true) => Err(id_taken(record.id, index)),
+                (None, false) => self
+                    .registry
+                    .load_entity(record, index, &context)
+                    .map(|(entity, _)| {
+                        self.manager.spawn_entity_with_id(record.id, entity).unwrap();
+                    }),
             };
 
-            let game_object = game_objects
-                .get(&(transform_json["id"].as_u64().unwrap() as u32))
-                .unwrap();
-
-            self.manager.push(game_object, transform);
-        }
-
-        for model_json in json["models"].as_array().unwrap().iter() {
-            let mut model = Model::new(Arc::clone(&device));
-
-            let mut meshes: Vec<Mesh> = Vec::new();
-            let mut materials: Vec<lumina_pbr::material::Material> = Vec::new();
-
-            for mesh in model_json["meshes"].as_array().unwrap().iter() {
-                let mut vertices = Vec::new();
-                let mut indices = Vec::new();
-
-                for vertex in mesh["vertices"].as_array().unwrap().iter() {
-                    vertices.push(Vertex3D {
-                        position: glam::vec3(
-                            vertex["position"].as_array().unwrap()[0].as_f64().unwrap() as f32,
-                            vertex["position"].as_array().unwrap()[1].as_f64().unwrap() as f32,
-                            vertex["position"].as_array().unwrap()[2].as_f64().unwrap() as f32,
-                        ),
-                        normal: glam::vec3(
-                            vertex["normal"].as_array().unwrap()[0].as_f64().unwrap() as f32,
-                            vertex["normal"].as_array().unwrap()[1].as_f64().unwrap() as f32,
-                            vertex["normal"].as_array().unwrap()[2].as_f64().unwrap() as f32,
-                        ),
-                        uv: glam::vec2(
-                            vertex["uv"].as_array().unwrap()[0].as_f64().unwrap() as f32,
-                            vertex["uv"].as_array().unwrap()[1].as_f64().unwrap() as f32,
-                        ),
-                    });
+            match result {
+                Ok(()) => {
+                    records.insert(record.id, record.clone());
                 }
+                Err(err) => {
+                    eprintln!(
+                        "ERROR: Failed to reload entity {} of the scene {}: {}",
+                        format_id(record.id),
+                        watcher.file_path(),
+                        err
+                    );
 
-                for indice in mesh["indices"].as_array().unwrap().iter() {
-                    indices.push(indice.as_u64().unwrap() as u32)
+                    // Tried again on the next edit
+                    if let Some(old) = watcher.records.get(&record.id) {
+                        records.insert(record.id, old.clone());
+                    }
                 }
-
-                meshes.push(Mesh::new(Arc::clone(&device), vertices, indices));
             }
+        }
 
-            for material_json in model_json["materials"].as_array().unwrap().iter() {
-                let mut material = lumina_pbr::material::Material::new(
-                    glam::vec3(
-                        material_json["ambient"].as_array().unwrap()[0]
-                            .as_f64()
-                            .unwrap() as f32,
-                        material_json["ambient"].as_array().unwrap()[1]
-                            .as_f64()
-                            .unwrap() as f32,
-                        material_json["ambient"].as_array().unwrap()[2]
-                            .as_f64()
-                            .unwrap() as f32,
-                    ),
-                    glam::vec3(
-                        material_json["diffuse"].as_array().unwrap()[0]
-                            .as_f64()
-                            .unwrap() as f32,
-                        material_json["diffuse"].as_array().unwrap()[1]
-                            .as_f64()
-                            .unwrap() as f32,
-                        material_json["diffuse"].as_array().unwrap()[2]
-                            .as_f64()
-                            .unwrap() as f32,
-                    ),
-                    glam::vec3(
-                        material_json["metallic"].as_array().unwrap()[0]
-                            .as_f64()
-                            .unwrap() as f32,
-                        material_json["metallic"].as_array().unwrap()[1]
-                            .as_f64()
-                            .unwrap() as f32,
-                        material_json["metallic"].as_array().unwrap()[2]
-                            .as_f64()
-                            .unwrap() as f32,
-                    ),
-                    1.0,
-                );
-
-                material.ambient_texture =
-                    Texture::new_raw(material_json["ambient_texture"].as_str().unwrap());
-                material.metallic_texture =
-                    Texture::new_raw(material_json["metallic_texture"].as_str().unwrap());
-
-                materials.push(material);
-            }
+        let ids: BTreeSet<EntityId> = decoded.records.iter().map(|record| record.id).collect();
 
-            model.meshes = meshes;
-            model.materials = materials;
-            
-            model.init_model(render_pass, light_count as u64);
+        for id in watcher.records.keys().filter(|id| !ids.contains(id)) {
+            self.manager.remove_entity(*id);
+        }
 
-            let game_object = game_objects
-                .get(&(model_json["id"].as_u64().unwrap() as u32))
-                .unwrap();
+        watcher.set_scene(&decoded, records);
+        self.prefabs.merge(decoded.prefabs);
+    }
 
-            self.manager.push(game_object, model);
+    /// Writes the scene as text to `file_path` (see [`SceneFile::to_text`]),
+    /// listing the textures it uses where they are on disk instead of embedding
+    /// them. Meshes and prefabs are written to `<file stem>_assets`, along with
+    /// the textures loaded from an archive, which have no file on disk.
+    pub fn export_scene_text(&self, file_path: &str) {
+        let (mut scene, context) = self.build_scene();
+
+        let asset_dir = Path::new(file_path).with_file_name(format!(
+            "{}_assets",
+            Path::new(file_path).file_stem().unwrap_or_default().to_string_lossy()
+        ));
+
+        for (id, bytes) in context.meshes.iter() {
+            if let Err(err) = write_asset(
+                &mut scene,
+                file_path,
+                &asset_dir.to_string_lossy(),
+                &mesh_entry(id),
+                LuminaFileType::Mesh,
+                bytes,
+            ) {
+                eprintln!("ERROR: Failed to export the scene {}: {}", self.name, err);
+                return;
+            }
         }
 
-        for light_json in json["lights"].as_array().unwrap().iter() {
-            let mut light = Light::new();
+        for prefab in self.prefabs.iter() {
+            if let Err(err) = write_asset(
+                &mut scene,
+                file_path,
+                &asset_dir.to_string_lossy(),
+                &prefab_entry(&prefab.name),
+                LuminaFileType::Json,
+                &prefab.to_bytes(),
+            ) {
+                eprintln!("ERROR: Failed to export the scene {}: {}", self.name, err);
+                return;
+            }
+        }
 
-            light.change_light_type(light_json["light_type"].as_u64().unwrap() as u32);
-            light.change_color(glam::vec3(
-                light_json["color"].as_array().unwrap()[0].as_f64().unwrap() as f32,
-                light_json["color"].as_array().unwrap()[1].as_f64().unwrap() as f32,
-                light_json["color"].as_array().unwrap()[2].as_f64().unwrap() as f32,
-            ));
-            light.change_intensity(light_json["intensity"].as_f64().unwrap() as f32);
-            light.change_range(light_json["range"].as_f64().unwrap() as f32);
-            light.change_spot_size(light_json["spot_size"].as_f64().unwrap() as f32);
+        for (raw_path, texture) in context.textures.iter() {
+            let entry_path = format!("{}/{}", TEXTURE_DIRECTORY, texture.file_name);
+
+            if !Path::new(raw_path).is_file() {
+                if let Err(err) = write_asset(
+                    &mut scene,
+                    file_path,
+                    &asset_dir.to_string_lossy(),
+                    &entry_path,
+                    texture.file_type,
+                    &texture.file_content,
+                ) {
+                    eprintln!("ERROR: Failed to export the scene {}: {}", self.name, err);
+                    return;
+                }
 
-            let game_object = game_objects
-                .get(&(light_json["id"].as_u64().unwrap() as u32))
-                .unwrap();
+                continue;
+            }
 
-            self.manager.push(game_object, light);
+            scene.assets.insert(
+                entry_path,
+                AssetRecord {
+                    path: asset_path(raw_path, file_path),
+                    file_type: texture.file_type,
+                },
+            );
         }
 
-        println!("{:?}", self.manager.entities);
+        if let Err(err) = fs::write(file_path, scene.to_text()) {
+            eprintln!("ERROR: Failed to export the scene {}: {}", self.name, err);
+        }
     }
 
+    /// Spawns the entities of a text scene written by [`Stage::export_scene_text`] or `lumin export`.
+    pub fn import_scene_text(
+        &mut self,
+        device: Arc<Device>,
+        render_pass: vk::RenderPass,
+        file_path: &str,
+    ) {
+        let decoded = match decode_text_scene(file_path) {
+            Ok(decoded) => decoded,
+            Err(err) => {
+                eprintln!("ERROR: Failed to import the scene {}: {}", file_path, err);
+                return;
+            }
+        };
 
-    pub fn get_raw_lights(&mut self) -> Vec<lumina_core::RawLight> {
-        let num_cpus = num_cpus::get();
+        if let Err(err) = self.spawn_scene(device, render_pass, decoded) {
+            eprintln!("ERROR: Failed to import the scene {}: {}", file_path, err);
+        }
+    }
 
-        let mut raw_lights = Vec::new();
+    /// Builds every entity of `decoded` and spawns them, then adds its prefabs
+    /// to the stage. Nothing is spawned until every entity is built, so a
+    /// broken scene, or one with ids the stage already uses, leaves the stage
+    /// as it was. Returns the ids of the spawned entities.
+    fn spawn_scene(
+        &mut self,
+        device: Arc<Device>,
+        render_pass: vk::RenderPass,
+        mut decoded: DecodedScene,
+    ) -> Result<Vec<EntityId>, SceneError> {
+        let context = self.scene_context(device, render_pass, &mut decoded);
 
+        let mut entities = Vec::with_capacity(decoded.records.len());
 
-        let handles: Vec<_> = (0..num_cpus)
-        .map(|i| {
-            let manager = self.manager.entities.clone();
+        for (index, record) in decoded.records.iter().enumerate() {
+            if self.manager.contains(record.id) {
+                return Err(id_taken(record.id, index));
+            }
 
-            thread::spawn(move || {
-                let size = manager.read().unwrap().len();
-                let start = i * size / num_cpus;
-                let end = if i == num_cpus - 1 {
-                    size
-                } else {
-                    (i + 1) * size / num_cpus
-                };
+            match self.registry.load_entity(record, index, &context) {
+                Ok((entity, unknown)) => {
+                    for name in unknown {
+                        eprintln!(
+                            "WARNING: Unknown component {} on entity {}, it is kept as is",
+                            name, format_id(record.id)
+                        );
+                    }
 
-                let mut raw_lights = Vec::new();
+                    entities.push((record.id, entity));
+                }
+                Err(err) => return Err(err),
+            }
+        }
 
+        let ids = entities.iter().map(|(id, _)| *id).collect();
 
-                for (id,entity) in manager.read().unwrap().iter().skip(start).take(end - start) {
-                    let transform = entity.read().unwrap().get_component::<Transform>().unwrap().clone();
+        for (id, entity) in entities {
+            self.manager.spawn_entity_with_id(id, entity).unwrap();
+        }
 
-                    if let Some(light) = entity.read().unwrap().get_component::<Light>() {
-                        raw_lights.push(light.create_raw_light(id, &transform))
-                    }
-                }
+        self.prefabs.merge(decoded.prefabs);
 
-                raw_lights
-            })
-        }).collect();
+        Ok(ids)
+    }
+
+    /// What the components of `decoded` need to be built, models being set up
+    /// for the lights already in the stage plus the ones of `decoded`. The
+    /// meshes and textures of `decoded` are moved into the context.
+    fn scene_context(
+        &self,
+        device: Arc<Device>,
+        render_pass: vk::RenderPass,
+        decoded: &mut DecodedScene,
+    ) -> ComponentContext {
+        let light_name = self.registry.name_of::<Light>().unwrap_or_default();
 
-        for handle in handles {
-            raw_lights.extend(handle.join().unwrap())
+        let scene_lights = decoded
+            .records
+            .iter()
+            .filter(|entity| entity.components.contains_key(light_name))
+            .count();
+
+        ComponentContext {
+            device,
+            render_pass,
+            light_count: (self.light_count() + scene_lights) as u64,
+            meshes: std::mem::take(&mut decoded.meshes),
+            textures: std::mem::take(&mut decoded.textures),
+            packed_textures: std::mem::take(&mut decoded.packed_textures),
         }
+    }
+
+    /// Number of entities of the stage with a light.
+    fn light_count(&self) -> usize {
+        self.manager.column::<Light>().map_or(0, |lights| lights.len())
+    }
+
+    pub fn get_raw_lights(&mut self) -> Vec<lumina_core::RawLight> {
+        self.manager
+            .iter::<(&Light, &Transform)>()
+            .map(|(id, (light, transform))| light.create_raw_light(&id, transform))
+            .collect()
+    }
+}
 
-        raw_lights
+/// The entity `index` of a scene has an id another entity of the stage already has.
+fn id_taken(id: EntityId, index: usize) -> SceneError {
+    SceneError::InvalidField {
+        entity: Some(id),
+        field: format!("entities[{}].id", index),
+        message: "another entity of the stage has this id".to_string(),
     }
 }
 
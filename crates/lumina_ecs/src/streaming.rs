@@ -0,0 +1,319 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    fs, io,
+    sync::{Arc, RwLock},
+    thread::{self, JoinHandle},
+};
+
+use image::DynamicImage;
+use lumina_core::texture::Texture;
+use lumina_files::{
+    asset::texture_name_of_entry,
+    error::LuminaFileError,
+    file::LuminaFile,
+    format::LEGACY_VERSION,
+    id::EntityId,
+    key::LuminaKey,
+    loader::{ArchiveEntries, Loader},
+    mapped::MappedLoader,
+    mesh::{mesh_id_of_entry, MeshStore},
+    prefab::{prefab_name_of_entry, PrefabFile, PrefabLibrary, PrefabLink},
+    scene::{read_record, AssetRecord, EntityRecord, ModelRecord, SceneError, SceneFile},
+    text::{read_asset, SCENE_ENTRY},
+};
+use lumina_object::{game_object::new_persistent_id, registry::ComponentContext};
+use lumina_path::load_image;
+
+/// What a scene load is doing, see [`LoadProgress`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoadStep {
+    /// Reading, decrypting and decompressing the archive.
+    Reading,
+    /// Decoding the meshes and textures of the scene.
+    Decoding,
+    /// Building the entities and spawning them into the stage.
+    Spawning,
+    Finished,
+    Failed,
+}
+
+/// How far a scene load is, `done` out of `total` items of its current step.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LoadProgress {
+    pub step: LoadStep,
+    pub done: usize,
+    pub total: usize,
+}
+
+impl LoadProgress {
+    pub fn new() -> Self {
+        Self {
+            step: LoadStep::Reading,
+            done: 0,
+            total: 1,
+        }
+    }
+
+    pub(crate) fn set(progress: &RwLock<LoadProgress>, step: LoadStep, done: usize, total: usize) {
+        *progress.write().unwrap() = LoadProgress { step, done, total };
+    }
+}
+
+/// A scene whose entities are ready to be built: prefab instances are
+/// resolved and meshes and textures decoded.
+pub(crate) struct DecodedScene {
+    pub records: Vec<EntityRecord>,
+    pub meshes: MeshStore,
+    pub prefabs: PrefabLibrary,
+    /// Files a text scene lists, see [`SceneFile::assets`].
+    pub assets: BTreeMap<String, AssetRecord>,
+    /// Textures of the models by their path, see [`ComponentContext::textures`].
+    pub textures: HashMap<String, DynamicImage>,
+    /// Files of the scene's `textures` directory by entry name.
+    pub packed_textures: HashMap<String, Arc<LuminaFile>>,
+}
+
+impl DecodedScene {
+    pub fn new(
+        scene: &SceneFile,
+        meshes: MeshStore,
+        mut prefabs: PrefabLibrary,
+        packed_textures: HashMap<String, Arc<LuminaFile>>,
+    ) -> Result<Self, SceneError> {
+        prefabs.keep_textures_from(&packed_textures);
+
+        let records = scene
+            .entities
+            .iter()
+            .map(|record| prefabs.resolve(record))
+            .collect::<Result<Vec<_>, SceneError>>()?;
+
+        let mut meshes = meshes;
+        meshes.extend(&prefabs.meshes);
+
+        Ok(Self {
+            records,
+            meshes,
+            prefabs,
+            assets: scene.assets.clone(),
+            textures: HashMap::new(),
+            packed_textures,
+        })
+    }
+
+    /// Gives a new id to every entity from `records[from]` on whose id is
+    /// `taken`, updating the prefab instances pointing to it. Returns whether
+    /// any id changed.
+    pub fn remap_ids(&mut self, from: usize, taken: impl Fn(EntityId) -> bool) -> bool {
+        let mut ids = HashMap::new();
+        let mut used: HashSet<EntityId> = self.records.iter().map(|record| record.id).collect();
+
+        for record in self.records.iter_mut().skip(from) {
+            if taken(record.id) {
+                let id = new_persistent_id(|id| taken(id) || used.contains(&id));
+                used.insert(id);
+                ids.insert(record.id, id);
+                record.id = id;
+            }
+        }
+
+        if ids.is_empty() {
+            return false;
+        }
+
+        for record in self.records.iter_mut() {
+            if let Some(PrefabLink { instance, .. }) = record.prefab.as_mut() {
+                if let Some(id) = ids.get(instance) {
+                    *instance = *id;
+                }
+            }
+        }
+
+        true
+    }
+}
+
+/// Reads and decodes the scene archive at `file_path`. Everything done here
+/// is independent of the stage and the device, so it can run on any thread.
+pub(crate) fn decode_scene(
+    file_path: &str,
+    key: &LuminaKey,
+    model_name: Option<&str>,
+    progress: &RwLock<LoadProgress>,
+) -> Result<DecodedScene, LuminaFileError> {
+    LoadProgress::set(progress, LoadStep::Reading, 0, 1);
+
+    let loader: Box<dyn ArchiveEntries> = match MappedLoader::open(file_path, key.clone()) {
+        Ok(loader) => Box::new(loader),
+        // Legacy archives have no table of contents to map entries with
+        Err(LuminaFileError::UnsupportedVersion(LEGACY_VERSION)) => {
+            let mut loader = Loader::new();
+            loader.set_key(key.clone());
+            loader.load_file(file_path.to_string())?;
+
+            Box::new(loader)
+        }
+        Err(err) => return Err(err),
+    };
+
+    let scene = SceneFile::from_slice(&loader.get_entry(SCENE_ENTRY)?.file_content)?;
+    let meshes = MeshStore::from_loader(loader.as_ref())?;
+    let prefabs = PrefabLibrary::from_loader(loader.as_ref(), &meshes)?;
+
+    let mut packed_textures = HashMap::new();
+
+    for entry_path in loader.entry_paths() {
+        if let Some(name) = texture_name_of_entry(&entry_path) {
+            packed_textures.insert(name.to_string(), load_image(name, loader.as_ref())?);
+        }
+    }
+
+    let mut decoded = DecodedScene::new(&scene, meshes, prefabs, packed_textures)?;
+
+    let mesh_ids: Vec<String> = decoded.meshes.iter().map(|(id, _)| id.to_string()).collect();
+    let mut textures = BTreeSet::new();
+
+    // Broken models are reported when their entity is built
+    for record in decoded.records.iter() {
+        let model = match model_name.and_then(|name| record.components.get(name)) {
+            Some(model) => model,
+            None => continue,
+        };
+
+        if let Ok(model) = read_record::<ModelRecord>(model) {
+            for material in model.materials.iter() {
+                for texture in [&material.ambient_texture, &material.metallic_texture, &material.normal_texture] {
+                    if !texture.is_empty() {
+                        textures.insert(texture.clone());
+                    }
+                }
+            }
+        }
+    }
+
+    let total = mesh_ids.len() + textures.len();
+    LoadProgress::set(progress, LoadStep::Decoding, 0, total);
+
+    for (index, id) in mesh_ids.iter().enumerate() {
+        decoded.meshes.decode(id)?;
+        LoadProgress::set(progress, LoadStep::Decoding, index + 1, total);
+    }
+
+    for (index, texture) in textures.into_iter().enumerate() {
+        // Scenes saved before textures were referred to by entry name point to the files on disk
+        let image = match decoded.packed_textures.get(&texture) {
+            Some(file) => Texture::decode_packed(file),
+            None => Texture::decode(&texture),
+        };
+
+        match image {
+            Ok(image) => {
+                decoded.textures.insert(texture, image);
+            }
+            Err(err) => eprintln!("WARNING: Failed to decode the texture {}: {}", texture, err),
+        }
+
+        LoadProgress::set(progress, LoadStep::Decoding, mesh_ids.len() + index + 1, total);
+    }
+
+    Ok(decoded)
+}
+
+/// Reads the text scene at `file_path` along with the meshes and prefabs it lists.
+pub(crate) fn decode_text_scene(file_path: &str) -> Result<DecodedScene, LuminaFileError> {
+    let scene = SceneFile::from_slice(&fs::read(file_path)?)?;
+
+    let mut meshes = MeshStore::new();
+    let mut prefabs = PrefabLibrary::new();
+    let mut packed_textures = HashMap::new();
+
+    for (entry_path, asset) in scene.assets.iter() {
+        if let Some(id) = mesh_id_of_entry(entry_path) {
+            meshes.insert_encoded(id, read_listed_asset(file_path, asset)?);
+        } else if prefab_name_of_entry(entry_path).is_some() {
+            prefabs.insert(PrefabFile::from_slice(&read_listed_asset(file_path, asset)?)?);
+        } else if let Some(name) = texture_name_of_entry(entry_path) {
+            let file = LuminaFile::new(asset.file_type, name.to_string(), read_listed_asset(file_path, asset)?);
+            packed_textures.insert(name.to_string(), Arc::new(file));
+        }
+    }
+
+    prefabs.keep_meshes_from(&meshes);
+
+    Ok(DecodedScene::new(&scene, meshes, prefabs, packed_textures)?)
+}
+
+/// [`read_asset`], with the asset's path in the error.
+fn read_listed_asset(file_path: &str, asset: &AssetRecord) -> Result<Vec<u8>, LuminaFileError> {
+    read_asset(file_path, asset).map_err(|err| match err {
+        LuminaFileError::Io(err) => {
+            LuminaFileError::Io(io::Error::new(err.kind(), format!("{}: {}", asset.path, err)))
+        }
+        err => err,
+    })
+}
+
+/// Handle on a scene loaded into a stage in the background, see
+/// [`crate::stage::Stage::stream_scene`]. Reading and decoding happen on
+/// their own thread, entities are then spawned a chunk at a time by
+/// [`crate::stage::Stage::poll_scene_load`].
+pub struct SceneLoad {
+    pub(crate) sub_scene: String,
+    pub(crate) file_path: String,
+    pub(crate) progress: Arc<RwLock<LoadProgress>>,
+    pub(crate) thread: Option<JoinHandle<Result<DecodedScene, LuminaFileError>>>,
+    pub(crate) decoded: Option<DecodedScene>,
+    pub(crate) context: Option<ComponentContext>,
+    pub(crate) spawned: usize,
+}
+
+impl SceneLoad {
+    pub(crate) fn start(file_path: &str, sub_scene: &str, key: LuminaKey, model_name: Option<String>) -> Self {
+        let progress = Arc::new(RwLock::new(LoadProgress::new()));
+
+        let thread_progress = Arc::clone(&progress);
+        let thread_file_path = file_path.to_string();
+
+        let thread = thread::spawn(move || {
+            decode_scene(&thread_file_path, &key, model_name.as_deref(), &thread_progress)
+        });
+
+        Self {
+            sub_scene: sub_scene.to_string(),
+            file_path: file_path.to_string(),
+            progress,
+            thread: Some(thread),
+            decoded: None,
+            context: None,
+            spawned: 0,
+        }
+    }
+
+    pub fn sub_scene(&self) -> &str {
+        &self.sub_scene
+    }
+
+    pub fn progress(&self) -> LoadProgress {
+        *self.progress.read().unwrap()
+    }
+
+    /// Whether the load is over, successfully or not.
+    pub fn is_finished(&self) -> bool {
+        matches!(self.progress().step, LoadStep::Finished | LoadStep::Failed)
+    }
+
+    /// Whether reading and decoding are over, so polling will spawn entities.
+    pub fn is_decoded(&self) -> bool {
+        self.decoded.is_some() || self.thread.as_ref().map_or(true, |thread| thread.is_finished())
+    }
+
+    pub(crate) fn fail(&mut self, err: &dyn std::fmt::Display) {
+        eprintln!("ERROR: Failed to load the scene {}: {}", self.file_path, err);
+
+        let progress = self.progress();
+        LoadProgress::set(&self.progress, LoadStep::Failed, progress.done, progress.total);
+
+        self.decoded = None;
+        self.context = None;
+    }
+}
@@ -0,0 +1,193 @@
+use std::{
+    any::Any,
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+};
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+/// Jobs borrowing from the caller, see [`ThreadPool::run_all`].
+pub type ScopedJob<'a> = Box<dyn FnOnce() + Send + 'a>;
+
+/// Threads started once and kept waiting for jobs, so work split across
+/// them every frame doesn't pay for starting threads every frame.
+pub struct ThreadPool {
+    sender: Option<Sender<Job>>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl ThreadPool {
+    /// A pool of `size` threads, at least one.
+    pub fn new(size: usize) -> Self {
+        let (sender, receiver) = mpsc::channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+
+        let workers = (0..size.max(1))
+            .map(|index| {
+                let receiver = Arc::clone(&receiver);
+
+                thread::Builder::new()
+                    .name(format!("lumina-worker-{}", index))
+                    .spawn(move || work(receiver))
+                    .unwrap()
+            })
+            .collect();
+
+        Self {
+            sender: Some(sender),
+            workers,
+        }
+    }
+
+    /// Number of threads of the pool.
+    pub fn size(&self) -> usize {
+        self.workers.len()
+    }
+
+    /// Runs every job on the pool and returns once they all finished, so
+    /// jobs can borrow from the caller. If a job panics, the others still
+    /// finish before the panic carries on in the caller.
+    pub fn run_all<'a>(&self, jobs: Vec<ScopedJob<'a>>) {
+        if jobs.len() == 1 {
+            for job in jobs {
+                job();
+            }
+
+            return;
+        }
+
+        let (done_sender, done_receiver) = mpsc::channel::<Result<(), Box<dyn Any + Send>>>();
+
+        for job in jobs {
+            let done_sender = done_sender.clone();
+
+            let job: ScopedJob<'a> = Box::new(move || {
+                let _ = done_sender.send(panic::catch_unwind(AssertUnwindSafe(job)));
+            });
+
+            // Every job has a sender of `done_receiver`, which is only dropped once
+            // the job ran or was dropped unrun, and nothing returns before they all are
+            let job: Job = unsafe { std::mem::transmute::<ScopedJob<'a>, Job>(job) };
+
+            self.sender.as_ref().unwrap().send(job).unwrap();
+        }
+
+        drop(done_sender);
+
+        let mut panic = None;
+
+        for result in done_receiver.iter() {
+            if let Err(payload) = result {
+                panic.get_or_insert(payload);
+            }
+        }
+
+        if let Some(payload) = panic {
+            panic::resume_unwind(payload);
+        }
+    }
+}
+
+fn work(receiver: Arc<Mutex<Receiver<Job>>>) {
+    loop {
+        let job = match receiver.lock().unwrap().recv() {
+            Ok(job) => job,
+            // The pool was dropped
+            Err(_) => break,
+        };
+
+        job();
+    }
+}
+
+impl Default for ThreadPool {
+    /// A pool of one thread per CPU.
+    fn default() -> Self {
+        Self::new(num_cpus::get())
+    }
+}
+
+impl Drop for ThreadPool {
+    fn drop(&mut self) {
+        drop(self.sender.take());
+
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        panic::{self, AssertUnwindSafe},
+        sync::atomic::{AtomicUsize, Ordering},
+        thread,
+        time::Duration,
+    };
+
+    use super::{ScopedJob, ThreadPool};
+
+    #[test]
+    fn run_all_waits_for_every_job() {
+        let pool = ThreadPool::new(3);
+        let mut values = vec![0; 8];
+
+        let jobs: Vec<ScopedJob> = values
+            .iter_mut()
+            .enumerate()
+            .map(|(index, value)| {
+                Box::new(move || {
+                    thread::sleep(Duration::from_millis(10));
+                    *value = index + 1;
+                }) as ScopedJob
+            })
+            .collect();
+
+        pool.run_all(jobs);
+
+        assert_eq!(values, (1..=8).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn run_all_raises_a_job_panic_once_the_others_finished() {
+        let pool = ThreadPool::new(2);
+        let finished = AtomicUsize::new(0);
+
+        let jobs: Vec<ScopedJob> = (0..4)
+            .map(|index| {
+                let finished = &finished;
+
+                Box::new(move || {
+                    if index == 1 {
+                        panic!("job {} failed", index);
+                    }
+
+                    thread::sleep(Duration::from_millis(10));
+                    finished.fetch_add(1, Ordering::SeqCst);
+                }) as ScopedJob
+            })
+            .collect();
+
+        let payload = panic::catch_unwind(AssertUnwindSafe(|| pool.run_all(jobs))).unwrap_err();
+
+        assert_eq!(payload.downcast_ref::<String>().unwrap(), "job 1 failed");
+        assert_eq!(finished.load(Ordering::SeqCst), 3);
+
+        // The pool still runs jobs afterwards
+        let jobs: Vec<ScopedJob> = (0..2)
+            .map(|_| {
+                Box::new(|| {
+                    finished.fetch_add(1, Ordering::SeqCst);
+                }) as ScopedJob
+            })
+            .collect();
+        pool.run_all(jobs);
+
+        assert_eq!(finished.load(Ordering::SeqCst), 5);
+    }
+}
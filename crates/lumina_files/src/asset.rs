@@ -0,0 +1,156 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::Path,
+};
+
+use crate::{
+    error::LuminaFileError,
+    file::{LuminaFile, LuminaFileType},
+    integrity::{hash, HASH_SIZE},
+    saver::Saver,
+};
+
+/// Directory textures are packed in, as `textures/<file name>`.
+pub const TEXTURE_DIRECTORY: &str = "textures";
+
+/// Entry name of the texture held by the archive entry `entry_path`, if it is a texture.
+pub fn texture_name_of_entry(entry_path: &str) -> Option<&str> {
+    entry_path
+        .strip_prefix(TEXTURE_DIRECTORY)
+        .and_then(|file| file.strip_prefix('/'))
+}
+
+/// Files from disk to be packed next to a scene. Each file is read once
+/// however many times it is added, and files with the same content share one entry.
+#[derive(Debug, Clone, Default)]
+pub struct AssetStore {
+    /// Entry name of each added path, canonicalized when possible.
+    by_path: BTreeMap<String, String>,
+    by_hash: HashMap<[u8; HASH_SIZE], String>,
+    /// Files keyed by entry name, along with the path they were first read from.
+    files: BTreeMap<String, (String, LuminaFile)>,
+}
+
+impl AssetStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reads the file at `path` unless it was added already, and returns the
+    /// name of the entry holding it.
+    pub fn add_file(&mut self, path: &str) -> Result<String, LuminaFileError> {
+        let key = source_key(path);
+
+        if let Some(entry_name) = self.by_path.get(&key) {
+            return Ok(entry_name.clone());
+        }
+
+        let content = fs::read(path)?;
+
+        Ok(self.add(&key, content))
+    }
+
+    /// Adds `content`, read from `path`, and returns the name of the entry holding it.
+    pub fn add(&mut self, path: &str, content: Vec<u8>) -> String {
+        let content_hash = hash(&content);
+
+        if let Some(entry_name) = self.by_hash.get(&content_hash).cloned() {
+            self.by_path.insert(path.to_string(), entry_name.clone());
+            return entry_name;
+        }
+
+        let file_name = Path::new(path)
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_else(|| "asset".to_string());
+
+        // Different files with the same name are told apart by their hash
+        let entry_name = match self.files.contains_key(&file_name) {
+            false => file_name,
+            true => {
+                let short_hash: String = content_hash[..4].iter().map(|byte| format!("{:02x}", byte)).collect();
+
+                match file_name.rsplit_once('.') {
+                    Some((stem, extension)) => format!("{}-{}.{}", stem, short_hash, extension),
+                    None => format!("{}-{}", file_name, short_hash),
+                }
+            }
+        };
+
+        let file = LuminaFile::new(
+            LuminaFileType::detect(&entry_name, &content),
+            entry_name.clone(),
+            content,
+        );
+
+        self.by_path.insert(path.to_string(), entry_name.clone());
+        self.by_hash.insert(content_hash, entry_name.clone());
+        self.files.insert(entry_name.clone(), (path.to_string(), file));
+
+        entry_name
+    }
+
+    /// Name of the entry holding the file added from `path`.
+    pub fn entry_of(&self, path: &str) -> Option<&str> {
+        self.by_path.get(&source_key(path)).map(|entry_name| entry_name.as_str())
+    }
+
+    /// The file of the entry `entry_name`.
+    pub fn get(&self, entry_name: &str) -> Option<&LuminaFile> {
+        self.files.get(entry_name).map(|(_, file)| file)
+    }
+
+    /// Adds `file`, read from `path`, as the entry `file.file_name`, replacing
+    /// the entry already named like it.
+    pub fn insert(&mut self, path: &str, file: LuminaFile) {
+        let entry_name = file.file_name.clone();
+
+        if self.files.contains_key(&entry_name) {
+            self.by_path.retain(|_, name| *name != entry_name);
+            self.by_hash.retain(|_, name| *name != entry_name);
+        }
+
+        self.by_path.insert(path.to_string(), entry_name.clone());
+        self.by_hash.insert(hash(&file.file_content), entry_name.clone());
+        self.files.insert(entry_name, (path.to_string(), file));
+    }
+
+    /// Adds every entry of `other` under the same name, see [`AssetStore::insert`].
+    pub fn merge(&mut self, other: AssetStore) {
+        for (path, file) in other.files.into_values() {
+            self.insert(&path, file);
+        }
+    }
+
+    /// Every entry, with the path it was read from and its file.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &LuminaFile)> {
+        self.files.values().map(|(path, file)| (path.as_str(), file))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    /// Adds every file to the directory `directory` of `saver`.
+    pub fn write_to(&self, saver: &mut Saver, directory: &str) {
+        if self.files.is_empty() {
+            return;
+        }
+
+        if !saver.has_directory(directory) {
+            saver.create_directory(directory);
+        }
+
+        for (_, file) in self.files.values() {
+            saver.insert_file_into_directory(directory, file.clone());
+        }
+    }
+}
+
+/// The same file added through different paths should only be read once.
+fn source_key(path: &str) -> String {
+    fs::canonicalize(path)
+        .map(|path| path.to_string_lossy().to_string())
+        .unwrap_or_else(|_| path.to_string())
+}
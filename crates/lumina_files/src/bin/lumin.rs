@@ -0,0 +1,488 @@
+use std::{
+    env, fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    process,
+};
+
+use lumina_files::{
+    compression::{Compression, DEFAULT_ZSTD_LEVEL},
+    error::LuminaFileError,
+    file::{LuminaFile, LuminaFileType},
+    integrity::{content_hashes, VerifyingKey},
+    key::{LuminaKey, LUMINA_KEY_ENV},
+    loader::Loader,
+    merge::{diff_scenes, merge_scenes},
+    patch::create_patch,
+    saver::Saver,
+    scene::SceneFile,
+    text::{export_text, import_text, safe_relative, SCENE_ENTRY},
+};
+
+const USAGE: &str = "Usage: lumin [options] <command>
+
+Commands:
+    pack <dir> [-o <archive>]     Pack every sub-directory of <dir> into an archive
+    unpack <archive> <dir>        Extract every entry of <archive> into <dir>
+    list <archive>                List the entries of <archive>
+    cat <archive> <dir/file>      Write a single entry to stdout
+    verify <archive>              Check the entry checksums (and signature with --pubkey)
+    diff <a> <b>                  Show the entries added, removed or changed from <a> to <b>
+    patch <base> <new> [-o <out>] Write a patch archive turning <base> into <new>
+    export <archive> <scene.json> [-o <assets>]
+                                  Write the scene as text and the other entries to <assets>
+    import <scene.json> [-o <archive>]
+                                  Pack a text scene and the assets it lists into an archive
+    scene-diff <a> <b>            Show the entities and components changed from scene <a> to <b>
+    merge <base> <ours> <theirs> [-o <scene.json>]
+                                  Merge the scene changes of <ours> and <theirs> since <base>
+
+Scenes are read from text scenes (.json) or from the scene entry of archives.
+
+Options:
+    --key <passphrase>            Encrypt/decrypt with a project passphrase
+    --key-env <VAR>               Read the passphrase from an environment variable
+    --plain                       Pack without encryption
+    --compress <level>            Compress packed entries with zstd at <level>
+    --pubkey <file>               Raw 32 byte ed25519 public key checked by verify
+    --patch <file>                Mount a patch on the archive read, can be repeated in order";
+
+struct Options {
+    key: LuminaKey,
+    compression: Compression,
+    pubkey: Option<String>,
+    output: Option<String>,
+    patches: Vec<String>,
+    args: Vec<String>,
+}
+
+fn main() {
+    let options = match parse_args(env::args().skip(1).collect()) {
+        Ok(options) => options,
+        Err(err) => {
+            eprintln!("ERROR: {}\n\n{}", err, USAGE);
+            process::exit(2);
+        }
+    };
+
+    let args: Vec<&str> = options.args.iter().map(|arg| arg.as_str()).collect();
+
+    let result = match args.as_slice() {
+        ["pack", dir] => pack(&options, dir),
+        ["unpack", archive, dir] => unpack(&options, archive, dir),
+        ["list", archive] => list(&options, archive),
+        ["cat", archive, entry] => cat(&options, archive, entry),
+        ["verify", archive] => verify(&options, archive),
+        ["diff", a, b] => diff(&options, a, b),
+        ["patch", base, updated] => patch(&options, base, updated),
+        ["export", archive, text] => export(&options, archive, text),
+        ["import", text] => import(&options, text),
+        ["scene-diff", a, b] => scene_diff(&options, a, b),
+        ["merge", base, ours, theirs] => merge(&options, base, ours, theirs),
+        _ => {
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        }
+    };
+
+    match result {
+        Ok(true) => {}
+        Ok(false) => process::exit(1),
+        Err(err) => {
+            eprintln!("ERROR: {}", err);
+            process::exit(1);
+        }
+    }
+}
+
+fn parse_args(raw_args: Vec<String>) -> Result<Options, String> {
+    let mut options = Options {
+        key: match env::var(LUMINA_KEY_ENV) {
+            Ok(_) => LuminaKey::from_default_env().map_err(|e| e.to_string())?,
+            Err(_) => LuminaKey::default(),
+        },
+        compression: Compression::None,
+        pubkey: None,
+        output: None,
+        patches: Vec::new(),
+        args: Vec::new(),
+    };
+
+    let mut raw_args = raw_args.into_iter();
+
+    while let Some(arg) = raw_args.next() {
+        let mut value = |name: &str| {
+            raw_args
+                .next()
+                .ok_or_else(|| format!("{} expects a value", name))
+        };
+
+        match arg.as_str() {
+            "--key" => options.key = LuminaKey::from_passphrase(&value("--key")?),
+            "--key-env" => {
+                options.key = LuminaKey::from_env(&value("--key-env")?).map_err(|e| e.to_string())?
+            }
+            "--plain" => options.key = LuminaKey::Plain,
+            "--compress" => {
+                let level = value("--compress")?;
+                options.compression = Compression::Zstd(
+                    level
+                        .parse()
+                        .map_err(|_| format!("invalid compression level {}", level))?,
+                );
+            }
+            "--pubkey" => options.pubkey = Some(value("--pubkey")?),
+            "-o" | "--output" => options.output = Some(value("-o")?),
+            "--patch" => options.patches.push(value("--patch")?),
+            _ => options.args.push(arg),
+        }
+    }
+
+    Ok(options)
+}
+
+fn open(options: &Options, archive: &str) -> Result<Loader, LuminaFileError> {
+    let mut loader = Loader::new();
+    loader.set_key(options.key.clone());
+    loader.open_file(archive.to_string())?;
+
+    for patch in options.patches.iter() {
+        loader.mount_patch(patch.clone())?;
+    }
+
+    Ok(loader)
+}
+
+fn pack(options: &Options, dir: &str) -> Result<bool, LuminaFileError> {
+    let mut saver = Saver::new();
+    saver.set_key(options.key.clone());
+
+    for directory in sorted_dir(Path::new(dir))? {
+        if !directory.is_dir() {
+            eprintln!("WARNING: Skipping {}, only directories can be packed", directory.display());
+            continue;
+        }
+
+        let directory_name = directory.file_name().unwrap().to_string_lossy().into_owned();
+
+        // Scenes are always worth compressing, even when nothing else is
+        let compression = match (options.compression, directory_name.as_str()) {
+            (Compression::None, "gameData") => Compression::Zstd(DEFAULT_ZSTD_LEVEL),
+            (compression, _) => compression,
+        };
+
+        saver.create_compressed_directory(&directory_name, compression);
+
+        let mut files = Vec::new();
+        collect_files(&directory, &mut files)?;
+
+        for file_path in files {
+            let file_name = file_path
+                .strip_prefix(&directory)
+                .unwrap()
+                .components()
+                .map(|component| component.as_os_str().to_string_lossy().into_owned())
+                .collect::<Vec<String>>()
+                .join("/");
+
+            let content = fs::read(&file_path)?;
+
+            saver.insert_file_into_directory(
+                &directory_name,
+                LuminaFile::new(LuminaFileType::detect(&file_name, &content), file_name, content),
+            );
+        }
+    }
+
+    let output = match &options.output {
+        Some(output) => output.clone(),
+        None => {
+            let dir_name = Path::new(dir)
+                .canonicalize()?
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_else(|| "archive".to_string());
+
+            format!("{}.lumin", dir_name)
+        }
+    };
+
+    saver.write_archive(&output)?;
+    println!("Packed {} into {}", dir, output);
+
+    Ok(true)
+}
+
+fn unpack(options: &Options, archive: &str, dir: &str) -> Result<bool, LuminaFileError> {
+    let loader = open(options, archive)?;
+
+    for entry_path in loader.entries() {
+        let target = safe_relative(&entry_path)
+            .map(|relative| Path::new(dir).join(relative))
+            .ok_or_else(|| LuminaFileError::InvalidName(entry_path.clone()))?;
+
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        fs::write(&target, loader.load_entry(&entry_path)?.file_content)?;
+        println!("{}", entry_path);
+    }
+
+    Ok(true)
+}
+
+fn list(options: &Options, archive: &str) -> Result<bool, LuminaFileError> {
+    let loader = open(options, archive)?;
+
+    match loader.get_toc() {
+        Some(toc) if options.patches.is_empty() => {
+            println!("{:<8} {:>12} {:>12} {:<6} {}", "TYPE", "STORED", "SIZE", "PACK", "ENTRY");
+
+            for directory in toc.directories.iter() {
+                for entry in directory.entries.iter() {
+                    let file_type = LuminaFileType::from_byte(entry.file_type)?;
+                    let compression = match entry.compression {
+                        Compression::None => "-",
+                        Compression::Zstd(_) => "zstd",
+                    };
+
+                    println!(
+                        "{:<8} {:>12} {:>12} {:<6} {}/{}",
+                        format!("{:?}", file_type),
+                        entry.size,
+                        entry.raw_size,
+                        compression,
+                        directory.name,
+                        entry.file_name
+                    );
+                }
+            }
+        }
+        _ => {
+            if loader.get_toc().is_none() {
+                println!("(legacy v1 archive)");
+            }
+
+            for entry_path in loader.entries() {
+                println!("{}", entry_path);
+            }
+        }
+    }
+
+    Ok(true)
+}
+
+fn cat(options: &Options, archive: &str, entry_path: &str) -> Result<bool, LuminaFileError> {
+    let loader = open(options, archive)?;
+
+    io::stdout().write_all(&loader.load_entry(entry_path)?.file_content)?;
+
+    Ok(true)
+}
+
+fn verify(options: &Options, archive: &str) -> Result<bool, LuminaFileError> {
+    let mut loader = Loader::new();
+    loader.set_key(options.key.clone());
+
+    if let Some(pubkey_path) = &options.pubkey {
+        let pubkey_bytes: [u8; 32] = fs::read(pubkey_path)?
+            .try_into()
+            .map_err(|_| LuminaFileError::InvalidSignature("public keys are 32 bytes".to_string()))?;
+
+        let verifying_key = VerifyingKey::from_bytes(&pubkey_bytes)
+            .map_err(|e| LuminaFileError::InvalidSignature(e.to_string()))?;
+
+        loader.require_signature(verifying_key);
+    }
+
+    loader.open_file(archive.to_string())?;
+
+    for patch in options.patches.iter() {
+        loader.mount_patch(patch.clone())?;
+    }
+
+    let damaged = loader.verify()?;
+
+    for entry_path in damaged.iter() {
+        println!("DAMAGED {}", entry_path);
+    }
+
+    if damaged.is_empty() {
+        println!("{}: OK", archive);
+    }
+
+    Ok(damaged.is_empty())
+}
+
+/// Compares the decoded content of both archives, since encrypting the same
+/// content twice never gives the same bytes.
+fn diff(options: &Options, a: &str, b: &str) -> Result<bool, LuminaFileError> {
+    let hashes_a = content_hashes(&open(options, a)?)?;
+    let hashes_b = content_hashes(&open(options, b)?)?;
+
+    let mut identical = true;
+
+    for (entry_path, hash_a) in hashes_a.iter() {
+        match hashes_b.get(entry_path) {
+            None => println!("- {}", entry_path),
+            Some(hash_b) if hash_b != hash_a => println!("~ {}", entry_path),
+            Some(_) => continue,
+        }
+
+        identical = false;
+    }
+
+    for entry_path in hashes_b.keys() {
+        if !hashes_a.contains_key(entry_path) {
+            println!("+ {}", entry_path);
+            identical = false;
+        }
+    }
+
+    Ok(identical)
+}
+
+/// Patches are made against `base` with the `--patch` options mounted, so
+/// chains of patches can be built one release at a time.
+fn patch(options: &Options, base: &str, updated: &str) -> Result<bool, LuminaFileError> {
+    let base_loader = open(options, base)?;
+
+    let mut updated_loader = Loader::new();
+    updated_loader.set_key(options.key.clone());
+    updated_loader.open_file(updated.to_string())?;
+
+    let (mut saver, summary) = create_patch(&base_loader, &updated_loader)?;
+    saver.set_key(options.key.clone());
+
+    for entry_path in summary.added.iter() {
+        println!("+ {}", entry_path);
+    }
+    for entry_path in summary.changed.iter() {
+        println!("~ {}", entry_path);
+    }
+    for entry_path in summary.deleted.iter() {
+        println!("- {}", entry_path);
+    }
+
+    let output = match &options.output {
+        Some(output) => output.clone(),
+        None => format!("{}.patch.lumin", file_stem(updated)),
+    };
+
+    saver.write_archive(&output)?;
+    println!("Wrote patch {}", output);
+
+    Ok(true)
+}
+
+/// Assets go next to the text scene by default, in `<scene stem>_assets`.
+fn export(options: &Options, archive: &str, text_path: &str) -> Result<bool, LuminaFileError> {
+    let loader = open(options, archive)?;
+
+    let asset_dir = match &options.output {
+        Some(output) => output.clone(),
+        None => Path::new(text_path)
+            .with_file_name(format!("{}_assets", file_stem(text_path)))
+            .to_string_lossy()
+            .into_owned(),
+    };
+
+    export_text(&loader, text_path, &asset_dir)?;
+    println!("Exported {} to {} and {}", archive, text_path, asset_dir);
+
+    Ok(true)
+}
+
+fn import(options: &Options, text_path: &str) -> Result<bool, LuminaFileError> {
+    let mut saver = import_text(text_path)?;
+    saver.set_key(options.key.clone());
+
+    let output = match &options.output {
+        Some(output) => output.clone(),
+        None => format!("{}.lumin", file_stem(text_path)),
+    };
+
+    saver.save_data_to(&output)?;
+    println!("Imported {} into {}", text_path, output);
+
+    Ok(true)
+}
+
+fn scene_diff(options: &Options, a: &str, b: &str) -> Result<bool, LuminaFileError> {
+    let changes = diff_scenes(&read_scene(options, a)?, &read_scene(options, b)?);
+
+    for change in changes.iter() {
+        println!("{}", change);
+    }
+
+    Ok(changes.is_empty())
+}
+
+/// The merged scene is written even when there are conflicts, with our side
+/// of each, so they can be fixed by hand.
+fn merge(options: &Options, base: &str, ours: &str, theirs: &str) -> Result<bool, LuminaFileError> {
+    let merged = merge_scenes(
+        &read_scene(options, base)?,
+        &read_scene(options, ours)?,
+        &read_scene(options, theirs)?,
+    )?;
+
+    let output = match &options.output {
+        Some(output) => output.clone(),
+        None => format!("{}.merged.json", file_stem(ours)),
+    };
+
+    fs::write(&output, merged.scene.to_text())?;
+
+    for conflict in merged.conflicts.iter() {
+        println!("CONFLICT {}", conflict);
+    }
+
+    println!(
+        "Merged into {} with {} conflict(s)",
+        output,
+        merged.conflicts.len()
+    );
+
+    Ok(merged.conflicts.is_empty())
+}
+
+/// Reads a text scene, or the scene of an archive.
+fn read_scene(options: &Options, path: &str) -> Result<SceneFile, LuminaFileError> {
+    let bytes = match Path::new(path).extension().map_or(false, |extension| extension == "json") {
+        true => fs::read(path)?,
+        false => open(options, path)?.load_entry(SCENE_ENTRY)?.file_content,
+    };
+
+    Ok(SceneFile::from_slice(&bytes)?)
+}
+
+fn file_stem(path: &str) -> String {
+    Path::new(path)
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "archive".to_string())
+}
+
+fn sorted_dir(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut paths = fs::read_dir(dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<io::Result<Vec<PathBuf>>>()?;
+
+    paths.sort();
+
+    Ok(paths)
+}
+
+fn collect_files(dir: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    for path in sorted_dir(dir)? {
+        if path.is_dir() {
+            collect_files(&path, files)?;
+        } else {
+            files.push(path);
+        }
+    }
+
+    Ok(())
+}
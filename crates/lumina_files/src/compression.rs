@@ -0,0 +1,89 @@
+use std::io::Read;
+
+use crate::error::{check_length, LuminaFileError};
+use crate::format::MAX_ENTRY_SIZE;
+
+pub const DEFAULT_ZSTD_LEVEL: i32 = 3;
+
+/// How an entry's content is compressed before it gets encrypted.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Compression {
+    #[default]
+    None,
+    /// zstd with the given compression level (1 to 22, 0 picks zstd's default).
+    Zstd(i32),
+}
+
+impl Compression {
+    pub fn to_byte(&self) -> u8 {
+        match self {
+            Compression::None => 0,
+            Compression::Zstd(_) => 1,
+        }
+    }
+
+    /// The level isn't stored in the archive since it's only needed when compressing.
+    pub fn from_byte(compression_byte: u8) -> Result<Self, LuminaFileError> {
+        match compression_byte {
+            0 => Ok(Compression::None),
+            1 => Ok(Compression::Zstd(0)),
+            _ => Err(LuminaFileError::UnknownCompression(compression_byte)),
+        }
+    }
+}
+
+/// Compresses `content`, falling back to storing it as-is when compression
+/// doesn't make it smaller (e.g. PNG/JPG textures).
+///
+/// Returns the compression that was actually applied along with the data.
+pub fn compress(content: &[u8], compression: Compression) -> Result<(Compression, Vec<u8>), LuminaFileError> {
+    match compression {
+        Compression::None => Ok((Compression::None, content.to_vec())),
+        Compression::Zstd(level) => {
+            let compressed = zstd::bulk::compress(content, level)
+                .map_err(|e| LuminaFileError::Compression(e.to_string()))?;
+
+            if compressed.len() < content.len() {
+                Ok((compression, compressed))
+            } else {
+                Ok((Compression::None, content.to_vec()))
+            }
+        }
+    }
+}
+
+/// Decompresses `content`, which has to give exactly `raw_size` bytes.
+///
+/// `raw_size` comes from the archive, so nothing is allocated from it up
+/// front: the output grows as the stream is decoded, and decoding stops a
+/// byte past `raw_size`, so a small entry claiming a huge size costs no more
+/// than what it actually decodes to.
+pub fn decompress(content: Vec<u8>, compression: Compression, raw_size: u64) -> Result<Vec<u8>, LuminaFileError> {
+    let raw_size = check_length("uncompressed size", raw_size, MAX_ENTRY_SIZE)?;
+
+    let decompressed = match compression {
+        Compression::None => content,
+        Compression::Zstd(_) => {
+            let decoder = zstd::stream::read::Decoder::new(content.as_slice())
+                .map_err(|e| LuminaFileError::Decompression(e.to_string()))?;
+
+            let mut decompressed = Vec::new();
+            decoder
+                .take(raw_size as u64 + 1)
+                .read_to_end(&mut decompressed)
+                .map_err(|e| LuminaFileError::Decompression(e.to_string()))?;
+
+            decompressed
+        }
+    };
+
+    if decompressed.len() != raw_size {
+        return Err(LuminaFileError::Decompression(format!(
+            "expected {} bytes but got {}",
+            raw_size,
+            decompressed.len()
+        )));
+    }
+
+    Ok(decompressed)
+}
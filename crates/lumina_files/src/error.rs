@@ -0,0 +1,122 @@
+use std::{fmt, io};
+
+use crate::scene::SceneError;
+
+#[derive(Debug)]
+pub enum LuminaFileError {
+    /// The archive ended before its header, table of contents or a legacy
+    /// record header could be fully read.
+    TruncatedHeader,
+    /// The archive ended in the middle of the named entry's content.
+    TruncatedEntry(String),
+    Decryption(String),
+    Encryption(String),
+    Compression(String),
+    Decompression(String),
+    UnknownFileType(u8),
+    UnknownCompression(u8),
+    /// A length or count field is larger than the format allows or than
+    /// what is left in the archive.
+    OversizeLength {
+        field: &'static str,
+        length: u64,
+        max: u64,
+    },
+    UnsupportedVersion(u16),
+    /// The archive is encrypted but no key was given, or the key's
+    /// environment variable isn't set.
+    MissingKey(String),
+    InvalidName(String),
+    /// The stored bytes of the named entry (or of the table of contents) don't
+    /// match their recorded hash.
+    ChecksumMismatch(String),
+    InvalidSignature(String),
+    EntryNotFound(String),
+    /// The archive isn't a patch, or was made for another base archive.
+    InvalidPatch(String),
+    /// The scene document, in an archive or in a text scene, couldn't be read.
+    InvalidScene(SceneError),
+    /// A mesh blob is malformed, see [`crate::mesh::encode_mesh`].
+    InvalidMesh(String),
+    Io(io::Error),
+}
+
+impl fmt::Display for LuminaFileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LuminaFileError::TruncatedHeader => write!(f, "truncated .lumin header"),
+            LuminaFileError::TruncatedEntry(entry) => write!(f, "truncated .lumin entry {}", entry),
+            LuminaFileError::Decryption(err) => write!(f, "failed to decrypt: {}", err),
+            LuminaFileError::Encryption(err) => write!(f, "failed to encrypt: {}", err),
+            LuminaFileError::Compression(err) => write!(f, "failed to compress: {}", err),
+            LuminaFileError::Decompression(err) => write!(f, "failed to decompress: {}", err),
+            LuminaFileError::UnknownFileType(byte) => {
+                write!(f, "unknown file type byte 0x{:02x}", byte)
+            }
+            LuminaFileError::UnknownCompression(byte) => {
+                write!(f, "unknown compression byte 0x{:02x}", byte)
+            }
+            LuminaFileError::OversizeLength { field, length, max } => write!(
+                f,
+                "{} of {} bytes exceeds the maximum of {}",
+                field, length, max
+            ),
+            LuminaFileError::UnsupportedVersion(version) => {
+                write!(f, "unsupported .lumin version {}", version)
+            }
+            LuminaFileError::MissingKey(source) => write!(f, "missing key: {}", source),
+            LuminaFileError::InvalidName(name) => write!(f, "invalid entry name {:?}", name),
+            LuminaFileError::ChecksumMismatch(entry) => write!(f, "checksum mismatch in {}", entry),
+            LuminaFileError::InvalidSignature(err) => write!(f, "invalid signature: {}", err),
+            LuminaFileError::EntryNotFound(entry) => write!(f, "entry {} not found", entry),
+            LuminaFileError::InvalidPatch(err) => write!(f, "invalid patch: {}", err),
+            LuminaFileError::InvalidScene(err) => write!(f, "{}", err),
+            LuminaFileError::InvalidMesh(err) => write!(f, "invalid mesh: {}", err),
+            LuminaFileError::Io(err) => write!(f, "I/O error: {}", err),
+        }
+    }
+}
+
+impl LuminaFileError {
+    /// Adds the entry path to decryption errors so it's clear which file failed.
+    pub fn for_entry(self, entry_path: &str) -> Self {
+        match self {
+            LuminaFileError::Decryption(err) => {
+                LuminaFileError::Decryption(format!("{}: {}", entry_path, err))
+            }
+            err => err,
+        }
+    }
+}
+
+impl std::error::Error for LuminaFileError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            LuminaFileError::Io(err) => Some(err),
+            LuminaFileError::InvalidScene(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for LuminaFileError {
+    fn from(err: io::Error) -> Self {
+        LuminaFileError::Io(err)
+    }
+}
+
+impl From<SceneError> for LuminaFileError {
+    fn from(err: SceneError) -> Self {
+        LuminaFileError::InvalidScene(err)
+    }
+}
+
+/// Checks a length or count read from an archive against `max`, so corrupted
+/// or malicious files can't make the loader allocate arbitrary amounts of memory.
+pub fn check_length(field: &'static str, length: u64, max: u64) -> Result<usize, LuminaFileError> {
+    if length > max {
+        return Err(LuminaFileError::OversizeLength { field, length, max });
+    }
+
+    Ok(length as usize)
+}
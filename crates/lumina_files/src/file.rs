@@ -0,0 +1,159 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{error::LuminaFileError, mesh::MESH_MAGIC};
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, Serialize, Deserialize)]
+pub enum LuminaFileType {
+    Json,
+    Jpg,
+    Png,
+    Fbx,
+    Gltf,
+    /// Compiled SPIR-V shader
+    Spirv,
+    /// GLSL shader source
+    Glsl,
+    Wav,
+    Ogg,
+    Ktx2,
+    /// Binary vertex/index data of a mesh
+    Mesh,
+    Hdr,
+    /// Arbitrary bytes with no specific meaning to the engine
+    Raw,
+    None,
+}
+
+impl LuminaFileType {
+    /// Byte identifying the type inside a `.lumin` archive.
+    pub fn to_byte(&self) -> u8 {
+        match self {
+            LuminaFileType::Json => b'J',
+            LuminaFileType::Png => b'P',
+            LuminaFileType::Fbx => b'F',
+            LuminaFileType::Gltf => b'G',
+            LuminaFileType::Jpg => b'E',
+            LuminaFileType::Spirv => b'S',
+            LuminaFileType::Glsl => b'L',
+            LuminaFileType::Wav => b'W',
+            LuminaFileType::Ogg => b'O',
+            LuminaFileType::Ktx2 => b'K',
+            LuminaFileType::Mesh => b'M',
+            LuminaFileType::Hdr => b'H',
+            LuminaFileType::Raw => b'R',
+            LuminaFileType::None => 0,
+        }
+    }
+
+    pub fn from_byte(file_type_byte: u8) -> Result<Self, LuminaFileError> {
+        match file_type_byte {
+            b'J' => Ok(LuminaFileType::Json),
+            b'P' => Ok(LuminaFileType::Png),
+            b'F' => Ok(LuminaFileType::Fbx),
+            b'G' => Ok(LuminaFileType::Gltf),
+            b'E' => Ok(LuminaFileType::Jpg),
+            b'S' => Ok(LuminaFileType::Spirv),
+            b'L' => Ok(LuminaFileType::Glsl),
+            b'W' => Ok(LuminaFileType::Wav),
+            b'O' => Ok(LuminaFileType::Ogg),
+            b'K' => Ok(LuminaFileType::Ktx2),
+            b'M' => Ok(LuminaFileType::Mesh),
+            b'H' => Ok(LuminaFileType::Hdr),
+            b'R' => Ok(LuminaFileType::Raw),
+            0 => Ok(LuminaFileType::None),
+            _ => Err(LuminaFileError::UnknownFileType(file_type_byte)),
+        }
+    }
+
+    /// Guesses the type from a file name's extension, anything unknown is [`LuminaFileType::Raw`].
+    pub fn from_extension(file_name: &str) -> Self {
+        let extension = match file_name.rsplit_once('.') {
+            Some((_, extension)) => extension.to_ascii_lowercase(),
+            None => return LuminaFileType::Raw,
+        };
+
+        match extension.as_str() {
+            "json" => LuminaFileType::Json,
+            "jpg" | "jpeg" => LuminaFileType::Jpg,
+            "png" => LuminaFileType::Png,
+            "fbx" => LuminaFileType::Fbx,
+            "gltf" | "glb" => LuminaFileType::Gltf,
+            "spv" => LuminaFileType::Spirv,
+            "glsl" | "vert" | "frag" | "comp" | "geom" | "tesc" | "tese" => LuminaFileType::Glsl,
+            "wav" => LuminaFileType::Wav,
+            "ogg" => LuminaFileType::Ogg,
+            "ktx2" => LuminaFileType::Ktx2,
+            "mesh" => LuminaFileType::Mesh,
+            "hdr" => LuminaFileType::Hdr,
+            _ => LuminaFileType::Raw,
+        }
+    }
+
+    /// Recognizes the type from the magic bytes at the start of `content`,
+    /// for the formats that have some.
+    pub fn from_content(content: &[u8]) -> Option<Self> {
+        const SIGNATURES: &[(&[u8], LuminaFileType)] = &[
+            (b"\x89PNG\r\n\x1a\n", LuminaFileType::Png),
+            (b"\xff\xd8\xff", LuminaFileType::Jpg),
+            (b"\xabKTX 20\xbb\r\n\x1a\n", LuminaFileType::Ktx2),
+            (b"#?RADIANCE", LuminaFileType::Hdr),
+            (b"#?RGBE", LuminaFileType::Hdr),
+            (b"Kaydara FBX Binary", LuminaFileType::Fbx),
+            (b"glTF", LuminaFileType::Gltf),
+            (b"OggS", LuminaFileType::Ogg),
+            (b"\x03\x02\x23\x07", LuminaFileType::Spirv),
+            (MESH_MAGIC, LuminaFileType::Mesh),
+        ];
+
+        if content.len() >= 12 && &content[..4] == b"RIFF" && &content[8..12] == b"WAVE" {
+            return Some(LuminaFileType::Wav);
+        }
+
+        SIGNATURES
+            .iter()
+            .find(|(signature, _)| content.starts_with(signature))
+            .map(|(_, file_type)| *file_type)
+    }
+
+    /// Type of the file `file_name` holding `content`: what its content says
+    /// when it can tell, what its extension says otherwise.
+    pub fn detect(file_name: &str, content: &[u8]) -> Self {
+        LuminaFileType::from_content(content).unwrap_or_else(|| LuminaFileType::from_extension(file_name))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LuminaFile {
+    pub file_type: LuminaFileType,
+    pub file_name: String,
+    pub file_content: Vec<u8>,
+}
+
+impl LuminaFile {
+    pub fn new(file_type: LuminaFileType, file_name: String, file_content: Vec<u8>) -> Self {
+        Self {
+            file_type,
+            file_name,
+            file_content,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LuminaDirectory {
+    pub name: String,
+    pub files: Vec<LuminaFile>,
+}
+
+impl LuminaDirectory {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            files: Vec::new(),
+        }
+    }
+
+    pub fn get_file(&self, file_name: &str) -> Option<&LuminaFile> {
+        self.files.iter().find(|file| file.file_name == file_name)
+    }
+}
@@ -0,0 +1,273 @@
+use std::io::{self, Read, Write};
+
+use crate::{
+    compression::Compression,
+    error::{check_length, LuminaFileError},
+    integrity::HASH_SIZE,
+};
+
+/// Magic bytes at the start of every versioned `.lumin` archive.
+///
+/// Legacy (v1) archives start directly with the length of the first encrypted
+/// directory name, which would never be this large, so the two layouts can be
+/// told apart by reading the first four bytes.
+pub const LUMINA_MAGIC: &[u8; 4] = b"LUMN";
+
+pub const LEGACY_VERSION: u16 = 1;
+pub const FORMAT_VERSION: u16 = 2;
+
+/// Size in bytes of the fixed v2 header:
+/// magic(4) + version(2) + flags(2) + toc_offset(8) + toc_size(8) + digest(32).
+pub const HEADER_SIZE: u64 = 56;
+
+/// Header flag set when entries and the table of contents are encrypted.
+pub const FLAG_ENCRYPTED: u16 = 1 << 0;
+
+/// Header flag set on patch archives, see [`crate::patch`].
+pub const FLAG_PATCH: u16 = 1 << 1;
+
+/// Upper bounds for the length and count fields read from an archive.
+pub const MAX_NAME_LEN: u64 = 64 * 1024;
+pub const MAX_ENTRY_COUNT: u64 = 1 << 20;
+pub const MAX_TOC_SIZE: u64 = 64 * 1024 * 1024;
+/// Largest entry, stored or decompressed. Well above any mesh or texture an
+/// archive holds, but small enough that allocating it can't take a machine down.
+pub const MAX_ENTRY_SIZE: u64 = 256 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LuminaHeader {
+    pub version: u16,
+    pub flags: u16,
+    pub toc_offset: u64,
+    pub toc_size: u64,
+    /// Hash of the stored table of contents, which in turn holds the hash of
+    /// every entry, making it a digest of the whole archive.
+    pub digest: [u8; HASH_SIZE],
+}
+
+impl LuminaHeader {
+    pub fn new(flags: u16, toc_offset: u64, toc_size: u64, digest: [u8; HASH_SIZE]) -> Self {
+        Self {
+            version: FORMAT_VERSION,
+            flags,
+            toc_offset,
+            toc_size,
+            digest,
+        }
+    }
+
+    pub fn is_encrypted(&self) -> bool {
+        self.flags & FLAG_ENCRYPTED != 0
+    }
+
+    pub fn is_patch(&self) -> bool {
+        self.flags & FLAG_PATCH != 0
+    }
+
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(LUMINA_MAGIC)?;
+        writer.write_all(&self.version.to_le_bytes())?;
+        writer.write_all(&self.flags.to_le_bytes())?;
+        writer.write_all(&self.toc_offset.to_le_bytes())?;
+        writer.write_all(&self.toc_size.to_le_bytes())?;
+        writer.write_all(&self.digest)?;
+
+        Ok(())
+    }
+
+    /// Reads the header from the start of an archive.
+    ///
+    /// Returns `Ok(None)` when the data doesn't start with [`LUMINA_MAGIC`],
+    /// meaning it is a legacy v1 archive.
+    pub fn read_from(bytes: &[u8]) -> Result<Option<Self>, LuminaFileError> {
+        if bytes.len() < LUMINA_MAGIC.len() || &bytes[0..4] != LUMINA_MAGIC {
+            return Ok(None);
+        }
+
+        let mut cursor = io::Cursor::new(&bytes[4..]);
+
+        let version = read_u16(&mut cursor)?;
+        let flags = read_u16(&mut cursor)?;
+        let toc_offset = read_u64(&mut cursor)?;
+        let toc_size = read_u64(&mut cursor)?;
+
+        let mut digest = [0u8; HASH_SIZE];
+        read_header_bytes(&mut cursor, &mut digest)?;
+
+        if version != FORMAT_VERSION {
+            return Err(LuminaFileError::UnsupportedVersion(version));
+        }
+
+        Ok(Some(Self {
+            version,
+            flags,
+            toc_offset,
+            toc_size,
+            digest,
+        }))
+    }
+}
+
+/// Location of a single file inside the archive body.
+///
+/// `size` is the number of bytes stored in the archive, `raw_size` the length
+/// of the content once decrypted and decompressed. `hash` covers the stored bytes,
+/// so damage can be detected without the key.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TocEntry {
+    pub file_type: u8,
+    pub compression: Compression,
+    pub file_name: String,
+    pub offset: u64,
+    pub size: u64,
+    pub raw_size: u64,
+    pub hash: [u8; HASH_SIZE],
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TocDirectory {
+    pub name: String,
+    pub entries: Vec<TocEntry>,
+}
+
+/// Table of contents of a v2 archive, listing every directory and where
+/// each of its files is stored.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TableOfContents {
+    pub directories: Vec<TocDirectory>,
+}
+
+impl TableOfContents {
+    pub fn new() -> Self {
+        Self {
+            directories: Vec::new(),
+        }
+    }
+
+    /// Looks up an entry by its `directory/file` path, e.g. `gameData/scene.json`.
+    pub fn find(&self, entry_path: &str) -> Option<&TocEntry> {
+        let (directory_name, file_name) = entry_path.split_once('/')?;
+
+        self.directories
+            .iter()
+            .find(|directory| directory.name == directory_name)?
+            .entries
+            .iter()
+            .find(|entry| entry.file_name == file_name)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+
+        bytes.extend_from_slice(&(self.directories.len() as u32).to_le_bytes());
+
+        for directory in self.directories.iter() {
+            write_string(&mut bytes, &directory.name);
+            bytes.extend_from_slice(&(directory.entries.len() as u32).to_le_bytes());
+
+            for entry in directory.entries.iter() {
+                bytes.push(entry.file_type);
+                bytes.push(entry.compression.to_byte());
+                write_string(&mut bytes, &entry.file_name);
+                bytes.extend_from_slice(&entry.offset.to_le_bytes());
+                bytes.extend_from_slice(&entry.size.to_le_bytes());
+                bytes.extend_from_slice(&entry.raw_size.to_le_bytes());
+                bytes.extend_from_slice(&entry.hash);
+            }
+        }
+
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, LuminaFileError> {
+        let mut cursor = io::Cursor::new(bytes);
+
+        let directory_count =
+            check_length("directory count", read_u32(&mut cursor)? as u64, MAX_ENTRY_COUNT)?;
+        let mut directories = Vec::new();
+
+        for _ in 0..directory_count {
+            let name = read_string(&mut cursor)?;
+            let entry_count =
+                check_length("entry count", read_u32(&mut cursor)? as u64, MAX_ENTRY_COUNT)?;
+
+            let mut entries = Vec::new();
+
+            for _ in 0..entry_count {
+                let file_type = read_u8(&mut cursor)?;
+                let compression = Compression::from_byte(read_u8(&mut cursor)?)?;
+                let file_name = read_string(&mut cursor)?;
+                let offset = read_u64(&mut cursor)?;
+                let size = read_u64(&mut cursor)?;
+                let raw_size = read_u64(&mut cursor)?;
+
+                let mut hash = [0u8; HASH_SIZE];
+                read_header_bytes(&mut cursor, &mut hash)?;
+
+                check_length("entry size", size, MAX_ENTRY_SIZE)?;
+                check_length("uncompressed size", raw_size, MAX_ENTRY_SIZE)?;
+
+                entries.push(TocEntry {
+                    file_type,
+                    compression,
+                    file_name,
+                    offset,
+                    size,
+                    raw_size,
+                    hash,
+                });
+            }
+
+            directories.push(TocDirectory { name, entries });
+        }
+
+        Ok(Self { directories })
+    }
+}
+
+pub(crate) fn write_string(bytes: &mut Vec<u8>, value: &str) {
+    bytes.extend_from_slice(&(value.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(value.as_bytes());
+}
+
+pub(crate) fn read_string<R: Read>(reader: &mut R) -> Result<String, LuminaFileError> {
+    let len = check_length("name length", read_u32(reader)? as u64, MAX_NAME_LEN)?;
+
+    let mut string_bytes = vec![0u8; len];
+    read_header_bytes(reader, &mut string_bytes)?;
+
+    String::from_utf8(string_bytes)
+        .map_err(|e| LuminaFileError::InvalidName(String::from_utf8_lossy(e.as_bytes()).into_owned()))
+}
+
+/// `read_exact` that reports running out of data as [`LuminaFileError::TruncatedHeader`].
+pub fn read_header_bytes<R: Read>(reader: &mut R, buffer: &mut [u8]) -> Result<(), LuminaFileError> {
+    reader.read_exact(buffer).map_err(|e| match e.kind() {
+        io::ErrorKind::UnexpectedEof => LuminaFileError::TruncatedHeader,
+        _ => LuminaFileError::Io(e),
+    })
+}
+
+pub fn read_u8<R: Read>(reader: &mut R) -> Result<u8, LuminaFileError> {
+    let mut bytes = [0u8; 1];
+    read_header_bytes(reader, &mut bytes)?;
+    Ok(bytes[0])
+}
+
+pub fn read_u16<R: Read>(reader: &mut R) -> Result<u16, LuminaFileError> {
+    let mut bytes = [0u8; 2];
+    read_header_bytes(reader, &mut bytes)?;
+    Ok(u16::from_le_bytes(bytes))
+}
+
+pub fn read_u32<R: Read>(reader: &mut R) -> Result<u32, LuminaFileError> {
+    let mut bytes = [0u8; 4];
+    read_header_bytes(reader, &mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+pub fn read_u64<R: Read>(reader: &mut R) -> Result<u64, LuminaFileError> {
+    let mut bytes = [0u8; 8];
+    read_header_bytes(reader, &mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
@@ -0,0 +1,48 @@
+use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+/// Persistent id of an entity, a random UUID (version 4) so that ids from
+/// different scenes and sessions don't collide.
+///
+/// Scenes save it as a string in the usual `xxxxxxxx-xxxx-xxxx-xxxx-xxxxxxxxxxxx`
+/// form, JSON numbers not being able to hold it. Fields holding one use this
+/// module with `#[serde(with = "crate::id")]`.
+pub type EntityId = u128;
+
+/// `id` in the form scenes save it with.
+pub fn format_id(id: EntityId) -> String {
+    let hex = format!("{:032x}", id);
+
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[0..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..32]
+    )
+}
+
+/// Reads an id written by [`format_id`], `None` if `text` isn't one.
+pub fn parse_id(text: &str) -> Option<EntityId> {
+    let is_valid = text.len() == 36
+        && text.char_indices().all(|(index, c)| match index {
+            8 | 13 | 18 | 23 => c == '-',
+            _ => c.is_ascii_hexdigit(),
+        });
+
+    if !is_valid {
+        return None;
+    }
+
+    EntityId::from_str_radix(&text.replace('-', ""), 16).ok()
+}
+
+pub fn serialize<S: Serializer>(id: &EntityId, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&format_id(*id))
+}
+
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<EntityId, D::Error> {
+    let text = String::deserialize(deserializer)?;
+
+    parse_id(&text).ok_or_else(|| D::Error::custom(format!("invalid entity id {:?}", text)))
+}
@@ -0,0 +1,85 @@
+use std::{collections::BTreeMap, fs};
+
+use ed25519_dalek::{Signature, Signer, Verifier};
+
+pub use ed25519_dalek::{SigningKey, VerifyingKey};
+
+use crate::{
+    error::LuminaFileError,
+    file::LuminaFileType,
+    format::{read_header_bytes, LuminaHeader, HEADER_SIZE},
+    loader::Loader,
+};
+
+pub const HASH_SIZE: usize = 32;
+
+/// BLAKE3 hash of the bytes stored in the archive, used both for entries and
+/// for the table of contents.
+pub fn hash(bytes: &[u8]) -> [u8; HASH_SIZE] {
+    *blake3::hash(bytes).as_bytes()
+}
+
+/// Type and hash of the decoded content of every entry of an opened archive,
+/// by entry path. Archives are compared with it rather than with the hashes
+/// of their table of contents, since encrypting the same content twice never
+/// gives the same bytes.
+pub fn content_hashes(
+    loader: &Loader,
+) -> Result<BTreeMap<String, (LuminaFileType, [u8; HASH_SIZE])>, LuminaFileError> {
+    let mut hashes = BTreeMap::new();
+
+    for entry_path in loader.entries() {
+        let file = loader.load_entry(&entry_path)?;
+        hashes.insert(entry_path, (file.file_type, hash(&file.file_content)));
+    }
+
+    Ok(hashes)
+}
+
+/// Path of the detached signature written next to an archive.
+pub fn signature_path(archive_path: &str) -> String {
+    format!("{}.sig", archive_path)
+}
+
+/// Signs the archive digest stored in the header of `archive_path` and writes
+/// the signature to [`signature_path`].
+///
+/// Since the digest covers the table of contents, which itself holds the hash of
+/// every entry, the signature covers the whole archive.
+pub fn sign_archive(archive_path: &str, signing_key: &SigningKey) -> Result<(), LuminaFileError> {
+    let header = read_header(archive_path)?;
+
+    let signature = signing_key.sign(&header.digest);
+    fs::write(signature_path(archive_path), signature.to_bytes())?;
+
+    Ok(())
+}
+
+/// Checks the detached signature of an archive whose header digest is `digest`.
+pub fn verify_signature(
+    archive_path: &str,
+    digest: &[u8; HASH_SIZE],
+    verifying_key: &VerifyingKey,
+) -> Result<(), LuminaFileError> {
+    let signature_bytes = fs::read(signature_path(archive_path)).map_err(|_| {
+        LuminaFileError::InvalidSignature(format!("{} has no signature", archive_path))
+    })?;
+
+    let signature = Signature::from_slice(&signature_bytes)
+        .map_err(|e| LuminaFileError::InvalidSignature(e.to_string()))?;
+
+    verifying_key
+        .verify(digest, &signature)
+        .map_err(|e| LuminaFileError::InvalidSignature(e.to_string()))
+}
+
+fn read_header(archive_path: &str) -> Result<LuminaHeader, LuminaFileError> {
+    let mut file = fs::File::open(archive_path)?;
+
+    let mut header_bytes = vec![0u8; HEADER_SIZE as usize];
+    read_header_bytes(&mut file, &mut header_bytes)?;
+
+    LuminaHeader::read_from(&header_bytes)?.ok_or_else(|| {
+        LuminaFileError::InvalidSignature("legacy archives can't be signed".to_string())
+    })
+}
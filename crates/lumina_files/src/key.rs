@@ -0,0 +1,90 @@
+use std::env;
+
+use simple_crypt::{decrypt, encrypt};
+
+use crate::error::LuminaFileError;
+
+/// Key every archive used before keys became configurable. It is public in the
+/// source, so it only obfuscates content, but it is still needed to read v1
+/// archives and archives saved without a project key.
+const LEGACY_KEY: &[u8; 58] = b"cgvnhfjxcnmvmjtyurd34563245567878690hfgjcmxfghyuhjkfuiojlg";
+
+/// Environment variable read by [`LuminaKey::from_default_env`].
+pub const LUMINA_KEY_ENV: &str = "LUMINA_ARCHIVE_KEY";
+
+/// Decides how `.lumin` entries are encrypted by the `Saver` and decrypted by the `Loader`.
+#[derive(Clone, PartialEq)]
+pub enum LuminaKey {
+    /// Entries are stored unencrypted, meant for fast iteration during development.
+    Plain,
+    /// Entries are encrypted with the given key bytes.
+    Key(Vec<u8>),
+}
+
+impl LuminaKey {
+    /// The built-in key shared by every Lumina build.
+    pub fn legacy() -> Self {
+        LuminaKey::Key(LEGACY_KEY.to_vec())
+    }
+
+    /// A per-project key. `simple_crypt` stretches it with argon2 before use,
+    /// so a passphrase works just as well as random bytes.
+    pub fn from_bytes(key: &[u8]) -> Self {
+        LuminaKey::Key(key.to_vec())
+    }
+
+    pub fn from_passphrase(passphrase: &str) -> Self {
+        LuminaKey::Key(passphrase.as_bytes().to_vec())
+    }
+
+    /// Reads the key from the environment variable `var`.
+    pub fn from_env(var: &str) -> Result<Self, LuminaFileError> {
+        match env::var(var) {
+            Ok(passphrase) if !passphrase.is_empty() => Ok(LuminaKey::from_passphrase(&passphrase)),
+            _ => Err(LuminaFileError::MissingKey(var.to_string())),
+        }
+    }
+
+    /// Reads the key from [`LUMINA_KEY_ENV`].
+    pub fn from_default_env() -> Result<Self, LuminaFileError> {
+        LuminaKey::from_env(LUMINA_KEY_ENV)
+    }
+
+    pub fn is_plain(&self) -> bool {
+        *self == LuminaKey::Plain
+    }
+
+    pub fn encrypt(&self, data: &[u8]) -> Result<Vec<u8>, LuminaFileError> {
+        match self {
+            LuminaKey::Plain => Ok(data.to_vec()),
+            LuminaKey::Key(key) => {
+                encrypt(data, key).map_err(|e| LuminaFileError::Encryption(e.to_string()))
+            }
+        }
+    }
+
+    pub fn decrypt(&self, data: &[u8]) -> Result<Vec<u8>, LuminaFileError> {
+        match self {
+            LuminaKey::Plain => Ok(data.to_vec()),
+            LuminaKey::Key(key) => {
+                decrypt(data, key).map_err(|e| LuminaFileError::Decryption(e.to_string()))
+            }
+        }
+    }
+}
+
+impl Default for LuminaKey {
+    fn default() -> Self {
+        LuminaKey::legacy()
+    }
+}
+
+// Never print the key itself
+impl std::fmt::Debug for LuminaKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LuminaKey::Plain => write!(f, "LuminaKey::Plain"),
+            LuminaKey::Key(_) => write!(f, "LuminaKey::Key(..)"),
+        }
+    }
+}
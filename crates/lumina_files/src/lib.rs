@@ -1,2 +1,18 @@
+pub mod asset;
+pub mod compression;
+pub mod error;
+pub mod file;
+pub mod format;
+pub mod id;
+pub mod integrity;
+pub mod key;
+pub mod mapped;
+pub mod merge;
+pub mod mesh;
+pub mod migration;
+pub mod patch;
+pub mod prefab;
+pub mod scene;
 pub mod saver;
+pub mod text;
 pub mod loader;
\ No newline at end of file
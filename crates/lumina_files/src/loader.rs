@@ -1,163 +1,524 @@
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     fs::File,
-    io::{self, Read}
+    io::{self, Read, Seek, SeekFrom},
+    sync::{Arc, Mutex},
 };
 
-use simple_crypt::decrypt;
+use crate::{
+    compression::decompress,
+    error::{check_length, LuminaFileError},
+    file::{LuminaDirectory, LuminaFile, LuminaFileType},
+    format::{
+        read_header_bytes, read_u32, read_u8, LuminaHeader, TableOfContents, TocEntry,
+        HEADER_SIZE, LEGACY_VERSION, MAX_ENTRY_COUNT, MAX_ENTRY_SIZE, MAX_NAME_LEN, MAX_TOC_SIZE,
+    },
+    integrity::{hash, verify_signature, VerifyingKey, HASH_SIZE},
+    key::LuminaKey,
+    patch::{apply_to_directories, is_patch_entry, PatchManifest, PATCH_MANIFEST},
+};
 
-const LUMINA_KEY: &[u8; 58] = b"cgvnhfjxcnmvmjtyurd34563245567878690hfgjcmxfghyuhjkfuiojlg";
+/// An archive whose entries are fetched one at a time by their
+/// `directory/file` path, opened with a [`Loader`] or mapped with a
+/// [`crate::mapped::MappedLoader`].
+pub trait ArchiveEntries {
+    /// Paths of every entry of the archive.
+    fn entry_paths(&self) -> Vec<String>;
 
-#[derive(Debug, PartialEq,Clone, Copy)]
-pub enum LuminaFileType {
-    Json,
-    Jpg,
-    Png,
-    Fbx,
-    Gltf,
-    None,
+    fn get_entry(&self, entry_path: &str) -> Result<Arc<LuminaFile>, LuminaFileError>;
 }
 
-#[derive(Debug,Clone)]
-pub struct LuminaFile {
-    pub file_type: LuminaFileType,
-    pub file_name: String,
-    pub file_content: Vec<u8>,
+/// A patch mounted on top of the opened archive with [`Loader::mount_patch`].
+struct MountedPatch {
+    file_path: String,
+    file: Mutex<File>,
+    file_size: u64,
+    toc: TableOfContents,
+    encrypted: bool,
+    deleted: HashSet<String>,
+    digest: [u8; HASH_SIZE],
 }
 
-impl LuminaFile {
-    pub fn new(file_type: LuminaFileType, file_name: String, file_content: Vec<u8>) -> Self {
-        Self {
-            file_type,
-            file_name,
-            file_content,
+impl MountedPatch {
+    fn find(&self, entry_path: &str) -> Option<&TocEntry> {
+        if is_patch_entry(entry_path) {
+            return None;
         }
+
+        self.toc.find(entry_path)
     }
-}
 
-#[derive(Debug,Clone)]
-pub struct LuminaDirectory {
-    pub name: String,
-    pub files: Vec<LuminaFile>,
+    fn entries(&self) -> Vec<String> {
+        toc_entries(&self.toc)
+            .into_iter()
+            .filter(|entry_path| !is_patch_entry(entry_path))
+            .collect()
+    }
 }
 
 pub struct Loader {
     pub directories: HashMap<String, LuminaDirectory>,
+    file_path: String,
+    /// Kept open for entries to be read from, `None` for legacy v1 archives
+    /// which are fully loaded when opened.
+    file: Option<Mutex<File>>,
+    file_size: u64,
+    toc: Option<TableOfContents>,
+    key: LuminaKey,
+    encrypted: bool,
+    digest: Option<[u8; HASH_SIZE]>,
+    trusted_key: Option<VerifyingKey>,
+    patches: Vec<MountedPatch>,
 }
 
 impl Loader {
     pub fn new() -> Self {
         Self {
             directories: HashMap::new(),
+            file_path: String::new(),
+            file: None,
+            file_size: 0,
+            toc: None,
+            key: LuminaKey::default(),
+            encrypted: true,
+            digest: None,
+            trusted_key: None,
+            patches: Vec::new(),
         }
     }
 
-    pub fn load_file(&mut self, file_path: String) {
-    match File::open(&file_path) {
-        Ok(mut file) => {
-            let mut file_content = Vec::new();
-            match file.read_to_end(&mut file_content) {
-                Ok(_) => {
-                    let mut cursor = std::io::Cursor::new(file_content);
-
-                    loop {
-                        let mut directory_name_len_bytes = [0u8; 4];
-                        if cursor.read_exact(&mut directory_name_len_bytes).is_err() {
-                            break;
-                        }
-
-                        let directory_name_len =
-                            u32::from_le_bytes(directory_name_len_bytes) as usize;
-
-                        let mut dir_name_bytes = vec![0u8; directory_name_len];
-                        cursor.read_exact(&mut dir_name_bytes).unwrap();
-
-                        // Decrypt the directory name
-                        let decrypted_dir_name = decrypt(&dir_name_bytes, LUMINA_KEY)
-                            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))
-                            .unwrap();
-
-                        let directory_name =
-                            String::from_utf8_lossy(&decrypted_dir_name).into_owned();
-
-                        let mut num_files_bytes = [0u8; 4];
-                        cursor.read_exact(&mut num_files_bytes).unwrap();
-                        let num_files = u32::from_le_bytes(num_files_bytes) as usize;
-
-                        let mut files: Vec<LuminaFile> = Vec::with_capacity(num_files);
-
-                        for _ in 0..num_files {
-                            let mut file_type_byte = [0u8; 1];
-                            let mut name_size_bytes = [0u8; 4];
-                            let mut content_size_bytes = [0u8; 4];
-
-                            cursor.read_exact(&mut file_type_byte).unwrap();
-                            cursor.read_exact(&mut name_size_bytes).unwrap();
-                            cursor.read_exact(&mut content_size_bytes).unwrap();
-
-                            let file_type = match file_type_byte[0] {
-                                b'J' => LuminaFileType::Json,
-                                b'P' => LuminaFileType::Png,
-                                b'F' => LuminaFileType::Fbx,
-                                b'G' => LuminaFileType::Gltf,
-                                b'E' => LuminaFileType::Jpg,
-                                _ => {
-                                    println!("ERROR: Couldn't find a file type!");
-                                    LuminaFileType::None
-                                }
-                            };
-
-                            let name_size = u32::from_le_bytes(name_size_bytes) as usize;
-                            let content_size =
-                                u32::from_le_bytes(content_size_bytes) as usize;
-
-                            let mut name_bytes = vec![0u8; name_size];
-                            cursor.read_exact(&mut name_bytes).unwrap();
-
-                            let decrypted_file_name =
-                                decrypt(&name_bytes, LUMINA_KEY)
-                                    .map_err(|e| io::Error::new(io::ErrorKind::Other, e))
-                                    .unwrap();
-                            let file_name =
-                                String::from_utf8_lossy(&decrypted_file_name).into_owned();
-
-                            let mut content = vec![0u8; content_size];
-                            cursor.read_exact(&mut content).unwrap();
-
-                            // Decrypt the file content
-                            let decrypted_content = decrypt(
-                                &content,
-                                LUMINA_KEY,
-                            )
-                            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))
-                            .unwrap();
-
-                            files.push(LuminaFile {
-                                file_type,
-                                file_name,
-                                file_content: decrypted_content,
-                            });
-                        }
-
-                        self.directories.insert(
-                            directory_name.clone(),
-                            LuminaDirectory {
-                                name: directory_name,
-                                files,
-                            },
-                        );
-                    }
+    /// Sets the key used to decrypt encrypted archives. Plain archives are
+    /// read as-is whatever the key is, and legacy v1 archives always use the
+    /// built-in key.
+    pub fn set_key(&mut self, key: LuminaKey) {
+        self.key = key;
+    }
+
+    /// Makes the loader refuse any archive without a valid detached signature
+    /// from `verifying_key`, see [`crate::integrity::sign_archive`].
+    pub fn require_signature(&mut self, verifying_key: VerifyingKey) {
+        self.trusted_key = Some(verifying_key);
+    }
+
+    /// Digest of the opened archive, `None` for legacy v1 archives.
+    pub fn get_digest(&self) -> Option<[u8; HASH_SIZE]> {
+        self.digest
+    }
+
+    /// Whether the opened archive was saved encrypted.
+    pub fn is_encrypted(&self) -> bool {
+        self.encrypted
+    }
+
+    /// Loads every file of the archive into `directories`.
+    pub fn load_file(&mut self, file_path: String) -> Result<(), LuminaFileError> {
+        self.open_file(file_path)?;
+
+        let toc = match &self.toc {
+            Some(toc) => toc.clone(),
+            None => return Ok(()),
+        };
+
+        for toc_directory in toc.directories.iter() {
+            let mut files: Vec<LuminaFile> = Vec::with_capacity(toc_directory.entries.len());
+
+            for entry in toc_directory.entries.iter() {
+                files.push(self.read_entry(&toc_directory.name, entry)?);
+            }
+
+            self.directories.insert(
+                toc_directory.name.clone(),
+                LuminaDirectory {
+                    name: toc_directory.name.clone(),
+                    files,
+                },
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Reads only the header and table of contents of the archive, so
+    /// single entries can later be fetched with [`Loader::load_entry`].
+    ///
+    /// Legacy v1 archives have no table of contents and are fully loaded instead.
+    pub fn open_file(&mut self, file_path: String) -> Result<(), LuminaFileError> {
+        self.file_path = file_path.clone();
+        self.file = None;
+        self.toc = None;
+        self.digest = None;
+        self.patches.clear();
+        self.directories.clear();
+
+        let mut file = File::open(&file_path)?;
+        self.file_size = file.metadata()?.len();
+
+        match self.read_header(&file_path, &mut file, self.file_size)? {
+            Some((header, toc)) => {
+                self.toc = Some(toc);
+                self.digest = Some(header.digest);
+                self.encrypted = header.is_encrypted();
+                self.file = Some(Mutex::new(file));
+            }
+            None => {
+                if self.trusted_key.is_some() {
+                    return Err(LuminaFileError::InvalidSignature(
+                        "legacy archives can't be signed".to_string(),
+                    ));
                 }
-                Err(err) => {
-                    eprintln!("Error reading file content: {}", err);
+
+                let mut file_content = Vec::new();
+                file.seek(SeekFrom::Start(0))?;
+                file.read_to_end(&mut file_content)?;
+
+                self.encrypted = true;
+                self.load_legacy(file_content)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Mounts a patch archive (see [`PatchManifest`]) on top of the opened
+    /// archive. Patches have to be mounted in the order they were made, each
+    /// one right after the patch it was made on top of, entries then resolve
+    /// to their version in the most recently mounted patch.
+    ///
+    /// If the archive was fully loaded with [`Loader::load_file`], `directories`
+    /// is updated with the patch too.
+    pub fn mount_patch(&mut self, file_path: String) -> Result<(), LuminaFileError> {
+        let base_digest = self.digest.ok_or_else(|| {
+            LuminaFileError::InvalidPatch("patches need a v2 base archive to be opened first".to_string())
+        })?;
+
+        let mut file = File::open(&file_path)?;
+        let file_size = file.metadata()?.len();
+
+        let (header, toc) = self
+            .read_header(&file_path, &mut file, file_size)?
+            .ok_or_else(|| LuminaFileError::InvalidPatch(format!("{} is a legacy archive", file_path)))?;
+
+        if !header.is_patch() {
+            return Err(LuminaFileError::InvalidPatch(format!("{} is not a patch", file_path)));
+        }
+
+        let manifest_entry = toc
+            .find(PATCH_MANIFEST)
+            .ok_or_else(|| LuminaFileError::EntryNotFound(format!("{}: {}", file_path, PATCH_MANIFEST)))?;
+
+        let file = Mutex::new(file);
+        let stored = read_stored_at(&file, file_size, PATCH_MANIFEST, manifest_entry)?;
+        let manifest = PatchManifest::from_bytes(
+            &decode_entry(PATCH_MANIFEST, manifest_entry, &stored, &self.key, header.is_encrypted())?
+                .file_content,
+        )?;
+
+        if manifest.base_digest != base_digest {
+            return Err(LuminaFileError::InvalidPatch(format!(
+                "{} was made for another base archive than {}",
+                file_path, self.file_path
+            )));
+        }
+
+        if manifest.previous_digest != self.last_patch_digest() {
+            return Err(LuminaFileError::InvalidPatch(match self.patches.last() {
+                Some(previous) => format!("{} doesn't follow the patch {}", file_path, previous.file_path),
+                None => format!("{} has to be mounted after the patch it follows", file_path),
+            }));
+        }
+
+        let patch = MountedPatch {
+            file_path,
+            file,
+            file_size,
+            toc,
+            encrypted: header.is_encrypted(),
+            deleted: manifest.deleted.iter().cloned().collect(),
+            digest: header.digest,
+        };
+
+        if !self.directories.is_empty() {
+            let mut files = Vec::new();
+
+            for entry_path in patch.entries() {
+                let (directory_name, _) = entry_path.split_once('/').unwrap();
+                files.push((directory_name.to_string(), self.read_patch_entry(&patch, &entry_path)?));
+            }
+
+            apply_to_directories(&mut self.directories, &manifest.deleted, files);
+        }
+
+        self.patches.push(patch);
+
+        Ok(())
+    }
+
+    /// Header digest of the most recently mounted patch, see [`PatchManifest::previous_digest`].
+    pub fn last_patch_digest(&self) -> Option<[u8; HASH_SIZE]> {
+        self.patches.last().map(|patch| patch.digest)
+    }
+
+    /// Paths of the patches mounted with [`Loader::mount_patch`], oldest first.
+    pub fn patches(&self) -> Vec<&str> {
+        self.patches.iter().map(|patch| patch.file_path.as_str()).collect()
+    }
+
+    /// Reads the header and table of contents of a v2 archive, `None` for legacy archives.
+    fn read_header(
+        &self,
+        file_path: &str,
+        file: &mut File,
+        file_size: u64,
+    ) -> Result<Option<(LuminaHeader, TableOfContents)>, LuminaFileError> {
+        if file_size == 0 {
+            return Err(LuminaFileError::TruncatedHeader);
+        }
+
+        let mut header_bytes = vec![0u8; HEADER_SIZE.min(file_size) as usize];
+        read_header_bytes(file, &mut header_bytes)?;
+
+        let header = match LuminaHeader::read_from(&header_bytes)? {
+            Some(header) => header,
+            None => return Ok(None),
+        };
+
+        let toc_size = check_toc_bounds(&header, file_size)?;
+
+        let mut toc_bytes = vec![0u8; toc_size];
+        file.seek(SeekFrom::Start(header.toc_offset))?;
+        read_header_bytes(file, &mut toc_bytes)?;
+
+        let toc = read_toc(
+            file_path,
+            &header,
+            &toc_bytes,
+            &self.key,
+            self.trusted_key.as_ref(),
+        )?;
+
+        Ok(Some((header, toc)))
+    }
+
+    /// Fetches a single file by its `directory/file` path (e.g. `gameData/scene.json`)
+    /// without decrypting the rest of the archive.
+    pub fn load_entry(&self, entry_path: &str) -> Result<LuminaFile, LuminaFileError> {
+        let not_found = || LuminaFileError::EntryNotFound(entry_path.to_string());
+
+        let (directory_name, file_name) = entry_path.split_once('/').ok_or_else(not_found)?;
+
+        for patch in self.patches.iter().rev() {
+            if patch.find(entry_path).is_some() {
+                return self.read_patch_entry(patch, entry_path);
+            }
+
+            if patch.deleted.contains(entry_path) {
+                return Err(not_found());
+            }
+        }
+
+        match &self.toc {
+            Some(toc) => self.read_entry(directory_name, toc.find(entry_path).ok_or_else(not_found)?),
+            None => self
+                .directories
+                .get(directory_name)
+                .and_then(|directory| {
+                    directory
+                        .files
+                        .iter()
+                        .find(|file| file.file_name == file_name)
+                })
+                .cloned()
+                .ok_or_else(not_found),
+        }
+    }
+
+    /// Lists the `directory/file` paths of every entry in the opened archive,
+    /// with the mounted patches applied.
+    pub fn entries(&self) -> Vec<String> {
+        let mut entries: Vec<String> = match &self.toc {
+            Some(toc) => toc_entries(toc),
+            None => self
+                .directories
+                .values()
+                .flat_map(|directory| {
+                    directory
+                        .files
+                        .iter()
+                        .map(move |file| format!("{}/{}", directory.name, file.file_name))
+                })
+                .collect(),
+        };
+
+        for patch in self.patches.iter() {
+            let patch_entries = patch.entries();
+
+            entries.retain(|entry_path| {
+                !patch.deleted.contains(entry_path) && !patch_entries.contains(entry_path)
+            });
+            entries.extend(patch_entries);
+        }
+
+        entries
+    }
+
+    pub fn get_toc(&self) -> Option<&TableOfContents> {
+        self.toc.as_ref()
+    }
+
+    /// Checks the stored bytes of every entry against their recorded hash and
+    /// returns the `directory/file` paths of the damaged ones. Damaged entries
+    /// of mounted patches are prefixed with the path of the patch.
+    ///
+    /// An entry that can't be read at all counts as damaged too, errors are
+    /// only returned when an archive itself can't be opened.
+    pub fn verify(&self) -> Result<Vec<String>, LuminaFileError> {
+        let toc = self
+            .toc
+            .as_ref()
+            .ok_or(LuminaFileError::UnsupportedVersion(LEGACY_VERSION))?;
+
+        let mut damaged = Vec::new();
+
+        let file = self
+            .file
+            .as_ref()
+            .ok_or(LuminaFileError::UnsupportedVersion(LEGACY_VERSION))?;
+
+        let archives = std::iter::once((self.file_path.as_str(), file, self.file_size, toc)).chain(
+            self.patches
+                .iter()
+                .map(|patch| (patch.file_path.as_str(), &patch.file, patch.file_size, &patch.toc)),
+        );
+
+        for (file_path, file, file_size, toc) in archives {
+            for directory in toc.directories.iter() {
+                for entry in directory.entries.iter() {
+                    let entry_path = format!("{}/{}", directory.name, entry.file_name);
+
+                    // Whatever goes wrong reading one entry, like a size field
+                    // too large or pointing past the end, means that entry is damaged
+                    if read_stored_at(file, file_size, &entry_path, entry).is_err() {
+                        damaged.push(if file_path == self.file_path {
+                            entry_path
+                        } else {
+                            format!("{}: {}", file_path, entry_path)
+                        })
+                    }
                 }
             }
         }
-        Err(err) => {
-            eprintln!("Error opening file {}: {}", file_path, err);
+
+        Ok(damaged)
+    }
+
+    fn read_stored(&self, directory_name: &str, entry: &TocEntry) -> Result<Vec<u8>, LuminaFileError> {
+        let entry_path = format!("{}/{}", directory_name, entry.file_name);
+
+        let file = self
+            .file
+            .as_ref()
+            .ok_or_else(|| LuminaFileError::EntryNotFound(entry_path.clone()))?;
+
+        read_stored_at(file, self.file_size, &entry_path, entry)
+    }
+
+    fn read_patch_entry(&self, patch: &MountedPatch, entry_path: &str) -> Result<LuminaFile, LuminaFileError> {
+        let entry = patch
+            .find(entry_path)
+            .ok_or_else(|| LuminaFileError::EntryNotFound(entry_path.to_string()))?;
+
+        let content = read_stored_at(&patch.file, patch.file_size, entry_path, entry)?;
+
+        decode_entry(entry_path, entry, &content, &self.key, patch.encrypted)
+    }
+
+    fn read_entry(&self, directory_name: &str, entry: &TocEntry) -> Result<LuminaFile, LuminaFileError> {
+        let entry_path = format!("{}/{}", directory_name, entry.file_name);
+
+        let content = self.read_stored(directory_name, entry)?;
+
+        decode_entry(&entry_path, entry, &content, &self.key, self.encrypted)
+    }
+
+    fn load_legacy(&mut self, file_content: Vec<u8>) -> Result<(), LuminaFileError> {
+        let legacy_key = LuminaKey::legacy();
+        let file_size = file_content.len() as u64;
+        let mut cursor = std::io::Cursor::new(file_content);
+
+        while cursor.position() < file_size {
+            let remaining = file_size - cursor.position();
+
+            let directory_name_len = check_length(
+                "directory name length",
+                read_u32(&mut cursor)? as u64,
+                MAX_NAME_LEN.min(remaining),
+            )?;
+
+            let mut dir_name_bytes = vec![0u8; directory_name_len];
+            read_header_bytes(&mut cursor, &mut dir_name_bytes)?;
+
+            // Decrypt the directory name
+            let decrypted_dir_name = legacy_key.decrypt(&dir_name_bytes)?;
+
+            let directory_name = String::from_utf8_lossy(&decrypted_dir_name).into_owned();
+
+            let num_files =
+                check_length("file count", read_u32(&mut cursor)? as u64, MAX_ENTRY_COUNT)?;
+
+            let mut files: Vec<LuminaFile> = Vec::new();
+
+            for _ in 0..num_files {
+                let file_type = LuminaFileType::from_byte(read_u8(&mut cursor)?)?;
+
+                let remaining = file_size - cursor.position();
+
+                let name_size = check_length(
+                    "file name length",
+                    read_u32(&mut cursor)? as u64,
+                    MAX_NAME_LEN.min(remaining),
+                )?;
+                let content_size = check_length(
+                    "file size",
+                    read_u32(&mut cursor)? as u64,
+                    MAX_ENTRY_SIZE.min(remaining),
+                )?;
+
+                let mut name_bytes = vec![0u8; name_size];
+                read_header_bytes(&mut cursor, &mut name_bytes)?;
+
+                let decrypted_file_name = legacy_key.decrypt(&name_bytes)?;
+                let file_name = String::from_utf8_lossy(&decrypted_file_name).into_owned();
+
+                let entry_path = format!("{}/{}", directory_name, file_name);
+
+                let mut content = vec![0u8; content_size];
+                cursor
+                    .read_exact(&mut content)
+                    .map_err(|_| LuminaFileError::TruncatedEntry(entry_path.clone()))?;
+
+                // Decrypt the file content
+                let decrypted_content = legacy_key
+                    .decrypt(&content)
+                    .map_err(|e| e.for_entry(&entry_path))?;
+
+                files.push(LuminaFile {
+                    file_type,
+                    file_name,
+                    file_content: decrypted_content,
+                });
+            }
+
+            self.directories.insert(
+                directory_name.clone(),
+                LuminaDirectory {
+                    name: directory_name,
+                    files,
+                },
+            );
         }
+
+        Ok(())
     }
-}
 
     /*pub fn load_data(&self,manager:&Query,device:Arc<Device>) {
         let entities = String::from_utf8(self.directories.get("directory1").unwrap().files[0].file_content.clone()).unwrap();
@@ -184,3 +545,121 @@ impl Loader {
         }
     }*/
 }
+
+impl ArchiveEntries for Loader {
+    fn entry_paths(&self) -> Vec<String> {
+        self.entries()
+    }
+
+    fn get_entry(&self, entry_path: &str) -> Result<Arc<LuminaFile>, LuminaFileError> {
+        Ok(Arc::new(self.load_entry(entry_path)?))
+    }
+}
+
+/// Reads the bytes of an entry as stored in the open archive `file` and
+/// checks them against its hash.
+fn read_stored_at(
+    file: &Mutex<File>,
+    file_size: u64,
+    entry_path: &str,
+    entry: &TocEntry,
+) -> Result<Vec<u8>, LuminaFileError> {
+    if entry.offset.saturating_add(entry.size) > file_size {
+        return Err(LuminaFileError::TruncatedEntry(entry_path.to_string()));
+    }
+
+    let mut file = file.lock().unwrap();
+
+    let mut content = vec![0u8; check_length("entry size", entry.size, MAX_ENTRY_SIZE)?];
+    file.seek(SeekFrom::Start(entry.offset))?;
+    file.read_exact(&mut content).map_err(|e| match e.kind() {
+        io::ErrorKind::UnexpectedEof => LuminaFileError::TruncatedEntry(entry_path.to_string()),
+        _ => LuminaFileError::Io(e),
+    })?;
+
+    check_stored(entry_path, entry, &content)?;
+
+    Ok(content)
+}
+
+fn toc_entries(toc: &TableOfContents) -> Vec<String> {
+    toc.directories
+        .iter()
+        .flat_map(|directory| {
+            directory
+                .entries
+                .iter()
+                .map(move |entry| format!("{}/{}", directory.name, entry.file_name))
+        })
+        .collect()
+}
+
+/// Returns the size of the table of contents after checking it fits in the archive.
+pub(crate) fn check_toc_bounds(header: &LuminaHeader, file_size: u64) -> Result<usize, LuminaFileError> {
+    let toc_size = check_length("table of contents size", header.toc_size, MAX_TOC_SIZE)?;
+
+    if header.toc_offset.saturating_add(header.toc_size) > file_size {
+        return Err(LuminaFileError::TruncatedHeader);
+    }
+
+    Ok(toc_size)
+}
+
+/// Checks the stored table of contents against the header digest (and the
+/// archive signature when a trusted key is given), then decrypts it.
+pub(crate) fn read_toc(
+    file_path: &str,
+    header: &LuminaHeader,
+    toc_bytes: &[u8],
+    key: &LuminaKey,
+    trusted_key: Option<&VerifyingKey>,
+) -> Result<TableOfContents, LuminaFileError> {
+    if hash(toc_bytes) != header.digest {
+        return Err(LuminaFileError::ChecksumMismatch("table of contents".to_string()));
+    }
+
+    if let Some(trusted_key) = trusted_key {
+        verify_signature(file_path, &header.digest, trusted_key)?;
+    }
+
+    let decrypted_toc = if header.is_encrypted() {
+        if key.is_plain() {
+            return Err(LuminaFileError::MissingKey(file_path.to_string()));
+        }
+
+        key.decrypt(toc_bytes)?
+    } else {
+        toc_bytes.to_vec()
+    };
+
+    TableOfContents::from_bytes(&decrypted_toc)
+}
+
+pub(crate) fn check_stored(entry_path: &str, entry: &TocEntry, stored: &[u8]) -> Result<(), LuminaFileError> {
+    if hash(stored) != entry.hash {
+        return Err(LuminaFileError::ChecksumMismatch(entry_path.to_string()));
+    }
+
+    Ok(())
+}
+
+/// Decrypts and decompresses the stored bytes of an entry.
+pub(crate) fn decode_entry(
+    entry_path: &str,
+    entry: &TocEntry,
+    stored: &[u8],
+    key: &LuminaKey,
+    encrypted: bool,
+) -> Result<LuminaFile, LuminaFileError> {
+    let decrypted_content = if encrypted {
+        key.decrypt(stored).map_err(|e| e.for_entry(entry_path))?
+    } else {
+        stored.to_vec()
+    };
+
+    Ok(LuminaFile {
+        file_type: LuminaFileType::from_byte(entry.file_type)?,
+        file_name: entry.file_name.clone(),
+        file_content: decompress(decrypted_content, entry.compression, entry.raw_size)?,
+    })
+}
@@ -0,0 +1,247 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::File,
+    sync::{Arc, Mutex},
+};
+
+use memmap2::Mmap;
+
+use crate::{
+    error::{check_length, LuminaFileError},
+    file::LuminaFile,
+    format::{LuminaHeader, TableOfContents, HEADER_SIZE, LEGACY_VERSION, MAX_ENTRY_SIZE},
+    integrity::VerifyingKey,
+    key::LuminaKey,
+    loader::{check_stored, check_toc_bounds, decode_entry, read_toc, ArchiveEntries},
+};
+
+/// Default amount of decoded entry bytes kept in the cache.
+pub const DEFAULT_CACHE_BUDGET: usize = 256 * 1024 * 1024;
+
+/// Least recently used cache of decoded entries, bounded by the total size of their content.
+///
+/// Every access stamps the entry with the next tick, `order` maps ticks back
+/// to entries so the oldest one is found without going through them all.
+struct EntryCache {
+    budget: usize,
+    used: usize,
+    tick: u64,
+    entries: HashMap<String, (Arc<LuminaFile>, u64)>,
+    order: BTreeMap<u64, String>,
+}
+
+impl EntryCache {
+    fn new(budget: usize) -> Self {
+        Self {
+            budget,
+            used: 0,
+            tick: 0,
+            entries: HashMap::new(),
+            order: BTreeMap::new(),
+        }
+    }
+
+    fn get(&mut self, entry_path: &str) -> Option<Arc<LuminaFile>> {
+        self.tick += 1;
+
+        let (file, stamp) = self.entries.get_mut(entry_path)?;
+
+        let path = self.order.remove(stamp).unwrap();
+        *stamp = self.tick;
+        self.order.insert(self.tick, path);
+
+        Some(file.clone())
+    }
+
+    fn insert(&mut self, entry_path: &str, file: Arc<LuminaFile>) {
+        let size = file.file_content.len();
+
+        // Entries bigger than the whole budget are handed out without being kept
+        if size > self.budget {
+            return;
+        }
+
+        self.tick += 1;
+
+        if let Some((previous, stamp)) = self.entries.insert(entry_path.to_string(), (file, self.tick)) {
+            self.used -= previous.file_content.len();
+            self.order.remove(&stamp);
+        }
+
+        self.used += size;
+        self.order.insert(self.tick, entry_path.to_string());
+
+        self.evict();
+    }
+
+    fn evict(&mut self) {
+        while self.used > self.budget {
+            let oldest = match self.order.pop_first() {
+                Some((_, oldest)) => oldest,
+                None => break,
+            };
+
+            if let Some((file, _)) = self.entries.remove(&oldest) {
+                self.used -= file.file_content.len();
+            }
+        }
+    }
+}
+
+/// Memory-mapped alternative to [`crate::loader::Loader`] for big archives.
+///
+/// Only the table of contents is decoded when opening. Entries are decrypted
+/// and decompressed on first access and kept in a cache that evicts the least
+/// recently used ones once the cache budget is exceeded, so the memory
+/// used stays bounded whatever the size of the archive.
+pub struct MappedLoader {
+    file_path: String,
+    mmap: Mmap,
+    toc: TableOfContents,
+    key: LuminaKey,
+    encrypted: bool,
+    cache: Mutex<EntryCache>,
+}
+
+impl MappedLoader {
+    /// Maps the archive at `file_path`. Legacy v1 archives have no table of
+    /// contents to index them and have to go through the `Loader` instead.
+    pub fn open(file_path: &str, key: LuminaKey) -> Result<Self, LuminaFileError> {
+        MappedLoader::open_with(file_path, key, None)
+    }
+
+    /// Like [`MappedLoader::open`], but refuses archives without a valid signature from `trusted_key`.
+    pub fn open_signed(
+        file_path: &str,
+        key: LuminaKey,
+        trusted_key: VerifyingKey,
+    ) -> Result<Self, LuminaFileError> {
+        MappedLoader::open_with(file_path, key, Some(&trusted_key))
+    }
+
+    fn open_with(
+        file_path: &str,
+        key: LuminaKey,
+        trusted_key: Option<&VerifyingKey>,
+    ) -> Result<Self, LuminaFileError> {
+        let file = File::open(file_path)?;
+
+        // The archive must not be written to while it is mapped. The saver
+        // replaces archives with a rename instead of writing into them, so
+        // saving over a mapped archive is fine
+        let mmap = unsafe { Mmap::map(&file)? };
+
+        if (mmap.len() as u64) < HEADER_SIZE {
+            return Err(LuminaFileError::TruncatedHeader);
+        }
+
+        let header = LuminaHeader::read_from(&mmap[..HEADER_SIZE as usize])?
+            .ok_or(LuminaFileError::UnsupportedVersion(LEGACY_VERSION))?;
+
+        let toc_size = check_toc_bounds(&header, mmap.len() as u64)?;
+        let toc_start = header.toc_offset as usize;
+
+        let toc = read_toc(
+            file_path,
+            &header,
+            &mmap[toc_start..toc_start + toc_size],
+            &key,
+            trusted_key,
+        )?;
+
+        Ok(Self {
+            file_path: file_path.to_string(),
+            mmap,
+            toc,
+            key,
+            encrypted: header.is_encrypted(),
+            cache: Mutex::new(EntryCache::new(DEFAULT_CACHE_BUDGET)),
+        })
+    }
+
+    /// Changes how many bytes of decoded entries are kept around, evicting
+    /// entries right away if the cache is now over budget.
+    pub fn set_cache_budget(&self, budget: usize) {
+        let mut cache = self.cache.lock().unwrap();
+        cache.budget = budget;
+        cache.evict();
+    }
+
+    pub fn clear_cache(&self) {
+        let mut cache = self.cache.lock().unwrap();
+        cache.entries.clear();
+        cache.order.clear();
+        cache.used = 0;
+    }
+
+    pub fn get_file_path(&self) -> &str {
+        &self.file_path
+    }
+
+    pub fn get_toc(&self) -> &TableOfContents {
+        &self.toc
+    }
+
+    pub fn contains(&self, entry_path: &str) -> bool {
+        self.toc.find(entry_path).is_some()
+    }
+
+    /// Returns the decoded entry at `directory/file`, decoding it if it isn't cached yet.
+    pub fn get(&self, entry_path: &str) -> Result<Arc<LuminaFile>, LuminaFileError> {
+        if let Some(file) = self.cache.lock().unwrap().get(entry_path) {
+            return Ok(file);
+        }
+
+        let entry = self
+            .toc
+            .find(entry_path)
+            .ok_or_else(|| LuminaFileError::EntryNotFound(entry_path.to_string()))?;
+
+        let size = check_length("entry size", entry.size, MAX_ENTRY_SIZE)?;
+        let start = entry.offset as usize;
+
+        if entry.offset.saturating_add(entry.size) > self.mmap.len() as u64 {
+            return Err(LuminaFileError::TruncatedEntry(entry_path.to_string()));
+        }
+
+        let stored = &self.mmap[start..start + size];
+
+        check_stored(entry_path, entry, stored)?;
+
+        let file = Arc::new(decode_entry(
+            entry_path,
+            entry,
+            stored,
+            &self.key,
+            self.encrypted,
+        )?);
+
+        self.cache.lock().unwrap().insert(entry_path, file.clone());
+
+        Ok(file)
+    }
+
+    /// Lists the `directory/file` paths of every entry.
+    pub fn entries(&self) -> Vec<String> {
+        self.toc
+            .directories
+            .iter()
+            .flat_map(|directory| {
+                directory
+                    .entries
+                    .iter()
+                    .map(move |entry| format!("{}/{}", directory.name, entry.file_name))
+            })
+            .collect()
+    }
+}
+
+impl ArchiveEntries for MappedLoader {
+    fn entry_paths(&self) -> Vec<String> {
+        self.entries()
+    }
+
+    fn get_entry(&self, entry_path: &str) -> Result<Arc<LuminaFile>, LuminaFileError> {
+        self.get(entry_path)
+    }
+}
@@ -0,0 +1,266 @@
+use std::{collections::BTreeSet, fmt};
+
+use serde_json::{Map, Value};
+
+use crate::{
+    id::{format_id, parse_id, EntityId},
+    scene::{SceneError, SceneFile},
+};
+
+/// A value that differs between two scenes.
+///
+/// `path` names the value inside its entity, or inside the scene when
+/// `entity` is `None`, with the keys leading to it joined by `.` (like
+/// `components.transform.translation`). A whole entity added or removed has
+/// an empty path. `None` on either side means the value isn't there.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SceneChange {
+    pub entity: Option<EntityId>,
+    pub path: String,
+    pub old: Option<Value>,
+    pub new: Option<Value>,
+}
+
+/// A value both sides of a merge changed from their common ancestor, in different ways.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MergeConflict {
+    pub entity: Option<EntityId>,
+    pub path: String,
+    pub base: Option<Value>,
+    pub ours: Option<Value>,
+    pub theirs: Option<Value>,
+}
+
+/// Result of [`merge_scenes`]. Conflicting values are left as in `ours`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SceneMerge {
+    pub scene: SceneFile,
+    pub conflicts: Vec<MergeConflict>,
+}
+
+impl fmt::Display for SceneChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sign = match (&self.old, &self.new) {
+            (None, _) => '+',
+            (_, None) => '-',
+            _ => '~',
+        };
+
+        write!(f, "{} {}", sign, location(self.entity, &self.path))?;
+
+        match (&self.old, &self.new) {
+            (Some(old), Some(new)) => write!(f, ": {} -> {}", old, new),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl fmt::Display for MergeConflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: base {}, ours {}, theirs {}",
+            location(self.entity, &self.path),
+            describe(&self.base),
+            describe(&self.ours),
+            describe(&self.theirs)
+        )
+    }
+}
+
+/// Every entity and component value that differs from `old` to `new`.
+/// Entities are matched by id, arrays are compared as a whole.
+pub fn diff_scenes(old: &SceneFile, new: &SceneFile) -> Vec<SceneChange> {
+    let mut changes = Vec::new();
+
+    diff_values(
+        Some(&scene_tree(old)),
+        Some(&scene_tree(new)),
+        &mut Vec::new(),
+        &mut changes,
+    );
+
+    changes
+}
+
+/// Merges the changes `ours` and `theirs` made to `base`, entity by entity
+/// and field by field. A value changed on one side only takes that side's
+/// value, values changed on both sides differently are conflicts.
+pub fn merge_scenes(base: &SceneFile, ours: &SceneFile, theirs: &SceneFile) -> Result<SceneMerge, SceneError> {
+    let mut conflicts = Vec::new();
+
+    let merged = merge_values(
+        Some(&scene_tree(base)),
+        Some(&scene_tree(ours)),
+        Some(&scene_tree(theirs)),
+        &mut Vec::new(),
+        &mut conflicts,
+    )
+    .unwrap_or(Value::Null);
+
+    let scene = SceneFile::from_slice(&serde_json::to_vec(&scene_document(merged)).unwrap())?;
+
+    Ok(SceneMerge { scene, conflicts })
+}
+
+/// The scene as JSON, with its entities keyed by id so they are matched by id.
+fn scene_tree(scene: &SceneFile) -> Value {
+    let mut tree = serde_json::to_value(scene).unwrap();
+
+    let entities: Map<String, Value> = scene
+        .entities
+        .iter()
+        .map(|entity| {
+            let mut entity_value = serde_json::to_value(entity).unwrap();
+            entity_value.as_object_mut().unwrap().remove("id");
+
+            (format_id(entity.id), entity_value)
+        })
+        .collect();
+
+    tree.as_object_mut()
+        .unwrap()
+        .insert("entities".to_string(), Value::Object(entities));
+
+    tree
+}
+
+/// Turns a tree from [`scene_tree`] back into a scene document, entities sorted by id.
+fn scene_document(mut tree: Value) -> Value {
+    let entities = match tree.get_mut("entities").map(Value::take) {
+        Some(Value::Object(entities)) => entities,
+        _ => Map::new(),
+    };
+
+    let mut records: Vec<(EntityId, Value)> = entities
+        .into_iter()
+        .filter_map(|(key, mut entity)| {
+            let id = parse_id(&key)?;
+            entity.as_object_mut()?.insert("id".to_string(), Value::String(key));
+
+            Some((id, entity))
+        })
+        .collect();
+
+    records.sort_by_key(|(id, _)| *id);
+
+    if let Some(tree) = tree.as_object_mut() {
+        tree.insert(
+            "entities".to_string(),
+            Value::Array(records.into_iter().map(|(_, entity)| entity).collect()),
+        );
+    }
+
+    tree
+}
+
+fn diff_values(old: Option<&Value>, new: Option<&Value>, path: &mut Vec<String>, changes: &mut Vec<SceneChange>) {
+    if old == new {
+        return;
+    }
+
+    if let (Some(Value::Object(old)), Some(Value::Object(new))) = (old, new) {
+        for key in keys(old, new) {
+            path.push(key.clone());
+            diff_values(old.get(&key), new.get(&key), path, changes);
+            path.pop();
+        }
+
+        return;
+    }
+
+    let (entity, field) = split_path(path);
+
+    changes.push(SceneChange {
+        entity,
+        path: field,
+        old: old.cloned(),
+        new: new.cloned(),
+    });
+}
+
+fn merge_values(
+    base: Option<&Value>,
+    ours: Option<&Value>,
+    theirs: Option<&Value>,
+    path: &mut Vec<String>,
+    conflicts: &mut Vec<MergeConflict>,
+) -> Option<Value> {
+    if ours == theirs || base == theirs {
+        return ours.cloned();
+    }
+
+    if base == ours {
+        return theirs.cloned();
+    }
+
+    // Objects changed on both sides are merged key by key
+    if let (Some(Value::Object(ours)), Some(Value::Object(theirs)), None | Some(Value::Object(_))) =
+        (ours, theirs, base)
+    {
+        let base = base.and_then(Value::as_object);
+        let mut merged = Map::new();
+
+        for key in keys(ours, theirs) {
+            path.push(key.clone());
+
+            let value = merge_values(
+                base.and_then(|base| base.get(&key)),
+                ours.get(&key),
+                theirs.get(&key),
+                path,
+                conflicts,
+            );
+
+            path.pop();
+
+            if let Some(value) = value {
+                merged.insert(key, value);
+            }
+        }
+
+        return Some(Value::Object(merged));
+    }
+
+    let (entity, field) = split_path(path);
+
+    conflicts.push(MergeConflict {
+        entity,
+        path: field,
+        base: base.cloned(),
+        ours: ours.cloned(),
+        theirs: theirs.cloned(),
+    });
+
+    ours.cloned()
+}
+
+fn keys(a: &Map<String, Value>, b: &Map<String, Value>) -> BTreeSet<String> {
+    a.keys().chain(b.keys()).cloned().collect()
+}
+
+/// Splits a tree path into the entity it is in, if any, and the path inside it.
+fn split_path(path: &[String]) -> (Option<EntityId>, String) {
+    match path {
+        [entities, id, rest @ ..] if entities == "entities" => match parse_id(id) {
+            Some(id) => (Some(id), rest.join(".")),
+            None => (None, path.join(".")),
+        },
+        _ => (None, path.join(".")),
+    }
+}
+
+fn location(entity: Option<EntityId>, path: &str) -> String {
+    match (entity, path.is_empty()) {
+        (Some(entity), true) => format!("entity {}", format_id(entity)),
+        (Some(entity), false) => format!("entity {} {}", format_id(entity), path),
+        (None, _) => path.to_string(),
+    }
+}
+
+fn describe(value: &Option<Value>) -> String {
+    match value {
+        Some(value) => value.to_string(),
+        None => "(none)".to_string(),
+    }
+}
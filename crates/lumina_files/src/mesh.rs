@@ -0,0 +1,409 @@
+use std::{
+    collections::BTreeMap,
+    io::{self, Read},
+    sync::Arc,
+};
+
+use crate::{
+    compression::{Compression, DEFAULT_ZSTD_LEVEL},
+    error::{check_length, LuminaFileError},
+    file::{LuminaFile, LuminaFileType},
+    format::{read_header_bytes, read_u16, read_u32, read_u8},
+    integrity::hash,
+    loader::ArchiveEntries,
+    saver::Saver,
+    scene::{MeshRecord, VertexRecord},
+};
+
+/// Directory meshes are saved in, as `meshes/<id>.mesh`.
+pub const MESH_DIRECTORY: &str = "meshes";
+
+pub const MESH_MAGIC: &[u8; 4] = b"LMSH";
+pub const MESH_VERSION: u8 = 1;
+
+/// Size of a float vertex: position(12) + normal(12) + uv(8).
+const FLOAT_VERTEX_SIZE: u64 = 32;
+/// Size of a quantized vertex: position(6) + normal(6) + uv(4).
+const QUANTIZED_VERTEX_SIZE: u64 = 16;
+
+/// How vertices are written in a mesh blob.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MeshEncoding {
+    /// 32 bit floats, the exact vertices.
+    #[default]
+    Float,
+    /// Positions and uvs as 16 bit steps inside the mesh bounds and normals as
+    /// 16 bit signed values, halving the size at the cost of some precision.
+    Quantized,
+}
+
+impl MeshEncoding {
+    fn to_byte(&self) -> u8 {
+        match self {
+            MeshEncoding::Float => 0,
+            MeshEncoding::Quantized => 1,
+        }
+    }
+
+    fn from_byte(encoding_byte: u8) -> Result<Self, LuminaFileError> {
+        match encoding_byte {
+            0 => Ok(MeshEncoding::Float),
+            1 => Ok(MeshEncoding::Quantized),
+            _ => Err(LuminaFileError::InvalidMesh(format!(
+                "unknown encoding byte 0x{:02x}",
+                encoding_byte
+            ))),
+        }
+    }
+}
+
+/// Writes a mesh as a binary blob:
+///
+/// magic(4) + version(1) + encoding(1) + index size(1) + reserved(1) +
+/// vertex count(4) + index count(4), then for quantized meshes the position
+/// and uv bounds as floats, then the vertices and the indices. Indices are
+/// written on 16 bits when every vertex can be reached with them.
+pub fn encode_mesh(mesh: &MeshRecord, encoding: MeshEncoding) -> Vec<u8> {
+    let index_size: u8 = if mesh.vertices.len() <= u16::MAX as usize + 1 { 2 } else { 4 };
+
+    let mut bytes = Vec::with_capacity(
+        16 + mesh.vertices.len() * FLOAT_VERTEX_SIZE as usize + mesh.indices.len() * index_size as usize,
+    );
+
+    bytes.extend_from_slice(MESH_MAGIC);
+    bytes.push(MESH_VERSION);
+    bytes.push(encoding.to_byte());
+    bytes.push(index_size);
+    bytes.push(0);
+    bytes.extend_from_slice(&(mesh.vertices.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(&(mesh.indices.len() as u32).to_le_bytes());
+
+    match encoding {
+        MeshEncoding::Float => {
+            for vertex in mesh.vertices.iter() {
+                for value in vertex.position.iter().chain(vertex.normal.iter()).chain(vertex.uv.iter()) {
+                    bytes.extend_from_slice(&value.to_le_bytes());
+                }
+            }
+        }
+        MeshEncoding::Quantized => {
+            let (position_min, position_max) = bounds(mesh.vertices.iter().map(|vertex| vertex.position));
+            let (uv_min, uv_max) = bounds(mesh.vertices.iter().map(|vertex| vertex.uv));
+
+            for value in position_min.iter().chain(&position_max).chain(&uv_min).chain(&uv_max) {
+                bytes.extend_from_slice(&value.to_le_bytes());
+            }
+
+            for vertex in mesh.vertices.iter() {
+                for axis in 0..3 {
+                    let step = quantize(vertex.position[axis], position_min[axis], position_max[axis]);
+                    bytes.extend_from_slice(&step.to_le_bytes());
+                }
+
+                for axis in 0..3 {
+                    let normal = (vertex.normal[axis].clamp(-1.0, 1.0) * i16::MAX as f32).round() as i16;
+                    bytes.extend_from_slice(&normal.to_le_bytes());
+                }
+
+                for axis in 0..2 {
+                    let step = quantize(vertex.uv[axis], uv_min[axis], uv_max[axis]);
+                    bytes.extend_from_slice(&step.to_le_bytes());
+                }
+            }
+        }
+    }
+
+    for indice in mesh.indices.iter() {
+        match index_size {
+            2 => bytes.extend_from_slice(&(*indice as u16).to_le_bytes()),
+            _ => bytes.extend_from_slice(&indice.to_le_bytes()),
+        }
+    }
+
+    bytes
+}
+
+/// Reads a blob written by [`encode_mesh`], checking that every index points to a vertex.
+pub fn decode_mesh(bytes: &[u8]) -> Result<MeshRecord, LuminaFileError> {
+    let mut cursor = io::Cursor::new(bytes);
+
+    let mut magic = [0u8; 4];
+    read_header_bytes(&mut cursor, &mut magic)?;
+
+    if &magic != MESH_MAGIC {
+        return Err(LuminaFileError::InvalidMesh("not a mesh".to_string()));
+    }
+
+    let version = read_u8(&mut cursor)?;
+
+    if version != MESH_VERSION {
+        return Err(LuminaFileError::InvalidMesh(format!("unsupported version {}", version)));
+    }
+
+    let encoding = MeshEncoding::from_byte(read_u8(&mut cursor)?)?;
+    let index_size = read_u8(&mut cursor)?;
+    let _reserved = read_u8(&mut cursor)?;
+
+    if index_size != 2 && index_size != 4 {
+        return Err(LuminaFileError::InvalidMesh(format!("invalid index size {}", index_size)));
+    }
+
+    let vertex_size = match encoding {
+        MeshEncoding::Float => FLOAT_VERTEX_SIZE,
+        MeshEncoding::Quantized => QUANTIZED_VERTEX_SIZE,
+    };
+
+    // Counts are checked against what is left so a corrupted blob can't make us allocate more than its size
+    let remaining = |cursor: &io::Cursor<&[u8]>| bytes.len() as u64 - cursor.position();
+
+    let vertex_count = read_u32(&mut cursor)? as u64;
+    let index_count = read_u32(&mut cursor)? as u64;
+    check_length("mesh vertices", vertex_count * vertex_size, remaining(&cursor))?;
+    check_length(
+        "mesh indices",
+        index_count * index_size as u64,
+        remaining(&cursor) - vertex_count * vertex_size,
+    )?;
+
+    let mut vertices = Vec::with_capacity(vertex_count as usize);
+
+    match encoding {
+        MeshEncoding::Float => {
+            for _ in 0..vertex_count {
+                vertices.push(VertexRecord {
+                    position: read_f32s(&mut cursor)?,
+                    normal: read_f32s(&mut cursor)?,
+                    uv: read_f32s(&mut cursor)?,
+                });
+            }
+        }
+        MeshEncoding::Quantized => {
+            let position_min: [f32; 3] = read_f32s(&mut cursor)?;
+            let position_max: [f32; 3] = read_f32s(&mut cursor)?;
+            let uv_min: [f32; 2] = read_f32s(&mut cursor)?;
+            let uv_max: [f32; 2] = read_f32s(&mut cursor)?;
+
+            for _ in 0..vertex_count {
+                let mut vertex = VertexRecord {
+                    position: [0.0; 3],
+                    normal: [0.0; 3],
+                    uv: [0.0; 2],
+                };
+
+                for axis in 0..3 {
+                    vertex.position[axis] =
+                        dequantize(read_u16(&mut cursor)?, position_min[axis], position_max[axis]);
+                }
+
+                for axis in 0..3 {
+                    vertex.normal[axis] = read_u16(&mut cursor)? as i16 as f32 / i16::MAX as f32;
+                }
+
+                for axis in 0..2 {
+                    vertex.uv[axis] = dequantize(read_u16(&mut cursor)?, uv_min[axis], uv_max[axis]);
+                }
+
+                vertices.push(vertex);
+            }
+        }
+    }
+
+    let mut indices = Vec::with_capacity(index_count as usize);
+
+    for position in 0..index_count {
+        let indice = match index_size {
+            2 => read_u16(&mut cursor)? as u32,
+            _ => read_u32(&mut cursor)?,
+        };
+
+        if indice as u64 >= vertex_count {
+            return Err(LuminaFileError::InvalidMesh(format!(
+                "index {} at {} out of {} vertices",
+                indice, position, vertex_count
+            )));
+        }
+
+        indices.push(indice);
+    }
+
+    Ok(MeshRecord { vertices, indices })
+}
+
+/// Name of the archive entry holding the mesh `id`.
+pub fn mesh_entry(id: &str) -> String {
+    format!("{}/{}.mesh", MESH_DIRECTORY, id)
+}
+
+/// Id of the mesh held by the archive entry `entry_path`, if it is a mesh.
+pub fn mesh_id_of_entry(entry_path: &str) -> Option<&str> {
+    entry_path
+        .strip_prefix(MESH_DIRECTORY)
+        .and_then(|file| file.strip_prefix('/'))
+        .and_then(|file| file.strip_suffix(".mesh"))
+}
+
+/// Encoded meshes of a scene, keyed by an id derived from their content so
+/// identical meshes are only stored once.
+#[derive(Debug, Clone, Default)]
+pub struct MeshStore {
+    encoding: MeshEncoding,
+    meshes: BTreeMap<String, Vec<u8>>,
+    /// Meshes decoded ahead of time by [`MeshStore::decode`].
+    decoded: BTreeMap<String, Arc<MeshRecord>>,
+}
+
+impl MeshStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets how meshes inserted from now on are encoded.
+    pub fn set_encoding(&mut self, encoding: MeshEncoding) {
+        self.encoding = encoding;
+    }
+
+    /// Reads every mesh saved in the `meshes` directory of an opened archive.
+    pub fn from_loader<L: ArchiveEntries + ?Sized>(loader: &L) -> Result<Self, LuminaFileError> {
+        let mut store = Self::new();
+
+        for entry_path in loader.entry_paths() {
+            if let Some(id) = mesh_id_of_entry(&entry_path) {
+                store.insert_encoded(id, loader.get_entry(&entry_path)?.file_content.clone());
+            }
+        }
+
+        Ok(store)
+    }
+
+    /// Encodes `mesh` and returns the id the scene references it with.
+    pub fn insert(&mut self, mesh: &MeshRecord) -> String {
+        let bytes = encode_mesh(mesh, self.encoding);
+
+        let id: String = hash(&bytes)[..16].iter().map(|byte| format!("{:02x}", byte)).collect();
+        self.meshes.entry(id.clone()).or_insert(bytes);
+
+        id
+    }
+
+    pub fn insert_encoded(&mut self, id: &str, bytes: Vec<u8>) {
+        self.decoded.remove(id);
+        self.meshes.insert(id.to_string(), bytes);
+    }
+
+    /// Adds the meshes of `other` that aren't in the store yet.
+    pub fn extend(&mut self, other: &MeshStore) {
+        for (id, bytes) in other.meshes.iter() {
+            if !self.meshes.contains_key(id) {
+                self.meshes.insert(id.clone(), bytes.clone());
+
+                if let Some(mesh) = other.decoded.get(id) {
+                    self.decoded.insert(id.clone(), Arc::clone(mesh));
+                }
+            }
+        }
+    }
+
+    pub fn contains(&self, id: &str) -> bool {
+        self.meshes.contains_key(id)
+    }
+
+    /// Decodes the mesh `id` now, typically on a loading thread, so that
+    /// [`MeshStore::get`] only has to copy it.
+    pub fn decode(&mut self, id: &str) -> Result<(), LuminaFileError> {
+        if !self.decoded.contains_key(id) {
+            let mesh = self.get(id)?;
+            self.decoded.insert(id.to_string(), Arc::new(mesh));
+        }
+
+        Ok(())
+    }
+
+    pub fn get(&self, id: &str) -> Result<MeshRecord, LuminaFileError> {
+        if let Some(mesh) = self.decoded.get(id) {
+            return Ok(mesh.as_ref().clone());
+        }
+
+        let bytes = self
+            .meshes
+            .get(id)
+            .ok_or_else(|| LuminaFileError::EntryNotFound(mesh_entry(id)))?;
+
+        decode_mesh(bytes).map_err(|err| match err {
+            LuminaFileError::InvalidMesh(message) => {
+                LuminaFileError::InvalidMesh(format!("{}: {}", mesh_entry(id), message))
+            }
+            err => err,
+        })
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &[u8])> {
+        self.meshes.iter().map(|(id, bytes)| (id.as_str(), bytes.as_slice()))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.meshes.is_empty()
+    }
+
+    /// Adds every mesh to the `meshes` directory of `saver`.
+    pub fn write_to(&self, saver: &mut Saver) {
+        if self.meshes.is_empty() {
+            return;
+        }
+
+        if !saver.has_directory(MESH_DIRECTORY) {
+            saver.create_compressed_directory(MESH_DIRECTORY, Compression::Zstd(DEFAULT_ZSTD_LEVEL));
+        }
+
+        for (id, bytes) in self.meshes.iter() {
+            saver.insert_file_into_directory(
+                MESH_DIRECTORY,
+                LuminaFile::new(LuminaFileType::Mesh, format!("{}.mesh", id), bytes.clone()),
+            );
+        }
+    }
+}
+
+fn bounds<const N: usize>(values: impl Iterator<Item = [f32; N]>) -> ([f32; N], [f32; N]) {
+    let mut min = [f32::MAX; N];
+    let mut max = [f32::MIN; N];
+    let mut empty = true;
+
+    for value in values {
+        empty = false;
+
+        for axis in 0..N {
+            min[axis] = min[axis].min(value[axis]);
+            max[axis] = max[axis].max(value[axis]);
+        }
+    }
+
+    if empty {
+        return ([0.0; N], [0.0; N]);
+    }
+
+    (min, max)
+}
+
+fn quantize(value: f32, min: f32, max: f32) -> u16 {
+    if max <= min {
+        return 0;
+    }
+
+    ((value - min) / (max - min) * u16::MAX as f32).round() as u16
+}
+
+fn dequantize(step: u16, min: f32, max: f32) -> f32 {
+    min + step as f32 / u16::MAX as f32 * (max - min)
+}
+
+fn read_f32s<R: Read, const N: usize>(reader: &mut R) -> Result<[f32; N], LuminaFileError> {
+    let mut values = [0.0; N];
+
+    for value in values.iter_mut() {
+        let mut bytes = [0u8; 4];
+        read_header_bytes(reader, &mut bytes)?;
+        *value = f32::from_le_bytes(bytes);
+    }
+
+    Ok(values)
+}
@@ -0,0 +1,210 @@
+use std::collections::BTreeMap;
+
+use serde_json::{Map, Value};
+
+use crate::{
+    id::{format_id, EntityId},
+    scene::{EntityRecord, SceneError},
+};
+
+/// Version of the scene documents written by this build.
+///
+/// Bumping it means adding a [`Migration`] to [`MIGRATIONS`] upgrading the
+/// previous version, so that scenes saved with it still load.
+pub const SCENE_VERSION: u32 = 4;
+
+/// Upgrades a scene document from version `from` to `from + 1`.
+pub struct Migration {
+    pub from: u32,
+    pub description: &'static str,
+    pub migrate: fn(&mut Map<String, Value>) -> Result<(), SceneError>,
+}
+
+/// Every migration, in order. Version 1 is the first scene layout, with one
+/// array per component type.
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        from: 1,
+        description: "group the per component arrays into entities",
+        migrate: entities_from_component_arrays,
+    },
+    Migration {
+        from: 2,
+        description: "rename the translation of transforms from `transform` to `translation`",
+        migrate: rename_transform_translation,
+    },
+    Migration {
+        from: 3,
+        description: "write entity ids as UUIDs",
+        migrate: uuid_entity_ids,
+    },
+];
+
+/// Version of a document. Documents written before versions were saved are
+/// told apart by their layout.
+pub fn document_version(object: &Map<String, Value>) -> Result<u32, SceneError> {
+    match object.get("version") {
+        Some(version) => version
+            .as_u64()
+            .filter(|version| *version >= 1 && *version <= u32::MAX as u64)
+            .map(|version| version as u32)
+            .ok_or_else(|| invalid(None, "version".to_string(), "expected a version number")),
+        None if object.contains_key("game_objects") => Ok(1),
+        None => Ok(2),
+    }
+}
+
+/// Runs every migration needed to bring `object` to [`SCENE_VERSION`] and
+/// returns the version it was saved with.
+pub fn migrate(object: &mut Map<String, Value>) -> Result<u32, SceneError> {
+    let saved_version = document_version(object)?;
+
+    if saved_version > SCENE_VERSION {
+        return Err(SceneError::UnsupportedVersion(saved_version));
+    }
+
+    for migration in MIGRATIONS.iter().filter(|migration| migration.from >= saved_version) {
+        (migration.migrate)(object)?;
+    }
+
+    object.insert("version".to_string(), Value::from(SCENE_VERSION));
+
+    Ok(saved_version)
+}
+
+fn invalid(entity: Option<EntityId>, field: String, message: &str) -> SceneError {
+    SceneError::InvalidField {
+        entity,
+        field,
+        message: message.to_string(),
+    }
+}
+
+/// Version 1 to 2: `game_objects` listed the entity ids, and `transforms`,
+/// `models` and `lights` held one record per component with the id of its entity.
+fn entities_from_component_arrays(object: &mut Map<String, Value>) -> Result<(), SceneError> {
+    let game_objects: Vec<u32> = serde_json::from_value(object.remove("game_objects").unwrap_or(Value::Null))
+        .map_err(|e| invalid(None, "game_objects".to_string(), &e.to_string()))?;
+    let game_objects: Vec<EntityId> = game_objects.into_iter().map(EntityId::from).collect();
+
+    let mut entities: BTreeMap<EntityId, EntityRecord> = BTreeMap::new();
+
+    for id in game_objects.iter() {
+        entities.insert(
+            *id,
+            EntityRecord {
+                id: *id,
+                prefab: None,
+                components: BTreeMap::new(),
+            },
+        );
+    }
+
+    for (list, component_name) in [("transforms", "transform"), ("models", "model"), ("lights", "light")] {
+        let records = match object.remove(list) {
+            Some(Value::Array(records)) => records,
+            Some(Value::Null) | None => Vec::new(),
+            Some(_) => return Err(invalid(None, list.to_string(), "expected an array")),
+        };
+
+        for (index, record) in records.into_iter().enumerate() {
+            let field = format!("{}[{}]", list, index);
+
+            let mut record = match record {
+                Value::Object(record) => record,
+                _ => return Err(invalid(None, field, "expected an object")),
+            };
+
+            let id = record
+                .remove("id")
+                .and_then(|id| id.as_u64())
+                .filter(|id| *id <= u32::MAX as u64)
+                .ok_or_else(|| invalid(None, field.clone() + ".id", "expected an entity id"))?
+                as EntityId;
+
+            let entity = entities
+                .get_mut(&id)
+                .ok_or_else(|| invalid(Some(id), field.clone() + ".id", "entity isn't listed in game_objects"))?;
+
+            if entity
+                .components
+                .insert(component_name.to_string(), Value::Object(record))
+                .is_some()
+            {
+                return Err(invalid(Some(id), field + ".id", "entity already has this component"));
+            }
+        }
+    }
+
+    // Computed from the lights when loading since version 2
+    object.remove("light_count");
+
+    // Entities keep the order of game_objects, duplicated ids are reported when the scene is validated
+    let mut ordered = Vec::with_capacity(game_objects.len());
+
+    for id in game_objects {
+        match entities.remove(&id) {
+            Some(entity) => ordered.push(entity),
+            None => ordered.push(EntityRecord {
+                id,
+                prefab: None,
+                components: BTreeMap::new(),
+            }),
+        }
+    }
+
+    object.insert(
+        "entities".to_string(),
+        serde_json::to_value(ordered).unwrap(),
+    );
+
+    Ok(())
+}
+
+/// Version 2 to 3: the translation of transforms was saved as `transform`.
+fn rename_transform_translation(object: &mut Map<String, Value>) -> Result<(), SceneError> {
+    let entities = match object.get_mut("entities") {
+        Some(Value::Array(entities)) => entities,
+        _ => return Ok(()),
+    };
+
+    for entity in entities.iter_mut() {
+        let transform = match entity.pointer_mut("/components/transform") {
+            Some(Value::Object(transform)) => transform,
+            _ => continue,
+        };
+
+        if let Some(translation) = transform.remove("transform") {
+            transform.insert("translation".to_string(), translation);
+        }
+    }
+
+    Ok(())
+}
+
+/// Version 3 to 4: entity ids were `u32` numbers, they are now UUIDs saved
+/// as strings (see [`crate::id`]). Old ids keep their value.
+fn uuid_entity_ids(object: &mut Map<String, Value>) -> Result<(), SceneError> {
+    let entities = match object.get_mut("entities") {
+        Some(Value::Array(entities)) => entities,
+        _ => return Ok(()),
+    };
+
+    for (index, entity) in entities.iter_mut().enumerate() {
+        for (pointer, field) in [("/id", "id"), ("/prefab/instance", "prefab.instance")] {
+            let id = match entity.pointer_mut(pointer) {
+                Some(id) if id.is_number() => id,
+                _ => continue,
+            };
+
+            let number = id
+                .as_u64()
+                .filter(|number| *number <= u32::MAX as u64)
+                .ok_or_else(|| invalid(None, format!("entities[{}].{}", index, field), "expected an entity id"))?;
+
+            *id = Value::String(format_id(number as EntityId));
+        }
+    }
+
+    Ok(())
+}
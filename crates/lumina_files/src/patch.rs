@@ -0,0 +1,226 @@
+use std::{
+    collections::HashMap,
+    io,
+};
+
+use crate::{
+    compression::{Compression, DEFAULT_ZSTD_LEVEL},
+    error::{check_length, LuminaFileError},
+    file::{LuminaDirectory, LuminaFile},
+    format::{read_header_bytes, read_string, read_u32, read_u8, write_string, MAX_ENTRY_COUNT},
+    integrity::{content_hashes, HASH_SIZE},
+    loader::Loader,
+    saver::Saver,
+};
+
+/// Directory reserved for the patch manifest.
+pub const PATCH_DIRECTORY: &str = ".patch";
+pub const PATCH_MANIFEST_NAME: &str = "manifest";
+pub const PATCH_MANIFEST: &str = ".patch/manifest";
+
+/// Manifest of a patch archive, an archive shipping only what changed since a base archive.
+///
+/// A patch is a regular v2 archive with [`crate::format::FLAG_PATCH`] set in
+/// its header. It holds the added and changed entries, plus this manifest in
+/// [`PATCH_MANIFEST`] naming the base archive, the patch it follows and the
+/// deleted entries. Patches are mounted in order on top of their base with
+/// [`Loader::mount_patch`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct PatchManifest {
+    /// Header digest of the archive the patch applies to.
+    pub base_digest: [u8; HASH_SIZE],
+    /// Header digest of the last patch mounted on the base when the patch was
+    /// made, `None` for the first patch of a base.
+    pub previous_digest: Option<[u8; HASH_SIZE]>,
+    /// `directory/file` paths of the entries removed by the patch.
+    pub deleted: Vec<String>,
+}
+
+impl PatchManifest {
+    pub fn new(base_digest: [u8; HASH_SIZE], previous_digest: Option<[u8; HASH_SIZE]>) -> Self {
+        Self {
+            base_digest,
+            previous_digest,
+            deleted: Vec::new(),
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+
+        bytes.extend_from_slice(&self.base_digest);
+
+        match &self.previous_digest {
+            Some(previous_digest) => {
+                bytes.push(1);
+                bytes.extend_from_slice(previous_digest);
+            }
+            None => bytes.push(0),
+        }
+
+        bytes.extend_from_slice(&(self.deleted.len() as u32).to_le_bytes());
+
+        for entry_path in self.deleted.iter() {
+            write_string(&mut bytes, entry_path);
+        }
+
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, LuminaFileError> {
+        let mut cursor = io::Cursor::new(bytes);
+
+        let mut base_digest = [0u8; HASH_SIZE];
+        read_header_bytes(&mut cursor, &mut base_digest)?;
+
+        let previous_digest = match read_u8(&mut cursor)? {
+            0 => None,
+            1 => {
+                let mut previous_digest = [0u8; HASH_SIZE];
+                read_header_bytes(&mut cursor, &mut previous_digest)?;
+                Some(previous_digest)
+            }
+            byte => {
+                return Err(LuminaFileError::InvalidPatch(format!(
+                    "unknown previous patch marker {}",
+                    byte
+                )))
+            }
+        };
+
+        let deleted_count =
+            check_length("deleted entry count", read_u32(&mut cursor)? as u64, MAX_ENTRY_COUNT)?;
+        let mut deleted = Vec::new();
+
+        for _ in 0..deleted_count {
+            deleted.push(read_string(&mut cursor)?);
+        }
+
+        Ok(Self {
+            base_digest,
+            previous_digest,
+            deleted,
+        })
+    }
+}
+
+/// Entries added, changed and deleted by a patch, as `directory/file` paths.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PatchSummary {
+    pub added: Vec<String>,
+    pub changed: Vec<String>,
+    pub deleted: Vec<String>,
+}
+
+impl PatchSummary {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.changed.is_empty() && self.deleted.is_empty()
+    }
+}
+
+/// Builds a patch turning `base` into `updated`, ready to be written with
+/// [`Saver::write_archive`] once its key is set.
+///
+/// `base` must be a v2 archive. Patches already mounted on it are taken into
+/// account, so each new patch only holds what changed since the previous one
+/// and has to be mounted after it.
+pub fn create_patch(base: &Loader, updated: &Loader) -> Result<(Saver, PatchSummary), LuminaFileError> {
+    let base_digest = base
+        .get_digest()
+        .ok_or_else(|| LuminaFileError::InvalidPatch("legacy archives can't be patched".to_string()))?;
+
+    let base_hashes = content_hashes(base)?;
+    let updated_hashes = content_hashes(updated)?;
+
+    let mut manifest = PatchManifest::new(base_digest, base.last_patch_digest());
+    let mut summary = PatchSummary::default();
+    let mut saver = Saver::new();
+
+    for (entry_path, (file_type, content_hash)) in updated_hashes.iter() {
+        match base_hashes.get(entry_path) {
+            None => summary.added.push(entry_path.clone()),
+            Some(base_hash) if base_hash != &(*file_type, *content_hash) => {
+                summary.changed.push(entry_path.clone())
+            }
+            Some(_) => continue,
+        }
+
+        let (directory_name, _) = entry_path.split_once('/').unwrap();
+
+        if !saver.has_directory(directory_name) {
+            saver.create_compressed_directory(directory_name, directory_compression(updated, directory_name));
+        }
+
+        saver.insert_file_into_directory(directory_name, updated.load_entry(entry_path)?);
+    }
+
+    for entry_path in base_hashes.keys() {
+        if !updated_hashes.contains_key(entry_path) {
+            summary.deleted.push(entry_path.clone());
+        }
+    }
+
+    manifest.deleted = summary.deleted.clone();
+    saver.set_patch(&manifest);
+
+    Ok((saver, summary))
+}
+
+/// Compresses a patch directory if the updated archive compressed any of its entries.
+fn directory_compression(updated: &Loader, directory_name: &str) -> Compression {
+    let compressed = updated
+        .get_toc()
+        .and_then(|toc| toc.directories.iter().find(|directory| directory.name == directory_name))
+        .map(|directory| {
+            directory
+                .entries
+                .iter()
+                .any(|entry| entry.compression != Compression::None)
+        })
+        .unwrap_or(false);
+
+    if compressed {
+        Compression::Zstd(DEFAULT_ZSTD_LEVEL)
+    } else {
+        Compression::None
+    }
+}
+
+/// Whether `entry_path` is part of the patch bookkeeping rather than of the patched content.
+pub(crate) fn is_patch_entry(entry_path: &str) -> bool {
+    entry_path.split_once('/').map(|(directory_name, _)| directory_name) == Some(PATCH_DIRECTORY)
+}
+
+/// Applies a patch to fully loaded directories, the way [`Loader::load_file`] fills them.
+pub(crate) fn apply_to_directories(
+    directories: &mut HashMap<String, LuminaDirectory>,
+    deleted: &[String],
+    files: Vec<(String, LuminaFile)>,
+) {
+    for entry_path in deleted.iter() {
+        if let Some((directory_name, file_name)) = entry_path.split_once('/') {
+            if let Some(directory) = directories.get_mut(directory_name) {
+                directory.files.retain(|file| file.file_name != file_name);
+
+                if directory.files.is_empty() {
+                    directories.remove(directory_name);
+                }
+            }
+        }
+    }
+
+    for (directory_name, file) in files {
+        let directory = directories
+            .entry(directory_name.clone())
+            .or_insert_with(|| LuminaDirectory::new(&directory_name));
+
+        match directory
+            .files
+            .iter_mut()
+            .find(|existing| existing.file_name == file.file_name)
+        {
+            Some(existing) => *existing = file,
+            None => directory.files.push(file),
+        }
+    }
+}
@@ -0,0 +1,400 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    sync::Arc,
+};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::{
+    asset::AssetStore,
+    compression::{Compression, DEFAULT_ZSTD_LEVEL},
+    error::LuminaFileError,
+    file::{LuminaFile, LuminaFileType},
+    id::EntityId,
+    loader::ArchiveEntries,
+    mesh::MeshStore,
+    migration::SCENE_VERSION,
+    saver::Saver,
+    scene::{read_record, EntityRecord, SceneError},
+};
+
+/// Directory prefabs are saved in, as `prefabs/<name>.json`.
+pub const PREFAB_DIRECTORY: &str = "prefabs";
+
+/// Name of the archive entry holding the prefab `name`.
+pub fn prefab_entry(name: &str) -> String {
+    format!("{}/{}.json", PREFAB_DIRECTORY, name)
+}
+
+/// Name of the prefab held by the archive entry `entry_path`, if it is a prefab.
+pub fn prefab_name_of_entry(entry_path: &str) -> Option<&str> {
+    entry_path
+        .strip_prefix(PREFAB_DIRECTORY)
+        .and_then(|file| file.strip_prefix('/'))
+        .and_then(|file| file.strip_suffix(".json"))
+}
+
+/// A reusable entity template: the components of a root entity plus its child
+/// entities, written with the same component names as scenes.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PrefabFile {
+    pub version: u32,
+    pub name: String,
+    pub root: PrefabNode,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PrefabNode {
+    /// Name of the child inside its parent, empty for the root.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub name: String,
+    #[serde(default)]
+    pub components: BTreeMap<String, Value>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub children: Vec<PrefabNode>,
+}
+
+/// Marks a scene entity as part of a prefab instance. The components of such
+/// an entity only hold what the instance overrides, see [`diff_patch`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PrefabLink {
+    pub prefab: String,
+    /// Path of the entity inside the prefab, child names joined with `/`, empty for the root.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub path: String,
+    /// Id of the root entity of the instance.
+    #[serde(with = "crate::id")]
+    pub instance: EntityId,
+}
+
+impl PrefabFile {
+    pub fn new(name: &str) -> Self {
+        Self {
+            version: SCENE_VERSION,
+            name: name.to_string(),
+            root: PrefabNode::default(),
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        serde_json::to_vec_pretty(self).unwrap()
+    }
+
+    pub fn from_slice(bytes: &[u8]) -> Result<Self, SceneError> {
+        let document: Value =
+            serde_json::from_slice(bytes).map_err(|e| SceneError::Syntax(e.to_string()))?;
+
+        let prefab: PrefabFile = read_record(&document)?;
+
+        if prefab.version > SCENE_VERSION {
+            return Err(SceneError::UnsupportedVersion(prefab.version));
+        }
+
+        prefab.validate()?;
+
+        Ok(prefab)
+    }
+
+    /// Checks that every child has a name, unique among its siblings, that can be used in a path.
+    pub fn validate(&self) -> Result<(), SceneError> {
+        fn check(node: &PrefabNode, field: &str) -> Result<(), SceneError> {
+            let mut names = HashSet::new();
+
+            for (index, child) in node.children.iter().enumerate() {
+                let child_field = format!("{}.children[{}]", field, index);
+
+                if child.name.is_empty() || child.name.contains('/') {
+                    return Err(invalid(child_field + ".name", "expected a name without '/'"));
+                }
+
+                if !names.insert(child.name.as_str()) {
+                    return Err(invalid(child_field + ".name", "duplicate child name"));
+                }
+
+                check(child, &child_field)?;
+            }
+
+            Ok(())
+        }
+
+        check(&self.root, "root")
+    }
+
+    /// Node at `path`, see [`PrefabLink::path`].
+    pub fn find(&self, path: &str) -> Option<&PrefabNode> {
+        let mut node = &self.root;
+
+        for name in path.split('/').filter(|name| !name.is_empty()) {
+            node = node.children.iter().find(|child| child.name == name)?;
+        }
+
+        Some(node)
+    }
+
+    /// Every node along with its path, parents before their children.
+    pub fn nodes(&self) -> Vec<(String, &PrefabNode)> {
+        fn walk<'a>(node: &'a PrefabNode, path: String, nodes: &mut Vec<(String, &'a PrefabNode)>) {
+            nodes.push((path.clone(), node));
+
+            for child in node.children.iter() {
+                let child_path = match path.is_empty() {
+                    true => child.name.clone(),
+                    false => format!("{}/{}", path, child.name),
+                };
+
+                walk(child, child_path, nodes);
+            }
+        }
+
+        let mut nodes = Vec::new();
+        walk(&self.root, String::new(), &mut nodes);
+
+        nodes
+    }
+}
+
+/// The prefabs of a scene, and the meshes and textures their models use.
+#[derive(Debug, Clone, Default)]
+pub struct PrefabLibrary {
+    prefabs: BTreeMap<String, PrefabFile>,
+    pub meshes: MeshStore,
+    /// Textures packed with the prefabs, by the entry name their models refer to them with.
+    pub textures: AssetStore,
+}
+
+impl PrefabLibrary {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reads every prefab saved in the `prefabs` directory of an opened archive,
+    /// keeping the meshes of `meshes` they refer to.
+    pub fn from_loader<L: ArchiveEntries + ?Sized>(
+        loader: &L,
+        meshes: &MeshStore,
+    ) -> Result<Self, LuminaFileError> {
+        let mut library = Self::new();
+
+        for entry_path in loader.entry_paths() {
+            if prefab_name_of_entry(&entry_path).is_some() {
+                let prefab = PrefabFile::from_slice(&loader.get_entry(&entry_path)?.file_content)?;
+                library.insert(prefab);
+            }
+        }
+
+        library.keep_meshes_from(meshes);
+
+        Ok(library)
+    }
+
+    /// Copies the meshes of `meshes` referenced by a prefab. Mesh ids are
+    /// content hashes, so any string of a prefab matching one is a reference to it.
+    pub fn keep_meshes_from(&mut self, meshes: &MeshStore) {
+        let strings = strings_of(&self.prefabs);
+
+        for (id, bytes) in meshes.iter() {
+            if strings.contains(id) {
+                self.meshes.insert_encoded(id, bytes.to_vec());
+            }
+        }
+    }
+
+    /// Copies the packed textures of `textures`, by entry name, referenced by a prefab.
+    pub fn keep_textures_from(&mut self, textures: &HashMap<String, Arc<LuminaFile>>) {
+        let strings = strings_of(&self.prefabs);
+
+        for (entry_name, file) in textures.iter() {
+            if strings.contains(entry_name.as_str()) {
+                self.textures.insert(entry_name, file.as_ref().clone());
+            }
+        }
+    }
+
+    /// Adds or replaces a prefab.
+    pub fn insert(&mut self, prefab: PrefabFile) {
+        self.prefabs.insert(prefab.name.clone(), prefab);
+    }
+
+    /// Adds every prefab of `other`, replacing the ones with the same name,
+    /// and their meshes and textures.
+    pub fn merge(&mut self, other: PrefabLibrary) {
+        self.meshes.extend(&other.meshes);
+        self.textures.merge(other.textures);
+        self.prefabs.extend(other.prefabs);
+    }
+
+    pub fn get(&self, name: &str) -> Option<&PrefabFile> {
+        self.prefabs.get(name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &PrefabFile> {
+        self.prefabs.values()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.prefabs.is_empty()
+    }
+
+    /// Gives the full components of a prefab instance's entity, its prefab
+    /// node with the overrides applied. Entities outside prefabs are returned as they are.
+    pub fn resolve(&self, record: &EntityRecord) -> Result<EntityRecord, SceneError> {
+        let link = match &record.prefab {
+            Some(link) => link,
+            None => return Ok(record.clone()),
+        };
+
+        let node = self.node(link).ok_or_else(|| SceneError::InvalidField {
+            entity: Some(record.id),
+            field: "prefab".to_string(),
+            message: format!("no prefab node {}:{}", link.prefab, link.path),
+        })?;
+
+        let mut components = Value::Object(node.components.clone().into_iter().collect());
+        merge_patch(&mut components, &Value::Object(record.components.clone().into_iter().collect()));
+
+        Ok(EntityRecord {
+            id: record.id,
+            prefab: record.prefab.clone(),
+            components: match components {
+                Value::Object(components) => components.into_iter().collect(),
+                _ => BTreeMap::new(),
+            },
+        })
+    }
+
+    /// Turns the full components of a prefab instance's entity into the
+    /// overrides saved in scenes. Returns false, leaving the record untouched,
+    /// when its prefab isn't in the library.
+    pub fn to_overrides(&self, record: &mut EntityRecord) -> bool {
+        let node = match record.prefab.as_ref().and_then(|link| self.node(link)) {
+            Some(node) => node,
+            None => return false,
+        };
+
+        let base = Value::Object(node.components.clone().into_iter().collect());
+        let value = Value::Object(std::mem::take(&mut record.components).into_iter().collect());
+
+        record.components = match diff_patch(&base, &value) {
+            Some(Value::Object(overrides)) => overrides.into_iter().collect(),
+            _ => BTreeMap::new(),
+        };
+
+        true
+    }
+
+    /// Adds every prefab, and their meshes, to `saver`.
+    pub fn write_to(&self, saver: &mut Saver) {
+        if self.prefabs.is_empty() {
+            return;
+        }
+
+        if !saver.has_directory(PREFAB_DIRECTORY) {
+            saver.create_compressed_directory(PREFAB_DIRECTORY, Compression::Zstd(DEFAULT_ZSTD_LEVEL));
+        }
+
+        for prefab in self.prefabs.values() {
+            saver.insert_file_into_directory(
+                PREFAB_DIRECTORY,
+                LuminaFile::new(LuminaFileType::Json, format!("{}.json", prefab.name), prefab.to_bytes()),
+            );
+        }
+    }
+
+    fn node(&self, link: &PrefabLink) -> Option<&PrefabNode> {
+        self.prefabs.get(&link.prefab)?.find(&link.path)
+    }
+}
+
+/// Every string found in the components of `prefabs`.
+fn strings_of(prefabs: &BTreeMap<String, PrefabFile>) -> HashSet<&str> {
+    fn collect<'a>(value: &'a Value, strings: &mut HashSet<&'a str>) {
+        match value {
+            Value::String(string) => {
+                strings.insert(string.as_str());
+            }
+            Value::Array(values) => values.iter().for_each(|value| collect(value, strings)),
+            Value::Object(values) => values.values().for_each(|value| collect(value, strings)),
+            _ => {}
+        }
+    }
+
+    let mut strings = HashSet::new();
+
+    for prefab in prefabs.values() {
+        for (_, node) in prefab.nodes() {
+            node.components.values().for_each(|value| collect(value, &mut strings));
+        }
+    }
+
+    strings
+}
+
+/// Applies a JSON merge patch (RFC 7386) to `target`: objects are merged key
+/// by key, `null` removes a key and any other value replaces what was there.
+pub fn merge_patch(target: &mut Value, patch: &Value) {
+    let patch = match patch {
+        Value::Object(patch) => patch,
+        _ => {
+            *target = patch.clone();
+            return;
+        }
+    };
+
+    if !target.is_object() {
+        *target = Value::Object(Map::new());
+    }
+
+    let target = target.as_object_mut().unwrap();
+
+    for (key, value) in patch.iter() {
+        if value.is_null() {
+            target.remove(key);
+        } else {
+            merge_patch(target.entry(key.clone()).or_insert(Value::Null), value);
+        }
+    }
+}
+
+/// The merge patch turning `base` into `value`, `None` when they are equal.
+///
+/// Like every merge patch it can't set a key to `null`, such keys are removed instead.
+pub fn diff_patch(base: &Value, value: &Value) -> Option<Value> {
+    if base == value {
+        return None;
+    }
+
+    let (base, value) = match (base, value) {
+        (Value::Object(base), Value::Object(value)) => (base, value),
+        _ => return Some(value.clone()),
+    };
+
+    let mut patch = Map::new();
+
+    for key in base.keys() {
+        if !value.contains_key(key) {
+            patch.insert(key.clone(), Value::Null);
+        }
+    }
+
+    for (key, field) in value.iter() {
+        let field_patch = match base.get(key) {
+            Some(base_field) => diff_patch(base_field, field),
+            None => Some(field.clone()),
+        };
+
+        if let Some(field_patch) = field_patch {
+            patch.insert(key.clone(), field_patch);
+        }
+    }
+
+    Some(Value::Object(patch))
+}
+
+fn invalid(field: String, message: &str) -> SceneError {
+    SceneError::InvalidField {
+        entity: None,
+        field,
+        message: message.to_string(),
+    }
+}
@@ -1,114 +1,97 @@
 use std::{
     collections::HashMap,
-    fs::OpenOptions,
-    io::{self, Write},
+    fs::{self, OpenOptions},
+    io::{Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    process,
 };
 
-use lazy_static::lazy_static;
-use serde_json::{json, Value};
-use simple_crypt::encrypt;
-
-const LUMINA_KEY: &[u8; 58] = b"cgvnhfjxcnmvmjtyurd34563245567878690hfgjcmxfghyuhjkfuiojlg";
-
-#[derive(Debug, PartialEq)]
-pub enum LuminaFileType {
-    Json,
-    Jpg,
-    Png,
-    Fbx,
-    Gltf,
-    None,
-}
-
-#[derive(Debug)]
-pub struct LuminaFile {
-    file_type: LuminaFileType,
-    file_name: String,
-    file_content: Vec<u8>,
-}
-
-impl LuminaFile {
-    pub fn new(file_type: LuminaFileType, file_name: String, file_content: Vec<u8>) -> Self {
-        Self {
-            file_type,
-            file_name,
-            file_content,
-        }
-    }
-}
-
-#[derive(Debug)]
-struct LuminaDirectory {
-    name: String,
-    files: Vec<LuminaFile>,
-}
+use crate::{
+    compression::{compress, Compression, DEFAULT_ZSTD_LEVEL},
+    error::{check_length, LuminaFileError},
+    file::{LuminaDirectory, LuminaFile, LuminaFileType},
+    format::{
+        LuminaHeader, TableOfContents, TocDirectory, TocEntry, FLAG_ENCRYPTED, FLAG_PATCH,
+        HEADER_SIZE, MAX_ENTRY_SIZE,
+    },
+    integrity::{hash, HASH_SIZE},
+    key::LuminaKey,
+    patch::{PatchManifest, PATCH_DIRECTORY, PATCH_MANIFEST_NAME},
+    scene::{SceneFile, SkyboxRecord},
+};
 
 pub struct Saver {
-    pub json: Value,
+    pub scene: SceneFile,
     directories: HashMap<String, LuminaDirectory>,
+    compression: HashMap<String, Compression>,
+    key: LuminaKey,
+    backup_count: usize,
+    patch: bool,
 }
 
 impl Saver {
     pub fn new() -> Self {
-        let json: Value = json!({
-            "project_name": "",
-            "skybox": {
-                "x": "",
-                "-x": "",
-                "y": "",
-                "-y": "",
-                "z": "",
-                "-z": "",
-            },
-            "light_count": 0,
-            "game_objects": [],
-            "lights": [],
-            "models": [],
-            "transforms": []
-        });
-
         Self {
-            json,
+            scene: SceneFile::default(),
             directories: HashMap::new(),
+            compression: HashMap::new(),
+            key: LuminaKey::default(),
+            backup_count: 0,
+            patch: false,
         }
     }
 
-    pub fn add_entity(&mut self, entity: Value) {
-        self.json["entities"]
-            .as_array_mut()
-            .unwrap()
-            .push(entity);
-    }
-
-    pub fn modify_array_value(&mut self,value:&str,data:Vec<Value>){
-        if let Some(object) = self.json.as_object_mut() {
-            if object.contains_key(value) {
-                object.insert(value.to_string(), Value::Array(data));
-            }
-        }
+    /// Sets the key used to encrypt the archive, [`LuminaKey::Plain`] saves it unencrypted.
+    pub fn set_key(&mut self, key: LuminaKey) {
+        self.key = key;
     }
 
     pub fn modify_project_name(&mut self,project_name:&str) {
-        self.json["project_name"] = json!(project_name);
+        self.scene.project_name = project_name.to_string();
     }
 
     pub fn modify_skybox(&mut self, skybox_images: [String; 6]) {
-        self.json["skybox"]["x"] = json!(skybox_images[0]);
-        self.json["skybox"]["-x"] = json!(skybox_images[1]);
-        self.json["skybox"]["y"] = json!(skybox_images[2]);
-        self.json["skybox"]["-y"] = json!(skybox_images[3]);
-        self.json["skybox"]["z"] = json!(skybox_images[4]);
-        self.json["skybox"]["-z"] = json!(skybox_images[5]);
+        let [x, neg_x, y, neg_y, z, neg_z] = skybox_images;
+
+        self.scene.skybox = SkyboxRecord {
+            x,
+            neg_x,
+            y,
+            neg_y,
+            z,
+            neg_z,
+        };
     }
 
     pub fn create_directory(&mut self, name: &str) {
-        self.directories.insert(
-            name.to_string(),
-            LuminaDirectory {
-                name: name.to_string(),
-                files: Vec::new(),
-            },
+        self.create_compressed_directory(name, Compression::None);
+    }
+
+    /// Creates a directory whose files get compressed with `compression` when saved.
+    pub fn create_compressed_directory(&mut self, name: &str, compression: Compression) {
+        self.directories
+            .insert(name.to_string(), LuminaDirectory::new(name));
+        self.compression.insert(name.to_string(), compression);
+    }
+
+    pub fn set_directory_compression(&mut self, name: &str, compression: Compression) {
+        if self.directories.contains_key(name) {
+            self.compression.insert(name.to_string(), compression);
+        }
+    }
+
+    /// Turns the archive into a patch described by `manifest`, see [`PatchManifest`].
+    pub fn set_patch(&mut self, manifest: &PatchManifest) {
+        self.create_directory(PATCH_DIRECTORY);
+        self.insert_file_into_directory(
+            PATCH_DIRECTORY,
+            LuminaFile::new(LuminaFileType::Raw, PATCH_MANIFEST_NAME.to_string(), manifest.to_bytes()),
         );
+        self.patch = true;
+    }
+
+    pub fn has_directory(&self, name: &str) -> bool {
+        self.directories.contains_key(name)
     }
 
     pub fn insert_file_into_directory(&mut self, directory_name: &str, file: LuminaFile) {
@@ -119,67 +102,177 @@ impl Saver {
             .push(file);
     }
 
-    pub fn save_data(&mut self) {
-        let file_name = "./".to_string() + &self.json["project_name"].as_str().unwrap() + ".lumin";
+    /// Saves the archive to `./<project_name>.lumin`.
+    pub fn save_data(&mut self) -> Result<(), LuminaFileError> {
+        let file_name = "./".to_string() + &self.scene.project_name + ".lumin";
+
+        self.save_data_to(&file_name)
+    }
+
+    /// Saves the archive, with the scene in `gameData/scene.json`, to `file_path`.
+    ///
+    /// See [`Saver::write_archive`] for how the file gets replaced.
+    pub fn save_data_to(&mut self, file_path: &str) -> Result<(), LuminaFileError> {
+        let scene = self.scene.to_bytes();
+
+        if !self.directories.contains_key("gameData") {
+            self.create_compressed_directory("gameData", Compression::Zstd(DEFAULT_ZSTD_LEVEL));
+        }
+
+        // Saving again replaces the previous scene instead of adding a second one
+        let game_data = self.directories.get_mut("gameData").unwrap();
+        game_data.files.retain(|file| file.file_name != "scene.json");
+        game_data.files.push(LuminaFile::new(LuminaFileType::Json, "scene.json".to_string(), scene));
+
+        self.write_archive(file_path)
+    }
+
+    /// Keeps the last `backup_count` versions of an archive as `<file>.bak1`
+    /// (newest) to `<file>.bak<backup_count>` (oldest) every time it is overwritten.
+    pub fn set_backup_count(&mut self, backup_count: usize) {
+        self.backup_count = backup_count;
+    }
+
+    /// Writes the directories as they are to `file_path`, without adding the scene.
+    ///
+    /// The archive is written to a temporary file next to `file_path`, flushed
+    /// to disk and then renamed over it, so a crash mid-save leaves the previous
+    /// archive untouched.
+    pub fn write_archive(&self, file_path: &str) -> Result<(), LuminaFileError> {
+        let path = Path::new(file_path);
+        let file_name = path
+            .file_name()
+            .ok_or_else(|| LuminaFileError::InvalidName(file_path.to_string()))?
+            .to_string_lossy();
+        let temp_path = path.with_file_name(format!(".{}.{}.tmp", file_name, process::id()));
 
-        self.create_directory("gameData");
-        self.insert_file_into_directory("gameData", LuminaFile { file_type: LuminaFileType::Json, file_name: "scene.json".to_string(), file_content: serde_json::to_vec(&self.json).unwrap() });
+        let result = self
+            .write_to_temp(&temp_path)
+            .and_then(|_| self.rotate_backups(path))
+            .and_then(|_| Ok(fs::rename(&temp_path, path)?));
 
+        if result.is_err() {
+            let _ = fs::remove_file(&temp_path);
+            return result;
+        }
+
+        sync_parent_dir(path);
+
+        Ok(())
+    }
+
+    fn rotate_backups(&self, path: &Path) -> Result<(), LuminaFileError> {
+        if self.backup_count == 0 || !path.exists() {
+            return Ok(());
+        }
+
+        let backup_path = |index: usize| {
+            let mut backup = path.as_os_str().to_owned();
+            backup.push(format!(".bak{}", index));
+            PathBuf::from(backup)
+        };
+
+        for index in (1..self.backup_count).rev() {
+            if backup_path(index).exists() {
+                fs::rename(backup_path(index), backup_path(index + 1))?;
+            }
+        }
+
+        // Linking keeps the current archive in place until the rename replaces it
+        let newest = backup_path(1);
+        let _ = fs::remove_file(&newest);
+
+        if fs::hard_link(path, &newest).is_err() {
+            fs::copy(path, &newest)?;
+        }
+
+        Ok(())
+    }
+
+    fn write_to_temp(&self, temp_path: &Path) -> Result<(), LuminaFileError> {
         let mut file = OpenOptions::new()
             .create(true)
             .write(true)
             .truncate(true)
-            .open(file_name)
-            .unwrap();
-
-        for (name, directory) in self.directories.iter_mut() {
-            let encrypted_dir_name = encrypt(
-                &directory.name.as_bytes(),
-                LUMINA_KEY,
-            )
-            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))
-            .unwrap();
-
-            file.write_all(&(encrypted_dir_name.len() as u32).to_le_bytes())
-                .unwrap();
-            file.write_all(&encrypted_dir_name).unwrap();
-
-            file.write_all(&(directory.files.len() as u32).to_le_bytes())
-                .unwrap();
+            .open(temp_path)?;
+
+        let mut flags = if self.key.is_plain() { 0 } else { FLAG_ENCRYPTED };
+
+        if self.patch {
+            flags |= FLAG_PATCH;
+        }
+
+        // The header is rewritten once the table of contents position is known
+        LuminaHeader::new(flags, 0, 0, [0u8; HASH_SIZE]).write_to(&mut file)?;
+
+        let mut offset = HEADER_SIZE;
+        let mut toc = TableOfContents::new();
+
+        let mut directory_names: Vec<&String> = self.directories.keys().collect();
+        directory_names.sort();
+
+        for name in directory_names {
+            let directory = &self.directories[name];
+            let directory_compression = self.compression.get(name).copied().unwrap_or_default();
+
+            let mut toc_directory = TocDirectory {
+                name: directory.name.clone(),
+                entries: Vec::with_capacity(directory.files.len()),
+            };
+
             for file_info in directory.files.iter() {
-                let file_type_byte = match file_info.file_type {
-                    LuminaFileType::Json => b'J',
-                    LuminaFileType::Png => b'P',
-                    LuminaFileType::Fbx => b'F',
-                    LuminaFileType::Gltf => b'G',
-                    LuminaFileType::Jpg => b'E',
-                    LuminaFileType::None => 0,
-                };
-
-                let encrypted_file_name = encrypt(
-                    &file_info.file_name.as_bytes(),
-                    LUMINA_KEY,
-                )
-                .map_err(|e| io::Error::new(io::ErrorKind::Other, e))
-                .unwrap();
-
-                file.write_all(&[file_type_byte]).unwrap();
-                file.write_all(&(encrypted_file_name.len() as u32).to_le_bytes())
-                    .unwrap();
-
-                let encrypted_content = encrypt(
-                    &file_info.file_content,
-                    LUMINA_KEY,
-                )
-                .map_err(|e| io::Error::new(io::ErrorKind::Other, e))
-                .unwrap();
-
-                let content_len = encrypted_content.len() as u32;
-                file.write_all(&content_len.to_le_bytes()).unwrap();
-
-                file.write_all(&encrypted_file_name).unwrap();
-                file.write_all(&encrypted_content).unwrap();
+                // Entries the loader would refuse fail the save, leaving the last archive in place
+                check_length("uncompressed size", file_info.file_content.len() as u64, MAX_ENTRY_SIZE)?;
+
+                let (compression, compressed_content) =
+                    compress(&file_info.file_content, directory_compression)?;
+
+                let encrypted_content = self.key.encrypt(&compressed_content)?;
+                check_length("entry size", encrypted_content.len() as u64, MAX_ENTRY_SIZE)?;
+
+                file.write_all(&encrypted_content)?;
+
+                toc_directory.entries.push(TocEntry {
+                    file_type: file_info.file_type.to_byte(),
+                    compression,
+                    file_name: file_info.file_name.clone(),
+                    offset,
+                    size: encrypted_content.len() as u64,
+                    raw_size: file_info.file_content.len() as u64,
+                    hash: hash(&encrypted_content),
+                });
+
+                offset += encrypted_content.len() as u64;
             }
+
+            toc.directories.push(toc_directory);
+        }
+
+        let encrypted_toc = self.key.encrypt(&toc.to_bytes())?;
+
+        file.write_all(&encrypted_toc)?;
+
+        file.seek(SeekFrom::Start(0))?;
+        LuminaHeader::new(flags, offset, encrypted_toc.len() as u64, hash(&encrypted_toc))
+            .write_to(&mut file)?;
+
+        file.sync_all()?;
+
+        Ok(())
+    }
+}
+
+/// Makes the rename itself durable. Only possible on unix, where directories can be opened.
+fn sync_parent_dir(path: &Path) {
+    #[cfg(unix)]
+    {
+        let parent = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+
+        if let Ok(dir) = fs::File::open(parent) {
+            let _ = dir.sync_all();
         }
     }
 }
@@ -0,0 +1,406 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    fmt,
+};
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{
+    file::LuminaFileType,
+    id::{format_id, EntityId},
+    migration::{migrate, SCENE_VERSION},
+    prefab::PrefabLink,
+};
+
+/// The scene document saved as `gameData/scene.json`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SceneFile {
+    /// Always [`SCENE_VERSION`] once loaded, older documents are migrated first.
+    pub version: u32,
+    pub project_name: String,
+    #[serde(default)]
+    pub skybox: SkyboxRecord,
+    #[serde(default)]
+    pub entities: Vec<EntityRecord>,
+    /// Files living outside the scene, keyed by their `directory/file` path in
+    /// the archive. Only text scenes list them, archives embed the files instead.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub assets: BTreeMap<String, AssetRecord>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SkyboxRecord {
+    pub x: String,
+    #[serde(rename = "-x")]
+    pub neg_x: String,
+    pub y: String,
+    #[serde(rename = "-y")]
+    pub neg_y: String,
+    pub z: String,
+    #[serde(rename = "-z")]
+    pub neg_z: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AssetRecord {
+    /// Path of the file on disk, relative to the text scene.
+    pub path: String,
+    #[serde(rename = "type")]
+    pub file_type: LuminaFileType,
+}
+
+/// An entity and its components, keyed by the name they were registered
+/// under (see `lumina_object::registry::ComponentRegistry`).
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct EntityRecord {
+    #[serde(with = "crate::id")]
+    pub id: EntityId,
+    /// Set on entities spawned from a prefab, whose components are then only
+    /// what the entity overrides, see [`crate::prefab::PrefabLibrary::resolve`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prefab: Option<PrefabLink>,
+    #[serde(default)]
+    pub components: BTreeMap<String, Value>,
+}
+
+/// Content of the `transform` component.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TransformRecord {
+    pub translation: [f32; 3],
+    pub rotation: [f32; 3],
+    pub scale: [f32; 3],
+}
+
+/// Content of the `light` component.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LightRecord {
+    /// 0 for directional, 1 for point and 2 for spot lights.
+    pub light_type: u32,
+    pub color: [f32; 3],
+    pub intensity: f32,
+    pub range: f32,
+    pub spot_size: f32,
+}
+
+/// Content of the `model` component.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ModelRecord {
+    /// Source file the model was imported from, empty for models built in code.
+    #[serde(rename = "file", default)]
+    pub file_path: String,
+    /// Set on models imported from `file`, which are imported again when
+    /// loaded instead of having their meshes saved.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub import: Option<ImportSettings>,
+    #[serde(default)]
+    pub materials: Vec<MaterialRecord>,
+    /// Ids of the meshes saved in the `meshes` directory, see [`crate::mesh::MeshStore`].
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub mesh_ids: Vec<String>,
+    /// Meshes written in the scene itself, as they were before meshes got their
+    /// own directory. Still loaded, never saved anymore.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub meshes: Vec<MeshRecord>,
+}
+
+/// How a model was imported from its source file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ImportSettings {
+    pub flip_uvs: bool,
+    pub join_identical_vertices: bool,
+    pub calculate_tangent_space: bool,
+}
+
+impl Default for ImportSettings {
+    fn default() -> Self {
+        Self {
+            flip_uvs: true,
+            join_identical_vertices: true,
+            calculate_tangent_space: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MaterialRecord {
+    /// Index of the mesh the material is bound to.
+    #[serde(default)]
+    pub parent_id: usize,
+    pub ambient: [f32; 3],
+    #[serde(default)]
+    pub ambient_texture: String,
+    pub diffuse: [f32; 3],
+    pub metallic: [f32; 3],
+    #[serde(default)]
+    pub metallic_texture: String,
+    #[serde(default)]
+    pub normal_texture: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct MeshRecord {
+    pub vertices: Vec<VertexRecord>,
+    pub indices: Vec<u32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct VertexRecord {
+    pub position: [f32; 3],
+    pub normal: [f32; 3],
+    pub uv: [f32; 2],
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SceneError {
+    /// The document isn't valid JSON, or isn't an object.
+    Syntax(String),
+    /// The scene was saved by a newer version of the engine.
+    UnsupportedVersion(u32),
+    /// A field is missing or holds an invalid value. `entity` is the id of
+    /// the record's entity when it could be read, `field` the path of the
+    /// field inside the document, e.g. `models[0].meshes[2].indices[5]`.
+    InvalidField {
+        entity: Option<EntityId>,
+        field: String,
+        message: String,
+    },
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SceneError::Syntax(err) => write!(f, "invalid scene: {}", err),
+            SceneError::UnsupportedVersion(version) => write!(
+                f,
+                "scene version {} is newer than the supported version {}",
+                version, SCENE_VERSION
+            ),
+            SceneError::InvalidField {
+                entity: Some(entity),
+                field,
+                message,
+            } => write!(f, "entity {}: {}: {}", format_id(*entity), field, message),
+            SceneError::InvalidField {
+                entity: None,
+                field,
+                message,
+            } => write!(f, "{}: {}", field, message),
+        }
+    }
+}
+
+impl SceneError {
+    /// Places an error reported by a component's record inside its entity,
+    /// e.g. `light_type` becomes `entities[3].components.light.light_type`.
+    pub fn within(self, entity: EntityId, prefix: &str) -> Self {
+        match self {
+            SceneError::InvalidField { field, message, .. } => SceneError::InvalidField {
+                entity: Some(entity),
+                field: join_field(prefix, &field),
+                message,
+            },
+            err => err,
+        }
+    }
+}
+
+impl std::error::Error for SceneError {}
+
+impl Default for SceneFile {
+    fn default() -> Self {
+        Self {
+            version: SCENE_VERSION,
+            project_name: String::new(),
+            skybox: SkyboxRecord::default(),
+            entities: Vec::new(),
+            assets: BTreeMap::new(),
+        }
+    }
+}
+
+impl SceneFile {
+    pub fn new(project_name: &str) -> Self {
+        Self {
+            project_name: project_name.to_string(),
+            ..Default::default()
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        serde_json::to_vec(self).unwrap()
+    }
+
+    /// Pretty-printed form meant to be kept under version control. Entities
+    /// are sorted by id and component names are always sorted, so saving the
+    /// same scene twice gives the same text and small edits give small diffs.
+    pub fn to_text(&self) -> String {
+        let mut scene = self.clone();
+        scene.entities.sort_by_key(|entity| entity.id);
+
+        let mut text = serde_json::to_string_pretty(&scene).unwrap();
+        text.push('\n');
+
+        text
+    }
+
+    /// Parses a scene document, upgrading it first if it was saved with an
+    /// older version (see [`crate::migration`]). Only the layout is checked
+    /// here, the content of each component is checked when the component gets deserialized.
+    pub fn from_slice(bytes: &[u8]) -> Result<Self, SceneError> {
+        let mut document: Value =
+            serde_json::from_slice(bytes).map_err(|e| SceneError::Syntax(e.to_string()))?;
+
+        let object = document
+            .as_object_mut()
+            .ok_or_else(|| SceneError::Syntax("the scene isn't a JSON object".to_string()))?;
+
+        migrate(object)?;
+
+        let scene = Self {
+            version: SCENE_VERSION,
+            project_name: read_field(object.get("project_name").unwrap_or(&Value::Null), None, "project_name")?,
+            skybox: match object.get("skybox") {
+                Some(skybox) => read_field(skybox, None, "skybox")?,
+                None => SkyboxRecord::default(),
+            },
+            entities: match object.get("entities") {
+                Some(entities) => read_field(entities, None, "entities")?,
+                None => Vec::new(),
+            },
+            assets: match object.get("assets") {
+                Some(assets) => read_field(assets, None, "assets")?,
+                None => BTreeMap::new(),
+            },
+        };
+
+        scene.validate()?;
+
+        Ok(scene)
+    }
+
+    /// Checks that entity ids are unique.
+    pub fn validate(&self) -> Result<(), SceneError> {
+        let mut ids = HashSet::new();
+
+        for (index, entity) in self.entities.iter().enumerate() {
+            if !ids.insert(entity.id) {
+                return Err(invalid(Some(entity.id), format!("entities[{}].id", index), "duplicate entity id"));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl TransformRecord {
+    pub fn validate(&self) -> Result<(), SceneError> {
+        check_finite("translation", &self.translation)?;
+        check_finite("rotation", &self.rotation)?;
+        check_finite("scale", &self.scale)
+    }
+}
+
+impl LightRecord {
+    pub fn validate(&self) -> Result<(), SceneError> {
+        if self.light_type > 2 {
+            return Err(invalid(
+                None,
+                "light_type".to_string(),
+                &format!("unknown light type {}", self.light_type),
+            ));
+        }
+
+        check_finite("color", &self.color)?;
+        check_finite("intensity", &[self.intensity])?;
+        check_finite("range", &[self.range])?;
+        check_finite("spot_size", &[self.spot_size])
+    }
+}
+
+impl ModelRecord {
+    pub fn validate(&self) -> Result<(), SceneError> {
+        for (mesh_index, mesh) in self.meshes.iter().enumerate() {
+            let vertex_count = mesh.vertices.len();
+
+            for (position, indice) in mesh.indices.iter().enumerate() {
+                if *indice as usize >= vertex_count {
+                    return Err(invalid(
+                        None,
+                        format!("meshes[{}].indices[{}]", mesh_index, position),
+                        &format!("index {} out of {} vertices", indice, vertex_count),
+                    ));
+                }
+            }
+        }
+
+        // Imported models only know how many meshes they have once imported
+        let mesh_count = self.meshes.len() + self.mesh_ids.len();
+
+        for (material_index, material) in self.materials.iter().enumerate() {
+            if self.import.is_none() && mesh_count != 0 && material.parent_id >= mesh_count {
+                return Err(invalid(
+                    None,
+                    format!("materials[{}].parent_id", material_index),
+                    &format!("mesh {} out of {} meshes", material.parent_id, mesh_count),
+                ));
+            }
+        }
+
+        if self.import.is_some() && self.file_path.is_empty() {
+            return Err(invalid(None, "file".to_string(), "imported models need their source file"));
+        }
+
+        Ok(())
+    }
+}
+
+/// Deserializes a component record, reporting errors with the path of the
+/// field inside the record. Use [`SceneError::within`] to place it in the scene.
+pub fn read_record<T: DeserializeOwned>(value: &Value) -> Result<T, SceneError> {
+    read_field(value, None, "")
+}
+
+fn invalid(entity: Option<EntityId>, field: String, message: &str) -> SceneError {
+    SceneError::InvalidField {
+        entity,
+        field,
+        message: message.to_string(),
+    }
+}
+
+fn check_finite(field: &str, values: &[f32]) -> Result<(), SceneError> {
+    if values.iter().all(|value| value.is_finite()) {
+        return Ok(());
+    }
+
+    Err(invalid(None, field.to_string(), "expected finite numbers"))
+}
+
+fn read_field<T: DeserializeOwned>(value: &Value, entity: Option<EntityId>, field: &str) -> Result<T, SceneError> {
+    serde_path_to_error::deserialize(value).map_err(|err| {
+        let path = match err.path().to_string().as_str() {
+            "." => String::new(),
+            path => path.to_string(),
+        };
+
+        // Missing fields are reported on their parent, so name them explicitly
+        let message = err.into_inner().to_string();
+        let path = match message.strip_prefix("missing field `") {
+            Some(missing) => join_field(&path, missing.trim_end_matches('`')),
+            None => path,
+        };
+
+        invalid(entity, join_field(field, &path), &message)
+    })
+}
+
+fn join_field(parent: &str, field: &str) -> String {
+    match (parent.is_empty(), field.is_empty()) {
+        (true, _) => field.to_string(),
+        (_, true) => parent.to_string(),
+        _ if field.starts_with('[') => format!("{}{}", parent, field),
+        _ => format!("{}.{}", parent, field),
+    }
+}
@@ -0,0 +1,165 @@
+use std::{
+    fs,
+    path::{Component, Path, PathBuf},
+};
+
+use crate::{
+    compression::{Compression, DEFAULT_ZSTD_LEVEL},
+    error::LuminaFileError,
+    file::{LuminaFile, LuminaFileType},
+    loader::Loader,
+    mesh::MESH_DIRECTORY,
+    prefab::PREFAB_DIRECTORY,
+    saver::Saver,
+    scene::{AssetRecord, SceneFile},
+};
+
+/// Entry of the scene inside an archive.
+pub const SCENE_ENTRY: &str = "gameData/scene.json";
+
+/// Writes the scene of an opened archive to `text_path` (see [`SceneFile::to_text`])
+/// and every other entry as a plain file under `asset_dir`, listed in the
+/// scene's `assets` with a path relative to the text file.
+pub fn export_text(loader: &Loader, text_path: &str, asset_dir: &str) -> Result<(), LuminaFileError> {
+    let mut scene = SceneFile::from_slice(&loader.load_entry(SCENE_ENTRY)?.file_content)?;
+
+    scene.assets.clear();
+
+    for entry_path in loader.entries() {
+        if entry_path == SCENE_ENTRY {
+            continue;
+        }
+
+        let file = loader.load_entry(&entry_path)?;
+        write_asset(&mut scene, text_path, asset_dir, &entry_path, file.file_type, &file.file_content)?;
+    }
+
+    fs::write(text_path, scene.to_text())?;
+
+    Ok(())
+}
+
+/// Writes the archive entry `entry_path` as a plain file under `asset_dir` and
+/// lists it in the assets of the text scene that will be written to `text_path`.
+pub fn write_asset(
+    scene: &mut SceneFile,
+    text_path: &str,
+    asset_dir: &str,
+    entry_path: &str,
+    file_type: LuminaFileType,
+    content: &[u8],
+) -> Result<(), LuminaFileError> {
+    let relative =
+        safe_relative(entry_path).ok_or_else(|| LuminaFileError::InvalidName(entry_path.to_string()))?;
+    let target = Path::new(asset_dir).join(relative);
+
+    if let Some(parent) = target.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    fs::write(&target, content)?;
+
+    scene.assets.insert(
+        entry_path.to_string(),
+        AssetRecord {
+            path: relative_to(&target, &parent_dir(Path::new(text_path))),
+            file_type,
+        },
+    );
+
+    Ok(())
+}
+
+/// Reads an asset listed by the text scene at `text_path`.
+pub fn read_asset(text_path: &str, asset: &AssetRecord) -> Result<Vec<u8>, LuminaFileError> {
+    Ok(fs::read(parent_dir(Path::new(text_path)).join(&asset.path))?)
+}
+
+/// Reads a text scene and the assets it lists back into a `Saver`, ready to
+/// be written with [`Saver::save_data_to`].
+pub fn import_text(text_path: &str) -> Result<Saver, LuminaFileError> {
+    let mut scene = SceneFile::from_slice(&fs::read(text_path)?)?;
+
+    let mut saver = Saver::new();
+
+    for (entry_path, asset) in scene.assets.iter() {
+        let (directory_name, file_name) = entry_path
+            .split_once('/')
+            .ok_or_else(|| LuminaFileError::InvalidName(entry_path.clone()))?;
+
+        if !saver.has_directory(directory_name) {
+            let compression = match directory_name {
+                "gameData" | MESH_DIRECTORY | PREFAB_DIRECTORY => Compression::Zstd(DEFAULT_ZSTD_LEVEL),
+                _ => Compression::None,
+            };
+
+            saver.create_compressed_directory(directory_name, compression);
+        }
+
+        let content = read_asset(text_path, asset)?;
+
+        saver.insert_file_into_directory(
+            directory_name,
+            LuminaFile::new(asset.file_type, file_name.to_string(), content),
+        );
+    }
+
+    scene.assets.clear();
+    saver.scene = scene;
+
+    Ok(saver)
+}
+
+/// Path of `file_path` relative to the text scene at `text_path`, as stored in
+/// [`AssetRecord::path`]. Falls back to the path as given when either doesn't exist yet.
+pub fn asset_path(file_path: &str, text_path: &str) -> String {
+    let text_dir = parent_dir(Path::new(text_path));
+
+    match (Path::new(file_path).canonicalize(), text_dir.canonicalize()) {
+        (Ok(file_path), Ok(text_dir)) => relative_to(&file_path, &text_dir),
+        _ => file_path.replace('\\', "/"),
+    }
+}
+
+fn parent_dir(path: &Path) -> PathBuf {
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+        _ => PathBuf::from("."),
+    }
+}
+
+/// Entry paths come from the archive, so refuse anything that would escape
+/// the directory they are written to: only plain names, no `..`, root or prefix.
+pub fn safe_relative(entry_path: &str) -> Option<&Path> {
+    let relative = Path::new(entry_path);
+
+    if relative
+        .components()
+        .any(|component| !matches!(component, Component::Normal(_)))
+    {
+        return None;
+    }
+
+    Some(relative)
+}
+
+/// `target` relative to `base` with `/` separators, so text scenes read the same on every platform.
+fn relative_to(target: &Path, base: &Path) -> String {
+    let target_components: Vec<Component> = target.components().filter(|c| *c != Component::CurDir).collect();
+    let base_components: Vec<Component> = base.components().filter(|c| *c != Component::CurDir).collect();
+
+    let common = target_components
+        .iter()
+        .zip(base_components.iter())
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    let mut parts: Vec<String> = base_components[common..].iter().map(|_| "..".to_string()).collect();
+    parts.extend(
+        target_components[common..]
+            .iter()
+            .map(|component| component.as_os_str().to_string_lossy().into_owned()),
+    );
+
+    parts.join("/")
+}
@@ -0,0 +1,170 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use lumina_files::{
+    error::LuminaFileError,
+    file::{LuminaFile, LuminaFileType},
+    key::LuminaKey,
+    loader::Loader,
+    patch::create_patch,
+    saver::Saver,
+};
+
+fn scratch_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("lumina_archives_{}", name));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+
+    dir
+}
+
+fn archive_path(dir: &Path, name: &str) -> String {
+    dir.join(format!("{}.lumin", name))
+        .to_string_lossy()
+        .into_owned()
+}
+
+fn build_archive(files: &[(&str, &[u8])], key: LuminaKey, file_path: &str) {
+    let mut saver = Saver::new();
+    saver.modify_project_name("archives");
+    saver.set_key(key);
+
+    for (path, content) in files {
+        let (directory_name, file_name) = path.split_once('/').unwrap();
+
+        if !saver.has_directory(directory_name) {
+            saver.create_directory(directory_name);
+        }
+        saver.insert_file_into_directory(
+            directory_name,
+            LuminaFile::new(LuminaFileType::Raw, file_name.to_string(), content.to_vec()),
+        );
+    }
+
+    saver.save_data_to(file_path).unwrap();
+}
+
+fn open_archive(file_path: &str, key: LuminaKey) -> Loader {
+    let mut loader = Loader::new();
+    loader.set_key(key);
+    loader.open_file(file_path.to_string()).unwrap();
+
+    loader
+}
+
+#[test]
+fn archives_round_trip_plain_and_with_a_key() {
+    let dir = scratch_dir("round_trip");
+    let files: &[(&str, &[u8])] = &[("textures/a.png", &[1, 2, 3]), ("sounds/b.ogg", &[4; 64])];
+
+    for (name, key) in [
+        ("plain", LuminaKey::Plain),
+        ("keyed", LuminaKey::from_passphrase("archives")),
+    ] {
+        let file_path = archive_path(&dir, name);
+        build_archive(files, key.clone(), &file_path);
+
+        let loader = open_archive(&file_path, key);
+        for (path, content) in files {
+            assert_eq!(
+                loader.load_entry(path).unwrap().file_content,
+                content.to_vec(),
+                "{}: {}",
+                name,
+                path
+            );
+        }
+        assert!(loader.load_entry("gameData/scene.json").is_ok());
+    }
+}
+
+#[test]
+fn verify_names_the_damaged_entry() {
+    let dir = scratch_dir("verify");
+    let file_path = archive_path(&dir, "damaged");
+    build_archive(
+        &[
+            ("textures/a.png", &[1, 2, 3, 4, 5, 6, 7, 8]),
+            ("textures/b.png", &[9; 8]),
+        ],
+        LuminaKey::Plain,
+        &file_path,
+    );
+
+    let loader = open_archive(&file_path, LuminaKey::Plain);
+    assert!(loader.verify().unwrap().is_empty());
+
+    let offset = loader
+        .get_toc()
+        .unwrap()
+        .find("textures/a.png")
+        .unwrap()
+        .offset as usize;
+    let mut bytes = fs::read(&file_path).unwrap();
+    bytes[offset + 2] ^= 1;
+    fs::write(&file_path, &bytes).unwrap();
+
+    let loader = open_archive(&file_path, LuminaKey::Plain);
+    assert_eq!(loader.verify().unwrap(), vec!["textures/a.png".to_string()]);
+    assert!(matches!(
+        loader.load_entry("textures/a.png"),
+        Err(LuminaFileError::ChecksumMismatch(_))
+    ));
+    assert!(loader.load_entry("textures/b.png").is_ok());
+}
+
+#[test]
+fn patches_mount_only_in_order() {
+    let dir = scratch_dir("patches");
+    let path = |name: &str| archive_path(&dir, name);
+
+    build_archive(
+        &[("data/x", b"1"), ("data/y", b"2")],
+        LuminaKey::default(),
+        &path("base"),
+    );
+    build_archive(
+        &[("data/x", b"1"), ("data/y", b"22")],
+        LuminaKey::default(),
+        &path("v2"),
+    );
+    build_archive(
+        &[("data/x", b"111"), ("data/y", b"22")],
+        LuminaKey::default(),
+        &path("v3"),
+    );
+
+    let (first, _) = create_patch(
+        &open_archive(&path("base"), LuminaKey::default()),
+        &open_archive(&path("v2"), LuminaKey::default()),
+    )
+    .unwrap();
+    first.write_archive(&path("patch1")).unwrap();
+
+    let mut patched = open_archive(&path("base"), LuminaKey::default());
+    patched.mount_patch(path("patch1")).unwrap();
+    let (second, _) =
+        create_patch(&patched, &open_archive(&path("v3"), LuminaKey::default())).unwrap();
+    second.write_archive(&path("patch2")).unwrap();
+
+    let mut skipped = open_archive(&path("base"), LuminaKey::default());
+    assert!(matches!(
+        skipped.mount_patch(path("patch2")),
+        Err(LuminaFileError::InvalidPatch(_))
+    ));
+
+    let mut repeated = open_archive(&path("base"), LuminaKey::default());
+    repeated.mount_patch(path("patch1")).unwrap();
+    assert!(matches!(
+        repeated.mount_patch(path("patch1")),
+        Err(LuminaFileError::InvalidPatch(_))
+    ));
+
+    let mut ordered = open_archive(&path("base"), LuminaKey::default());
+    ordered.mount_patch(path("patch1")).unwrap();
+    ordered.mount_patch(path("patch2")).unwrap();
+    assert_eq!(ordered.load_entry("data/x").unwrap().file_content, b"111");
+    assert_eq!(ordered.load_entry("data/y").unwrap().file_content, b"22");
+}
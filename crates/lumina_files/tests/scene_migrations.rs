@@ -0,0 +1,143 @@
+use lumina_files::{
+    id::EntityId,
+    migration::{MIGRATIONS, SCENE_VERSION},
+    scene::{read_record, LightRecord, ModelRecord, SceneError, SceneFile, TransformRecord},
+};
+
+const FIXTURES: &[(u32, &str)] = &[
+    (1, include_str!("fixtures/scene_v1.json")),
+    (2, include_str!("fixtures/scene_v2.json")),
+    (3, include_str!("fixtures/scene_v3.json")),
+    (4, include_str!("fixtures/scene_v4.json")),
+];
+
+fn load(version: u32, document: &str) -> SceneFile {
+    SceneFile::from_slice(document.as_bytes())
+        .unwrap_or_else(|err| panic!("scene v{} failed to load: {}", version, err))
+}
+
+#[test]
+fn every_version_has_a_fixture() {
+    let versions: Vec<u32> = FIXTURES.iter().map(|(version, _)| *version).collect();
+
+    assert_eq!(versions, (1..=SCENE_VERSION).collect::<Vec<u32>>());
+}
+
+#[test]
+fn migrations_form_a_chain() {
+    let froms: Vec<u32> = MIGRATIONS.iter().map(|migration| migration.from).collect();
+
+    assert_eq!(froms, (1..SCENE_VERSION).collect::<Vec<u32>>());
+}
+
+#[test]
+fn fixtures_load_as_the_current_version() {
+    for (version, document) in FIXTURES {
+        let scene = load(*version, document);
+
+        assert_eq!(scene.version, SCENE_VERSION);
+        assert_eq!(scene.project_name, "fixture");
+        assert_eq!(scene.skybox.neg_x, "skybox/left.jpg");
+        assert_eq!(
+            scene.entities.iter().map(|entity| entity.id).collect::<Vec<EntityId>>(),
+            vec![12, 40],
+            "scene v{}",
+            version
+        );
+
+        let transform: TransformRecord = read_record(&scene.entities[0].components["transform"]).unwrap();
+        transform.validate().unwrap();
+        assert_eq!(transform.translation, [1.0, 2.0, 3.0], "scene v{}", version);
+        assert_eq!(transform.rotation, [0.0, 0.5, 0.0]);
+
+        let model: ModelRecord = read_record(&scene.entities[0].components["model"]).unwrap();
+        model.validate().unwrap();
+        assert_eq!(model.meshes[0].indices, vec![0, 1, 2]);
+        assert_eq!(model.materials[0].ambient_texture, "textures/albedo.jpg");
+
+        let light: LightRecord = read_record(&scene.entities[1].components["light"]).unwrap();
+        light.validate().unwrap();
+        assert_eq!(light.light_type, 1);
+        assert_eq!(light.color, [1.0, 0.5, 0.25]);
+    }
+}
+
+#[test]
+fn migrated_fixtures_save_as_the_current_fixture() {
+    let current = load(SCENE_VERSION, FIXTURES[FIXTURES.len() - 1].1);
+
+    for (version, document) in FIXTURES {
+        let migrated = load(*version, document);
+
+        // Saving and loading again goes through no migration at all
+        let saved = String::from_utf8(migrated.to_bytes()).unwrap();
+        assert!(saved.starts_with(&format!("{{\"version\":{},", SCENE_VERSION)));
+        assert_eq!(load(SCENE_VERSION, &saved), migrated);
+
+        for (entity, current_entity) in migrated.entities.iter().zip(current.entities.iter()) {
+            for name in ["transform", "light"] {
+                assert_eq!(
+                    entity.components.get(name),
+                    current_entity.components.get(name),
+                    "scene v{}: {} of entity {}",
+                    version,
+                    name,
+                    entity.id
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn newer_versions_are_refused() {
+    let document = format!("{{\"version\":{},\"project_name\":\"p\"}}", SCENE_VERSION + 1);
+
+    assert_eq!(
+        SceneFile::from_slice(document.as_bytes()),
+        Err(SceneError::UnsupportedVersion(SCENE_VERSION + 1))
+    );
+}
+
+#[test]
+fn broken_v1_scenes_name_the_field() {
+    let err = SceneFile::from_slice(br#"{"project_name":"p","game_objects":[5],"lights":[{"id":7}]}"#)
+        .unwrap_err();
+
+    assert_eq!(
+        err,
+        SceneError::InvalidField {
+            entity: Some(7),
+            field: "lights[0].id".to_string(),
+            message: "entity isn't listed in game_objects".to_string(),
+        }
+    );
+}
+
+#[test]
+fn numeric_ids_become_uuids() {
+    let scene = SceneFile::from_slice(
+        br#"{"version":3,"project_name":"p","entities":[{"id":7,"prefab":{"prefab":"door","instance":7}}]}"#,
+    )
+    .unwrap();
+
+    assert_eq!(scene.entities[0].id, 7);
+    assert_eq!(scene.entities[0].prefab.as_ref().unwrap().instance, 7);
+
+    let saved = String::from_utf8(scene.to_bytes()).unwrap();
+    assert!(saved.contains(r#""id":"00000000-0000-0000-0000-000000000007""#));
+}
+
+#[test]
+fn invalid_ids_name_the_field() {
+    let err = SceneFile::from_slice(br#"{"version":4,"project_name":"p","entities":[{"id":"door"}]}"#).unwrap_err();
+
+    assert_eq!(
+        err,
+        SceneError::InvalidField {
+            entity: None,
+            field: "entities[0].id".to_string(),
+            message: "invalid entity id \"door\"".to_string(),
+        }
+    );
+}
@@ -1,16 +1,51 @@
 use std::{any::{TypeId, Any}, collections::HashMap};
 
-use crate::game_object::Component;
+use crate::{
+    game_object::Component,
+    storage::{ComponentInfo, EntityView},
+};
 
+/// An entity's components on their own, before they are spawned into a query.
 #[derive(Debug)]
 pub struct Entity {
-    components: HashMap<TypeId, Box<dyn Any + Send + Sync>>,
+    components: HashMap<TypeId, (ComponentInfo, Box<dyn Any + Send + Sync>)>,
 }
 
 impl Entity {
     pub fn add_component<T: Component + Send + Sync + 'static>(&mut self, component: T) {
+        self.insert_boxed(ComponentInfo::of::<T>(), Box::new(component));
+    }
+
+    /// Adds a component whose type is only known at runtime, see [`crate::registry::ComponentRegistry`].
+    pub fn insert_boxed(&mut self, info: ComponentInfo, component: Box<dyn Any + Send + Sync>) {
+        self.components.insert(info.type_id, (info, component));
+    }
+
+    /// Removes the component of type `type_id`, see [`crate::registry::ComponentRegistry`].
+    pub(crate) fn remove_boxed(&mut self, type_id: TypeId) -> Option<Box<dyn Any + Send + Sync>> {
+        self.components.remove(&type_id).map(|(_, component)| component)
+    }
+
+    /// Every component of the entity along with its type, in no particular order.
+    pub fn components(&self) -> impl Iterator<Item = (TypeId, &'static str, &(dyn Any + Send + Sync))> {
         self.components
-            .insert(TypeId::of::<T>(), Box::new(component));
+            .iter()
+            .map(|(type_id, (info, component))| (*type_id, info.type_name, component.as_ref()))
+    }
+
+    /// Takes the components out of the entity, to be stored elsewhere.
+    pub fn into_components(self) -> impl Iterator<Item = (ComponentInfo, Box<dyn Any + Send + Sync>)> {
+        self.components.into_values()
+    }
+
+    pub fn view(&self) -> EntityView<'_> {
+        let mut view = EntityView::new();
+
+        for (info, component) in self.components.values() {
+            view.push(*info, component.as_ref());
+        }
+
+        view
     }
 
     pub fn has_component<T: Component + Send + Sync + 'static>(&self) -> bool {
@@ -18,7 +53,7 @@ impl Entity {
     }
 
     pub fn get_component<T: Component + Send + Sync + 'static>(&self) -> Option<&T> {
-        if let Some(component) = self.components.get(&TypeId::of::<T>()) {
+        if let Some((_, component)) = self.components.get(&TypeId::of::<T>()) {
             Some(component.downcast_ref::<T>().unwrap())
         } else {
             None
@@ -29,12 +64,12 @@ impl Entity {
         return self
             .components
             .values()
-            .filter_map(|component| component.downcast_ref::<T>())
+            .filter_map(|(_, component)| component.downcast_ref::<T>())
             .collect();
     }
 
     pub fn get_mut_component<T: Component + Send + Sync + 'static>(&mut self) -> Option<&mut T> {
-        if let Some(component) = self.components.get_mut(&TypeId::of::<T>()) {
+        if let Some((_, component)) = self.components.get_mut(&TypeId::of::<T>()) {
             Some(component.downcast_mut::<T>().unwrap())
     } else {
             None
@@ -45,12 +80,14 @@ impl Entity {
         return self
             .components
             .values_mut()
-            .filter_map(|component| component.downcast_mut::<T>())
+            .filter_map(|(_, component)| component.downcast_mut::<T>())
             .collect();
     }
 
     pub fn new() -> Self{
-        return Self { components: HashMap::new() };
+        return Self {
+            components: HashMap::new(),
+        };
     }
 
 }
@@ -1,37 +1,49 @@
 use ash::vk;
 use rand::Rng;
-use serde_json::Value;
 use std::{
     any::{Any, TypeId},
     collections::HashMap,
 };
 
 use lumina_core::device::Device;
+use lumina_files::id::EntityId;
 use lazy_static::lazy_static;
 
-static mut EXISTING_IDS: Vec<u32> = vec![];
-
-pub trait Component: Any + Send + Sync {
-    fn convert_to_json(&self,id:u32) -> Value {
-        serde_json::json!({})
-    }
-}
-
+/// Anything that can be attached to an entity. To be saved with scenes, a
+/// component also has to be registered in a [`crate::registry::ComponentRegistry`].
+pub trait Component: Any + Send + Sync {}
+
+/// A handle to an entity of a query, as returned when spawning it.
+///
+/// An entity is found through its row in the query (`index`), and a row
+/// is reused once its entity is killed. The `generation` of a row goes up
+/// every time that happens, so a handle to a killed entity is told apart
+/// from the entity reusing its row and is just ignored by the query.
+///
+/// The id of the entity (see [`GameObject::get_id`]) is what scenes and
+/// prefab instances refer to it with instead, it stays the same across
+/// saves and loads.
 #[derive(Debug,Clone)]
 pub struct GameObject {
-    id: u32,
+    index: u32,
+    generation: u32,
+    id: EntityId,
     tag: String,
     layer: String,
     name: String,
 }
 
 impl GameObject {
-    fn new(id: u32) -> Self {
+    /// A handle to the entity in row `index`. Handles are made by the query
+    /// holding the entity, which knows the generation of its rows.
+    pub fn new(index: u32, generation: u32, id: EntityId) -> Self {
         let layer = String::from("Default");
         let tag = String::from("Entity");
         let name = String::default();
 
         return Self {
+            index,
+            generation,
             id,
             layer,
             tag,
@@ -39,36 +51,40 @@ impl GameObject {
         };
     }
 
-    pub fn create_game_object() -> Self {
-        let mut rng = rand::thread_rng();
-
-        let mut random_id:u32 = rng.gen_range(0x0000_0001,0xFFFF_FFFF);
-
-        while unsafe { EXISTING_IDS.contains(&random_id) }{
-            random_id = rng.gen_range(0x0000_0001,0xFFFF_FFFF);
-        }
+    pub fn index(&self) -> u32 {
+        return self.index;
+    }
 
-        let game_object: GameObject = unsafe { GameObject::new(random_id) };
+    pub fn generation(&self) -> u32 {
+        return self.generation;
+    }
 
-        unsafe {
-            EXISTING_IDS.push(random_id);
-        }
+    /// The persistent id of the entity, the one scenes save it with.
+    pub fn get_id(&self) -> EntityId {
+        return self.id;
+    }
+}
 
-        return game_object;
+impl PartialEq for GameObject {
+    fn eq(&self, other: &Self) -> bool {
+        self.index == other.index && self.generation == other.generation
     }
+}
 
-    pub fn create_game_object_with_id(id:u32) -> Self {
-        let game_object: GameObject = unsafe { GameObject::new(id) };
+impl Eq for GameObject {}
 
-        unsafe {
-            EXISTING_IDS.push(id);
-        }
+/// A random (version 4) UUID for a new entity, one `taken` says no entity has.
+pub fn new_persistent_id(taken: impl Fn(EntityId) -> bool) -> EntityId {
+    let mut rng = rand::thread_rng();
 
-        return game_object;
-    }
+    loop {
+        let random: EntityId = rng.gen();
 
-    pub fn get_id(&self) -> u32 {
-        return self.id;
+        // Version 4 in the version nibble, RFC 4122 in the variant bits
+        let id = (random & !(0xF << 76) & !(0x3 << 62)) | (0x4 << 76) | (0x2 << 62);
+
+        if !taken(id) {
+            return id;
+        }
     }
 }
-
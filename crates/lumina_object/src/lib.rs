@@ -1,3 +1,6 @@
 pub mod entity;
 pub mod game_object;
+pub mod prefab;
+pub mod registry;
+pub mod storage;
 pub mod transform;
\ No newline at end of file
@@ -0,0 +1,103 @@
+use lumina_files::{
+    id::EntityId,
+    prefab::{PrefabFile, PrefabLink},
+    scene::{EntityRecord, SceneError},
+};
+
+use crate::{
+    entity::Entity,
+    game_object::Component,
+    registry::{ComponentContext, ComponentRegistry},
+};
+
+/// Added to every entity spawned from a prefab, so saving the scene only
+/// writes what the entity changed from its prefab.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PrefabInstance {
+    pub prefab: String,
+    /// Path of the entity inside the prefab, empty for the root.
+    pub path: String,
+    /// Id of the root entity of the instance.
+    pub instance: EntityId,
+}
+
+impl Component for PrefabInstance {}
+
+impl PrefabInstance {
+    pub fn to_link(&self) -> PrefabLink {
+        PrefabLink {
+            prefab: self.prefab.clone(),
+            path: self.path.clone(),
+            instance: self.instance,
+        }
+    }
+
+    pub fn from_link(link: &PrefabLink) -> Self {
+        Self {
+            prefab: link.prefab.clone(),
+            path: link.path.clone(),
+            instance: link.instance,
+        }
+    }
+}
+
+/// A prefab ready to be instantiated, with what its components need to be built.
+pub struct Prefab {
+    file: PrefabFile,
+    registry: ComponentRegistry,
+    context: ComponentContext,
+}
+
+impl Prefab {
+    pub fn new(file: PrefabFile, registry: &ComponentRegistry, context: ComponentContext) -> Self {
+        Self {
+            file,
+            registry: registry.clone(),
+            context,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.file.name
+    }
+
+    pub fn file(&self) -> &PrefabFile {
+        &self.file
+    }
+
+    /// Builds new entities for one instance, parents before their children,
+    /// each along with its path in the prefab. Unknown components are kept
+    /// like in scenes.
+    pub fn build(&self) -> Result<Vec<(String, Entity)>, SceneError> {
+        let mut entities = Vec::new();
+
+        for (path, node) in self.file.nodes() {
+            let record = EntityRecord {
+                id: 0,
+                prefab: None,
+                components: node.components.clone(),
+            };
+
+            let field = match path.is_empty() {
+                true => "root".to_string(),
+                false => path.clone(),
+            };
+
+            let (entity, _) = self
+                .registry
+                .load_record(&record, &field, &self.context)
+                .map_err(|err| match err {
+                    SceneError::InvalidField { field, message, .. } => SceneError::InvalidField {
+                        entity: None,
+                        field,
+                        message,
+                    },
+                    err => err,
+                })?;
+
+            entities.push((path, entity));
+        }
+
+        Ok(entities)
+    }
+}
@@ -0,0 +1,297 @@
+use std::{
+    any::{Any, TypeId},
+    collections::{BTreeMap, BTreeSet, HashMap},
+    sync::Arc,
+};
+
+use ash::vk;
+use image::DynamicImage;
+use lumina_core::device::Device;
+use lumina_files::{
+    asset::AssetStore,
+    file::LuminaFile,
+    id::EntityId,
+    mesh::MeshStore,
+    scene::{EntityRecord, SceneError},
+};
+use serde_json::Value;
+
+use crate::{
+    entity::Entity,
+    game_object::Component,
+    prefab::PrefabInstance,
+    storage::{ComponentInfo, EntityView},
+};
+
+/// What components may need to rebuild themselves when a scene is loaded.
+#[derive(Clone)]
+pub struct ComponentContext {
+    pub device: Arc<Device>,
+    pub render_pass: vk::RenderPass,
+    /// Number of lights in the scene, which models need to set up their shaders.
+    pub light_count: u64,
+    /// Meshes saved alongside the scene.
+    pub meshes: MeshStore,
+    /// Textures of the scene decoded ahead by their path, dropped with the
+    /// context once the scene is spawned.
+    pub textures: HashMap<String, DynamicImage>,
+    /// Files of the archive's `textures` directory by entry name, which
+    /// saved textures refer to.
+    pub packed_textures: HashMap<String, Arc<LuminaFile>>,
+}
+
+/// What components may write next to the scene when it is saved.
+#[derive(Default)]
+pub struct SaveContext {
+    /// Meshes to save in the archive's `meshes` directory.
+    pub meshes: MeshStore,
+    /// Textures the saved components use, to pack in the archive's `textures` directory.
+    pub textures: AssetStore,
+}
+
+type SerializeFn = Box<dyn Fn(&(dyn Any + Send + Sync), &mut SaveContext) -> Value + Send + Sync>;
+type DeserializeFn = Box<
+    dyn Fn(&Value, &ComponentContext) -> Result<Box<dyn Any + Send + Sync>, SceneError> + Send + Sync,
+>;
+
+struct Registration {
+    name: String,
+    info: ComponentInfo,
+    serialize: SerializeFn,
+    deserialize: DeserializeFn,
+}
+
+/// Components found in a saved scene without a registration, kept as they
+/// were so saving the scene again doesn't lose them.
+#[derive(Debug, Clone, Default)]
+pub struct UnknownComponents {
+    pub components: BTreeMap<String, Value>,
+}
+
+impl Component for UnknownComponents {}
+
+/// Maps component types to the stable name they are saved under and to the
+/// functions turning them into scene records and back.
+///
+/// Cloning is cheap, registrations are shared between clones.
+#[derive(Clone)]
+pub struct ComponentRegistry {
+    registrations: Vec<Arc<Registration>>,
+    by_type: HashMap<TypeId, usize>,
+    by_name: HashMap<String, usize>,
+}
+
+impl ComponentRegistry {
+    pub fn new() -> Self {
+        Self {
+            registrations: Vec::new(),
+            by_type: HashMap::new(),
+            by_name: HashMap::new(),
+        }
+    }
+
+    /// Registers `T` under `name`. The name ends up in saved scenes, so it
+    /// must never change once scenes have been saved with it.
+    ///
+    /// Panics if `T` or `name` are already registered.
+    pub fn register<T: Component>(
+        &mut self,
+        name: &str,
+        serialize: fn(&T, &mut SaveContext) -> Value,
+        deserialize: fn(&Value, &ComponentContext) -> Result<T, SceneError>,
+    ) {
+        assert!(
+            !self.by_type.contains_key(&TypeId::of::<T>()),
+            "{} is already registered",
+            std::any::type_name::<T>()
+        );
+        assert!(
+            !self.by_name.contains_key(name),
+            "a component is already registered as {}",
+            name
+        );
+
+        self.by_type.insert(TypeId::of::<T>(), self.registrations.len());
+        self.by_name.insert(name.to_string(), self.registrations.len());
+
+        self.registrations.push(Arc::new(Registration {
+            name: name.to_string(),
+            info: ComponentInfo::of::<T>(),
+            serialize: Box::new(move |component, context| {
+                serialize(component.downcast_ref::<T>().unwrap(), context)
+            }),
+            deserialize: Box::new(move |value, context| {
+                Ok(Box::new(deserialize(value, context)?) as Box<dyn Any + Send + Sync>)
+            }),
+        }));
+    }
+
+    pub fn is_registered(&self, name: &str) -> bool {
+        self.by_name.contains_key(name)
+    }
+
+    /// Name `T` was registered under.
+    pub fn name_of<T: Component>(&self) -> Option<&str> {
+        self.by_type
+            .get(&TypeId::of::<T>())
+            .map(|index| self.registrations[*index].name.as_str())
+    }
+
+    /// Saves every registered component of `entity`, plus the unknown ones it
+    /// was loaded with. Returns the type names of the components that aren't
+    /// registered and so couldn't be saved.
+    pub fn save_entity(
+        &self,
+        id: EntityId,
+        entity: &EntityView,
+        context: &mut SaveContext,
+    ) -> (EntityRecord, Vec<&'static str>) {
+        let mut record = EntityRecord {
+            id,
+            prefab: entity.get_component::<PrefabInstance>().map(|instance| instance.to_link()),
+            components: BTreeMap::new(),
+        };
+        let mut unregistered = Vec::new();
+
+        if let Some(unknown) = entity.get_component::<UnknownComponents>() {
+            record.components.extend(unknown.components.clone());
+        }
+
+        for (type_id, type_name, component) in entity.components() {
+            if type_id == TypeId::of::<UnknownComponents>() || type_id == TypeId::of::<PrefabInstance>() {
+                continue;
+            }
+
+            match self.by_type.get(&type_id) {
+                Some(index) => {
+                    let registration = &self.registrations[*index];
+                    record
+                        .components
+                        .insert(registration.name.clone(), (registration.serialize)(component, context));
+                }
+                None => unregistered.push(type_name),
+            }
+        }
+
+        (record, unregistered)
+    }
+
+    /// Rebuilds an entity from its record. `index` is the position of the
+    /// record in the scene, used to name the fields of invalid components.
+    ///
+    /// Components with no registration are kept in [`UnknownComponents`],
+    /// their names are returned alongside the entity. Records of prefab
+    /// instances have to be resolved first, see `PrefabLibrary::resolve`.
+    pub fn load_entity(
+        &self,
+        record: &EntityRecord,
+        index: usize,
+        context: &ComponentContext,
+    ) -> Result<(Entity, Vec<String>), SceneError> {
+        self.load_record(record, &format!("entities[{}]", index), context)
+    }
+
+    /// [`ComponentRegistry::load_entity`] for records that aren't in a scene,
+    /// `field` being where the record is in its document.
+    pub(crate) fn load_record(
+        &self,
+        record: &EntityRecord,
+        field: &str,
+        context: &ComponentContext,
+    ) -> Result<(Entity, Vec<String>), SceneError> {
+        let mut entity = Entity::new();
+        let mut unknown = UnknownComponents::default();
+
+        for (name, value) in record.components.iter() {
+            match self.by_name.get(name) {
+                Some(registration_index) => {
+                    let registration = &self.registrations[*registration_index];
+
+                    let component = (registration.deserialize)(value, context).map_err(|err| {
+                        err.within(record.id, &format!("{}.components.{}", field, name))
+                    })?;
+
+                    entity.insert_boxed(registration.info, component);
+                }
+                None => {
+                    unknown.components.insert(name.clone(), value.clone());
+                }
+            }
+        }
+
+        if let Some(link) = &record.prefab {
+            entity.add_component(PrefabInstance::from_link(link));
+        }
+
+        let unknown_names = unknown.components.keys().cloned().collect();
+
+        if !unknown.components.is_empty() {
+            entity.add_component(unknown);
+        }
+
+        Ok((entity, unknown_names))
+    }
+
+    /// Brings `entity` up to date with `record` for the components named in
+    /// `changed`: they are rebuilt, added or removed, while the other
+    /// components keep their runtime state. Nothing changes if a component
+    /// fails to build. `index` is the position of the record in the scene.
+    pub fn update_entity(
+        &self,
+        entity: &mut Entity,
+        record: &EntityRecord,
+        index: usize,
+        changed: &BTreeSet<String>,
+        context: &ComponentContext,
+    ) -> Result<(), SceneError> {
+        let mut updates = Vec::new();
+
+        for name in changed.iter() {
+            let registration = match self.by_name.get(name) {
+                Some(registration_index) => &self.registrations[*registration_index],
+                None => continue,
+            };
+
+            let component = match record.components.get(name) {
+                Some(value) => Some((registration.deserialize)(value, context).map_err(|err| {
+                    err.within(record.id, &format!("entities[{}].components.{}", index, name))
+                })?),
+                None => None,
+            };
+
+            updates.push((registration.info, component));
+        }
+
+        for (info, component) in updates {
+            match component {
+                Some(component) => entity.insert_boxed(info, component),
+                None => {
+                    entity.remove_boxed(info.type_id);
+                }
+            }
+        }
+
+        let unknown = UnknownComponents {
+            components: record
+                .components
+                .iter()
+                .filter(|(name, _)| !self.by_name.contains_key(*name))
+                .map(|(name, value)| (name.clone(), value.clone()))
+                .collect(),
+        };
+
+        entity.remove_boxed(TypeId::of::<UnknownComponents>());
+
+        if !unknown.components.is_empty() {
+            entity.add_component(unknown);
+        }
+
+        entity.remove_boxed(TypeId::of::<PrefabInstance>());
+
+        if let Some(link) = &record.prefab {
+            entity.add_component(PrefabInstance::from_link(link));
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,267 @@
+use std::any::{Any, TypeId};
+
+use crate::game_object::Component;
+
+/// A component type along with what it takes to store it, so components
+/// whose type is only known at runtime can still get a column of their own.
+#[derive(Debug, Clone, Copy)]
+pub struct ComponentInfo {
+    pub type_id: TypeId,
+    pub type_name: &'static str,
+    new_column: fn() -> Box<dyn ComponentColumn>,
+}
+
+impl ComponentInfo {
+    pub fn of<T: Component>() -> Self {
+        Self {
+            type_id: TypeId::of::<T>(),
+            type_name: std::any::type_name::<T>(),
+            new_column: || Box::new(SparseSet::<T>::new()),
+        }
+    }
+
+    /// An empty column for components of this type.
+    pub fn new_column(&self) -> Box<dyn ComponentColumn> {
+        (self.new_column)()
+    }
+}
+
+/// Every component of one type, whatever that type is. Entities are known
+/// by their row, see [`SparseSet`].
+pub trait ComponentColumn: Send + Sync {
+    fn info(&self) -> ComponentInfo;
+
+    fn len(&self) -> usize;
+
+    fn contains(&self, row: usize) -> bool;
+
+    fn get_any(&self, row: usize) -> Option<&(dyn Any + Send + Sync)>;
+
+    /// Sets the component of `row`. Panics if `component` isn't of the column's type.
+    fn insert_any(&mut self, row: usize, component: Box<dyn Any + Send + Sync>);
+
+    fn remove_any(&mut self, row: usize) -> Option<Box<dyn Any + Send + Sync>>;
+
+    fn as_any(&self) -> &dyn Any;
+
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+/// Marks a row without a component in [`SparseSet::sparse`].
+const NO_VALUE: u32 = u32::MAX;
+
+/// Components of type `T` packed next to each other, so going through all
+/// of them is a walk over a `Vec`, while the component of a given row is
+/// still found in constant time.
+///
+/// Removing a component moves the last one in its place, so the order of
+/// the components changes as they come and go.
+#[derive(Debug, Clone)]
+pub struct SparseSet<T> {
+    /// Position in `values` of the component of each row, [`NO_VALUE`] if it has none.
+    sparse: Vec<u32>,
+    /// Row of each component of `values`.
+    rows: Vec<usize>,
+    values: Vec<T>,
+}
+
+impl<T> SparseSet<T> {
+    pub fn new() -> Self {
+        Self {
+            sparse: Vec::new(),
+            rows: Vec::new(),
+            values: Vec::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    pub fn contains(&self, row: usize) -> bool {
+        self.position(row).is_some()
+    }
+
+    pub fn get(&self, row: usize) -> Option<&T> {
+        self.position(row).map(|position| &self.values[position])
+    }
+
+    pub fn get_mut(&mut self, row: usize) -> Option<&mut T> {
+        self.position(row).map(|position| &mut self.values[position])
+    }
+
+    /// Sets the component of `row`, returning the one it replaces.
+    pub fn insert(&mut self, row: usize, value: T) -> Option<T> {
+        if let Some(position) = self.position(row) {
+            return Some(std::mem::replace(&mut self.values[position], value));
+        }
+
+        if row >= self.sparse.len() {
+            self.sparse.resize(row + 1, NO_VALUE);
+        }
+
+        self.sparse[row] = self.values.len() as u32;
+        self.rows.push(row);
+        self.values.push(value);
+
+        None
+    }
+
+    pub fn remove(&mut self, row: usize) -> Option<T> {
+        let position = self.position(row)?;
+
+        self.sparse[row] = NO_VALUE;
+        self.rows.swap_remove(position);
+        let value = self.values.swap_remove(position);
+
+        if let Some(moved_row) = self.rows.get(position) {
+            self.sparse[*moved_row] = position as u32;
+        }
+
+        Some(value)
+    }
+
+    /// Rows having a component, in the same order as [`SparseSet::values`].
+    pub fn rows(&self) -> &[usize] {
+        &self.rows
+    }
+
+    pub fn values(&self) -> &[T] {
+        &self.values
+    }
+
+    pub fn values_mut(&mut self) -> &mut [T] {
+        &mut self.values
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &T)> {
+        self.rows.iter().copied().zip(self.values.iter())
+    }
+
+    /// Borrows what finds the component of a row apart from the components
+    /// themselves, so components can be handed out mutably one row at a time.
+    pub fn split_mut(&mut self) -> (SparseIndex<'_>, &mut [T]) {
+        let index = SparseIndex {
+            sparse: &self.sparse,
+            rows: &self.rows,
+        };
+
+        (index, &mut self.values)
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (usize, &mut T)> {
+        self.rows.iter().copied().zip(self.values.iter_mut())
+    }
+
+    fn position(&self, row: usize) -> Option<usize> {
+        let index = SparseIndex {
+            sparse: &self.sparse,
+            rows: &self.rows,
+        };
+
+        index.position(row)
+    }
+}
+
+/// Where the components of a [`SparseSet`] are, see [`SparseSet::split_mut`].
+#[derive(Debug, Clone, Copy)]
+pub struct SparseIndex<'a> {
+    sparse: &'a [u32],
+    rows: &'a [usize],
+}
+
+impl<'a> SparseIndex<'a> {
+    /// Position of the component of `row` among the set's values.
+    pub fn position(&self, row: usize) -> Option<usize> {
+        match self.sparse.get(row) {
+            Some(&position) if position != NO_VALUE => Some(position as usize),
+            _ => None,
+        }
+    }
+
+    pub fn rows(&self) -> &'a [usize] {
+        self.rows
+    }
+}
+
+impl<T> Default for SparseSet<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Component> ComponentColumn for SparseSet<T> {
+    fn info(&self) -> ComponentInfo {
+        ComponentInfo::of::<T>()
+    }
+
+    fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    fn contains(&self, row: usize) -> bool {
+        SparseSet::contains(self, row)
+    }
+
+    fn get_any(&self, row: usize) -> Option<&(dyn Any + Send + Sync)> {
+        self.get(row).map(|value| value as &(dyn Any + Send + Sync))
+    }
+
+    fn insert_any(&mut self, row: usize, component: Box<dyn Any + Send + Sync>) {
+        match component.downcast::<T>() {
+            Ok(component) => {
+                self.insert(row, *component);
+            }
+            Err(_) => panic!("not a component of type {}", std::any::type_name::<T>()),
+        }
+    }
+
+    fn remove_any(&mut self, row: usize) -> Option<Box<dyn Any + Send + Sync>> {
+        self.remove(row)
+            .map(|value| Box::new(value) as Box<dyn Any + Send + Sync>)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+/// The components of one entity, borrowed from an [`crate::entity::Entity`]
+/// or from wherever a query stores them.
+pub struct EntityView<'a> {
+    components: Vec<(ComponentInfo, &'a (dyn Any + Send + Sync))>,
+}
+
+impl<'a> EntityView<'a> {
+    pub fn new() -> Self {
+        Self {
+            components: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, info: ComponentInfo, component: &'a (dyn Any + Send + Sync)) {
+        self.components.push((info, component));
+    }
+
+    /// Every component of the entity along with its type, in no particular order.
+    pub fn components(&self) -> impl Iterator<Item = (TypeId, &'static str, &'a (dyn Any + Send + Sync))> + '_ {
+        self.components
+            .iter()
+            .map(|(info, component)| (info.type_id, info.type_name, *component))
+    }
+
+    pub fn get_component<T: Component>(&self) -> Option<&'a T> {
+        self.components
+            .iter()
+            .find(|(info, _)| info.type_id == TypeId::of::<T>())
+            .and_then(|(_, component)| component.downcast_ref::<T>())
+    }
+}
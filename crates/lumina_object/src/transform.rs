@@ -1,3 +1,5 @@
+use lumina_files::scene::TransformRecord;
+
 use super::game_object::Component;
 
 #[derive(Debug,Clone, Copy)]
@@ -76,6 +78,22 @@ impl Transform {
         );
     }
 
+    pub fn to_record(&self) -> TransformRecord {
+        TransformRecord {
+            translation: self.translation.to_array(),
+            rotation: self.rotation.to_array(),
+            scale: self.scale.to_array(),
+        }
+    }
+
+    pub fn from_record(record: &TransformRecord) -> Self {
+        Self {
+            translation: glam::Vec3::from_array(record.translation),
+            rotation: glam::Vec3::from_array(record.rotation),
+            scale: glam::Vec3::from_array(record.scale),
+        }
+    }
+
     pub fn default() -> Self {
         return Self {
             translation: glam::Vec3::default(),
@@ -85,13 +103,4 @@ impl Transform {
     }
 }
 
-impl Component for Transform {
-    fn convert_to_json(&self,id:u32) -> serde_json::Value {
-        serde_json::json!({
-            "id": id,
-            "transform": self.translation.to_array(),
-            "rotation": self.rotation.to_array(),
-            "scale": self.scale.to_array()
-        })
-    }
-}
+impl Component for Transform {}
@@ -1,7 +1,8 @@
 use lazy_static::*;
 use lumina_files::{
-    loader::{Loader, LuminaFile},
-    saver::LuminaFileType,
+    error::LuminaFileError,
+    file::{LuminaFile, LuminaFileType},
+    loader::{ArchiveEntries, Loader},
 };
 use std::{
     collections::HashMap,
@@ -39,6 +40,15 @@ impl Path {
     pub fn is_raw_path(&self) -> bool {
         self.raw
     }
+
+    /// Path of a file packed in an archive as `file_name`, with no file on disk.
+    pub fn packed(file_name: &str) -> Self {
+        Self {
+            new_path: file_name.to_string(),
+            raw_path: String::new(),
+            raw: false,
+        }
+    }
 }
 
 impl Default for Path {
@@ -95,16 +105,9 @@ pub fn get_new_image(file_path: &str, loader: Arc<RwLock<Loader>>) -> Option<Pat
     }
 }
 
-pub fn load_image(file_name: &str, loader: Loader) -> LuminaFile {
-    loader
-        .directories
-        .get("textures")
-        .unwrap()
-        .files
-        .iter()
-        .find(|file| file_name == file.file_name)
-        .unwrap()
-        .clone()
+/// Borrows a packed texture, decoding it only if the archive hasn't cached it yet.
+pub fn load_image<L: ArchiveEntries + ?Sized>(file_name: &str, loader: &L) -> Result<Arc<LuminaFile>, LuminaFileError> {
+    loader.get_entry(&format!("textures/{}", file_name))
 }
 
 pub fn get_raw_model(file_path: &str) -> Option<Path> {
@@ -124,14 +127,6 @@ pub fn get_raw_model(file_path: &str) -> Option<Path> {
     }
 }
 
-pub fn get_scene_data(file_name: &str, loader: Loader) -> LuminaFile {
-    loader
-        .directories
-        .get("sceneData")
-        .unwrap()
-        .files
-        .iter()
-        .find(|file| file_name == file.file_name)
-        .unwrap()
-        .clone()
+pub fn get_scene_data<L: ArchiveEntries + ?Sized>(file_name: &str, loader: &L) -> Result<Arc<LuminaFile>, LuminaFileError> {
+    loader.get_entry(&format!("sceneData/{}", file_name))
 }
@@ -1,4 +1,5 @@
 use lumina_core::RawLight;
+use lumina_files::{id::EntityId, scene::LightRecord};
 use lumina_object::{game_object::Component, transform::Transform};
 
 #[repr(u32)]
@@ -28,7 +29,7 @@ impl Light {
         }
     }
 
-    pub fn create_raw_light(&self,id:&u32,transform:&Transform) -> RawLight {
+    pub fn create_raw_light(&self,id:&EntityId,transform:&Transform) -> RawLight {
         let linear = 1.2833333333333333333333333333333 + ((-0.05833333333333333333333333333333) * self.range);
         let quadratic = 2.0888888888888888888888888888888 + ((-0.04074074074074074074074074074074) * self.range);
 
@@ -46,6 +47,29 @@ impl Light {
         }
     }
 
+    pub fn to_record(&self) -> LightRecord {
+        LightRecord {
+            light_type: self.light_type as u32,
+            color: self.color,
+            intensity: self.intensity,
+            range: self.range,
+            spot_size: self.spot_size,
+        }
+    }
+
+    /// Builds a light from a record checked by `LightRecord::validate`, which rejects unknown light types.
+    pub fn from_record(record: &LightRecord) -> Self {
+        let mut light = Light::new();
+
+        light.change_light_type(record.light_type);
+        light.change_color(glam::Vec3::from_array(record.color));
+        light.change_intensity(record.intensity);
+        light.change_range(record.range);
+        light.change_spot_size(record.spot_size);
+
+        light
+    }
+
     pub fn change_color(&mut self, new_color: glam::Vec3) {
         self.color = new_color.to_array();
     }
@@ -67,15 +91,4 @@ impl Light {
     }
 }
 
-impl Component for Light {
-    fn convert_to_json(&self,id:u32) -> serde_json::Value {
-        serde_json::json!({
-            "id": id,
-            "light_type": self.light_type as u32,
-            "color": self.color.to_vec(),
-            "intensity": self.intensity,
-            "range": self.range,
-            "spot_size": self.spot_size
-        })
-    }
-}
\ No newline at end of file
+impl Component for Light {}
\ No newline at end of file
@@ -1,4 +1,7 @@
+use std::{collections::HashMap, sync::Arc};
+
 use lumina_core::texture::Texture;
+use lumina_files::{file::LuminaFile, scene::MaterialRecord};
 use lumina_graphic::shader::Shader;
 use lumina_object::game_object::Component;
 
@@ -64,6 +67,36 @@ impl Material {
         }
     }
 
+    /// `parent_id` is the index of the mesh the material is bound to.
+    pub fn to_record(&self, parent_id: usize) -> MaterialRecord {
+        MaterialRecord {
+            parent_id,
+            ambient: self.ambient.to_array(),
+            ambient_texture: self.ambient_texture.get_path(),
+            diffuse: self.diffuse.to_array(),
+            metallic: self.metallic.to_array(),
+            metallic_texture: self.metallic_texture.get_path(),
+            normal_texture: self.normal_texture.get_path(),
+        }
+    }
+
+    /// Textures are looked up by name in `packed` first, the files of the
+    /// archive the record was loaded from, and read from disk otherwise.
+    pub fn from_record(record: &MaterialRecord, packed: &HashMap<String, Arc<LuminaFile>>) -> Self {
+        let mut material = Material::new(
+            glam::Vec3::from_array(record.ambient),
+            glam::Vec3::from_array(record.diffuse),
+            glam::Vec3::from_array(record.metallic),
+            1.0,
+        );
+
+        material.ambient_texture = texture_from_record(&record.ambient_texture, packed);
+        material.metallic_texture = texture_from_record(&record.metallic_texture, packed);
+        material.normal_texture = texture_from_record(&record.normal_texture, packed);
+
+        material
+    }
+
     pub fn mix(material1: Material, material2: Material, percentage: f32) -> Material {
         Material {
             ambient: material1.ambient * percentage + material2.ambient * (1.0 - percentage),
@@ -90,3 +123,10 @@ impl Material {
         }
     }
 }
+
+fn texture_from_record(texture: &str, packed: &HashMap<String, Arc<LuminaFile>>) -> Texture {
+    match packed.get(texture) {
+        Some(file) => Texture::new_packed(Arc::clone(file)),
+        None => Texture::new_raw(texture),
+    }
+}
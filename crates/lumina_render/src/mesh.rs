@@ -4,7 +4,7 @@ use ash::vk;
 
 use lumina_data::buffer::Buffer;
 use lumina_core::{device::Device, Vertex3D};
-use serde_json::Value;
+use lumina_files::scene::{MeshRecord, VertexRecord};
 use crate::offset_of;
 
 #[derive(Clone, Copy)]
@@ -203,25 +203,32 @@ impl Mesh {
         return (attribute_descriptions, binding_descriptions);
     }
 
-    pub fn to_json(&self,id:usize) -> Value {
-        let mut json = serde_json::json!({
-            "vertices": [],
-            "indices": []
-        });
-
-        for vertice in self.vertex_array.iter() {
-            json["vertices"].as_array_mut().unwrap().push(serde_json::json!({
-                "id":id,
-                "position": vertice.position.to_array(),
-                "normal": vertice.normal.to_array(),
-                "uv": vertice.uv.to_array()
-            }))
-        }
-
-        for index in self.index_array.iter() {
-            json["indices"].as_array_mut().unwrap().push(serde_json::json!(index))
+    pub fn to_record(&self) -> MeshRecord {
+        MeshRecord {
+            vertices: self
+                .vertex_array
+                .iter()
+                .map(|vertex| VertexRecord {
+                    position: vertex.position.to_array(),
+                    normal: vertex.normal.to_array(),
+                    uv: vertex.uv.to_array(),
+                })
+                .collect(),
+            indices: self.index_array.clone(),
         }
+    }
 
-        json
+    pub fn from_record(device: Arc<Device>, record: &MeshRecord) -> Self {
+        let vertices = record
+            .vertices
+            .iter()
+            .map(|vertex| Vertex3D {
+                position: glam::Vec3::from_array(vertex.position),
+                normal: glam::Vec3::from_array(vertex.normal),
+                uv: glam::Vec2::from_array(vertex.uv),
+            })
+            .collect();
+
+        Mesh::new(device, vertices, record.indices.clone())
     }
 }
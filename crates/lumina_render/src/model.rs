@@ -1,4 +1,5 @@
 use std::{
+    borrow::Cow,
     collections::HashMap,
     hash::Hash,
     rc::Rc,
@@ -7,16 +8,20 @@ use std::{
 
 use ash::vk;
 
-use image::{DynamicImage, ImageBuffer};
+use image::{DynamicImage, GenericImageView, ImageBuffer};
 use lumina_atlas::atlas::Atlas;
 use lumina_core::{device::Device, texture::Texture, RawLight, Vertex3D};
 use lumina_data::descriptor_manager::CurValue;
-use lumina_files::loader::{Loader, LuminaFile};
+use lumina_files::{
+    file::LuminaFile,
+    loader::Loader,
+    mesh::MeshStore,
+    scene::{ImportSettings, ModelRecord, SceneError},
+};
 use lumina_graphic::shader::Shader;
 use lumina_object::game_object::{Component, GameObject};
 use lumina_pbr::material::Material;
 use russimp::scene::{PostProcess, Scene};
-use serde_json::Value;
 
 use crate::mesh::{Mesh, Vertex};
 
@@ -29,6 +34,9 @@ pub struct Model {
     device: Arc<Device>,
     pub meshes: Vec<Mesh>,
     pub file_path: String,
+    /// Set when the model was imported from `file_path`, in which case saved
+    /// scenes import it again instead of storing its meshes.
+    pub import_settings: Option<ImportSettings>,
     pub materials: Vec<Material>,
     pub mesh_material_bindings: HashMap<usize, usize>,
     pub shader: Shader,
@@ -59,6 +67,7 @@ impl Model {
             device: Arc::clone(&device),
             meshes: vec![],
             file_path: String::default(),
+            import_settings: None,
             mesh_material_bindings,
             materials: vec![],
             shader,
@@ -96,6 +105,7 @@ impl Model {
             device: Arc::clone(&device),
             meshes: vec![mesh],
             file_path: String::default(),
+            import_settings: None,
             mesh_material_bindings,
             materials: vec![],
             shader,
@@ -104,17 +114,23 @@ impl Model {
     }
 
     pub fn new_from_model(device: Arc<Device>, file_path: &str) -> Self {
-        let scene = Scene::from_file(
-            file_path,
-            vec![
-                PostProcess::CalculateTangentSpace,
-                PostProcess::Triangulate,
-                PostProcess::JoinIdenticalVertices,
-                PostProcess::SortByPrimitiveType,
-                PostProcess::FlipUVs,
-            ],
-        )
-        .unwrap();
+        Model::new_from_model_with(device, file_path, ImportSettings::default())
+    }
+
+    pub fn new_from_model_with(device: Arc<Device>, file_path: &str, settings: ImportSettings) -> Self {
+        let mut post_process = vec![PostProcess::Triangulate, PostProcess::SortByPrimitiveType];
+
+        if settings.calculate_tangent_space {
+            post_process.push(PostProcess::CalculateTangentSpace);
+        }
+        if settings.join_identical_vertices {
+            post_process.push(PostProcess::JoinIdenticalVertices);
+        }
+        if settings.flip_uvs {
+            post_process.push(PostProcess::FlipUVs);
+        }
+
+        let scene = Scene::from_file(file_path, post_process).unwrap();
 
         let mut meshes = Vec::new();
 
@@ -173,6 +189,7 @@ impl Model {
             device: Arc::clone(&device),
             meshes,
             file_path: file_path.to_string(),
+            import_settings: Some(settings),
             materials: vec![],
             shader,
             mesh_material_bindings: HashMap::new(),
@@ -180,27 +197,141 @@ impl Model {
         }
     }
 
+    /// Saves the model, adding its meshes to `meshes` unless it is imported from a file.
+    pub fn to_record(&self, meshes: &mut MeshStore) -> ModelRecord {
+        let materials = self
+            .materials
+            .iter()
+            .enumerate()
+            .map(|(material_id, material)| {
+                let mut parent_id = 0;
+
+                for (mesh_id, bound_material_id) in self.mesh_material_bindings.iter() {
+                    if *bound_material_id == material_id {
+                        parent_id = *mesh_id;
+                    }
+                }
+
+                material.to_record(parent_id)
+            })
+            .collect();
+
+        let import = match self.import_settings {
+            Some(settings) if !self.file_path.is_empty() => Some(settings),
+            _ => None,
+        };
+
+        let mesh_ids = match import {
+            Some(_) => Vec::new(),
+            None => self
+                .meshes
+                .iter()
+                .map(|mesh| meshes.insert(&mesh.to_record()))
+                .collect(),
+        };
+
+        ModelRecord {
+            file_path: self.file_path.clone(),
+            import,
+            materials,
+            mesh_ids,
+            meshes: Vec::new(),
+        }
+    }
+
+    /// Rebuilds the meshes and materials of a saved model, `init_model` still has to be called on it.
+    /// `textures` are the packed textures of the archive the model was saved in, by entry name.
+    pub fn from_record(
+        device: Arc<Device>,
+        record: &ModelRecord,
+        meshes: &MeshStore,
+        textures: &HashMap<String, Arc<LuminaFile>>,
+    ) -> Result<Self, SceneError> {
+        let mut model = match record.import {
+            Some(settings) => {
+                if !std::path::Path::new(&record.file_path).exists() {
+                    return Err(SceneError::InvalidField {
+                        entity: None,
+                        field: "file".to_string(),
+                        message: format!("{} doesn't exist", record.file_path),
+                    });
+                }
+
+                Model::new_from_model_with(Arc::clone(&device), &record.file_path, settings)
+            }
+            None => {
+                let mut model = Model::new(Arc::clone(&device));
+                model.file_path = record.file_path.clone();
+
+                for mesh in record.meshes.iter() {
+                    model.meshes.push(Mesh::from_record(Arc::clone(&device), mesh));
+                }
+
+                for (index, id) in record.mesh_ids.iter().enumerate() {
+                    let mesh = meshes.get(id).map_err(|err| SceneError::InvalidField {
+                        entity: None,
+                        field: format!("mesh_ids[{}]", index),
+                        message: err.to_string(),
+                    })?;
+
+                    model.meshes.push(Mesh::from_record(Arc::clone(&device), &mesh));
+                }
+
+                model
+            }
+        };
+
+        for (material_id, material) in record.materials.iter().enumerate() {
+            model.mesh_material_bindings.insert(material.parent_id, material_id);
+            model.materials.push(Material::from_record(material, textures));
+        }
+
+        Ok(model)
+    }
+
     pub fn init_model(
         &mut self,
         renderpass: vk::RenderPass,
         light_count: u64,
     ) {
+        self.init_model_with(renderpass, light_count, &HashMap::new());
+    }
+
+    /// Like `init_model`, but textures found in `textures` by their path (see
+    /// `Texture::get_path`) are used as they are instead of being decoded again.
+    pub fn init_model_with(
+        &mut self,
+        renderpass: vk::RenderPass,
+        light_count: u64,
+        textures: &HashMap<String, DynamicImage>,
+    ) {
+        let material = &self.materials[0];
+        let [color, normal, specular] = [
+            &material.ambient_texture,
+            &material.normal_texture,
+            &material.metallic_texture,
+        ]
+        .map(|texture| match textures.get(&texture.get_path()) {
+            Some(image) => Cow::Borrowed(image),
+            None => Cow::Owned(texture.create_texture()),
+        });
+
         self.shader.create_pipeline_layout(true);
         self.shader.create_pipeline(renderpass);
         self.shader.descriptor_manager.change_image_size(
             "colorMap",
-            self.materials[0].ambient_texture.get_texture_info().0,
-            self.materials[0].ambient_texture.get_texture_info().1,
+            color.dimensions().0,
+            color.dimensions().1,
         );
         self.shader.descriptor_manager.change_image_size(
             "normalMap",
-            self.materials[0].normal_texture.get_texture_info().0,
-            self.materials[0].normal_texture.get_texture_info().1,
+            normal.dimensions().0,
+            normal.dimensions().1,
         );
         self.shader.descriptor_manager.change_image_size(
             "specularMap",
-            self.materials[0].metallic_texture.get_texture_info().0,
-            self.materials[0].metallic_texture.get_texture_info().1,
+            specular.dimensions().0,
+            specular.dimensions().1,
         );
         self.shader
             .descriptor_manager
@@ -211,9 +342,9 @@ impl Model {
         unsafe { self.device.device().device_wait_idle().unwrap() };
 
 
-        self.shader.descriptor_manager.change_image_value("colorMap", &self.materials[0].ambient_texture.create_texture());
-        self.shader.descriptor_manager.change_image_value("normalMap", &self.materials[0].normal_texture.create_texture());
-        self.shader.descriptor_manager.change_image_value("specularMap", &self.materials[0].metallic_texture.create_texture());
+        self.shader.descriptor_manager.change_image_value("colorMap", color.as_ref());
+        self.shader.descriptor_manager.change_image_value("normalMap", normal.as_ref());
+        self.shader.descriptor_manager.change_image_value("specularMap", specular.as_ref());
 
     }
 
@@ -399,48 +530,7 @@ impl Drop for Model {
     }
 }
 
-impl Component for Model {
-    fn convert_to_json(&self, id: u32) -> Value {
-        let mut json = serde_json::json!({
-            "id": id,
-            "file": self.file_path,
-            "materials": [],
-            "meshes": [],
-        });
-
-        for (mat_id, material) in self.materials.iter().enumerate() {
-            let mut id = 0;
-
-            for (m_id, m_key) in self.mesh_material_bindings.iter() {
-                if *m_key == mat_id {
-                    id = *m_id;
-                }
-            }
-
-            json["materials"]
-                .as_array_mut()
-                .unwrap()
-                .push(serde_json::json!({
-                    "parent_id": id,
-                    "ambient": material.ambient.to_array(),
-                    "ambient_texture": material.ambient_texture.get_raw_path(),
-                    "diffuse": material.diffuse.to_array(),
-                    "metallic": material.metallic.to_array(),
-                    "metallic_texture": material.metallic_texture.get_raw_path(),
-                    "normal_texture": material.normal_texture.get_raw_path()
-                }));
-        }
-
-        for (id, mesh) in self.meshes.iter().enumerate() {
-            json["meshes"]
-                .as_array_mut()
-                .unwrap()
-                .push(mesh.to_json(id));
-        }
-
-        return json;
-    }
-}
+impl Component for Model {}
 
 unsafe impl Send for Model {}
 
@@ -19,7 +19,12 @@ use egui::{epaint::Primitive, CentralPanel, ColorImage, Frame, ImageData, Key, S
 use glsl_parser::parser::Parser;
 use image::{DynamicImage, ImageBuffer, Luma, Rgba};
 use lumina_atlas::atlas::Atlas;
-use lumina_ecs::{app::App, query::Query, stage::Stage};
+use lumina_ecs::{
+    app::App,
+    query::Query,
+    schedule::{System, SystemStage},
+    stage::Stage,
+};
 use lumina_pbr::light::Light;
 use nfd::Response;
 use rand::Rng;
@@ -98,6 +103,44 @@ struct LightInfo {
     light: RawLight,
 }
 
+/// Seconds the last frame took, as a resource of the stage.
+struct DeltaTime(f32);
+
+/// Moves the camera with the keyboard and turns it with the mouse.
+fn camera_controller() -> System {
+    System::new("camera_controller", |_, resources| {
+        let keyboard = resources.get::<Keyboard>().unwrap();
+        let mut mouse = resources.get_mut::<Mouse>().unwrap();
+        let mut camera = resources.get_mut::<Camera>().unwrap();
+        let delta_time = resources.get::<DeltaTime>().unwrap().0;
+
+        if keyboard.get_key(Keycode::W) {
+            camera.update_position(CameraDirection::FORWARD, delta_time);
+        }
+        if keyboard.get_key(Keycode::S) {
+            camera.update_position(CameraDirection::BACKWARD, delta_time);
+        }
+        if keyboard.get_key(Keycode::D) {
+            camera.update_position(CameraDirection::RIGHT, delta_time);
+        }
+        if keyboard.get_key(Keycode::A) {
+            camera.update_position(CameraDirection::LEFT, delta_time);
+        }
+        if keyboard.get_key(Keycode::Space) {
+            camera.update_position(CameraDirection::UP, delta_time);
+        }
+        if keyboard.get_key(Keycode::LCtrl) {
+            camera.update_position(CameraDirection::DOWN, delta_time);
+        }
+
+        camera.update_direction(mouse.get_dx(), mouse.get_dy(), delta_time);
+    })
+    .read_resource::<Keyboard>()
+    .write_resource::<Mouse>()
+    .write_resource::<Camera>()
+    .read_resource::<DeltaTime>()
+}
+
 fn print_type_id<T: std::any::Any>(value: &T) {
     let type_id = TypeId::of::<T>();
     println!("Type ID: {:?}", type_id);
@@ -158,28 +201,14 @@ fn main() {
     let low_poly: GameObject = stage.manager.spawn();
 
     stage.manager.push(&low_poly, model);
-    if let Some(transform) = stage
-        .manager
-        .query_entity(&low_poly)
-        .unwrap()
-        .write()
-        .unwrap()
-        .get_mut_component::<Transform>()
-    {
+    if let Some(mut transform) = stage.manager.get_mut::<Transform>(&low_poly) {
         transform.translation = glam::Vec3::new(0.0, 0.0, 5.0);
         transform.scale = glam::vec3(2.0, 2.0, 2.0);
         let angle = 20.0 * 0 as f32;
         transform.rotation = glam::vec3(-180.0, angle, angle);
     }
 
-    if let Some(model) = stage
-        .manager
-        .query_entity(&low_poly)
-        .unwrap()
-        .write()
-        .unwrap()
-        .get_mut_component::<Model>()
-    {
+    if let Some(mut model) = stage.manager.get_mut::<Model>(&low_poly) {
         let mut material = lumina_pbr::material::Material::default();
 
         material.ambient = glam::vec3(0.0, 0.0, 0.0);
@@ -225,28 +254,14 @@ fn main() {
 
     stage.manager.push(&cube, model);
 
-    if let Some(transform) = stage
-        .manager
-        .query_entity(&cube)
-        .unwrap()
-        .write()
-        .unwrap()
-        .get_mut_component::<Transform>()
-    {
+    if let Some(mut transform) = stage.manager.get_mut::<Transform>(&cube) {
         transform.translation = glam::Vec3::new(0.0, 0.0, 5.0);
         transform.scale = glam::vec3(100.0, 0.0, 100.0);
         let angle = 20.0 * 0 as f32;
         transform.rotation = glam::vec3(0.0, angle, angle);
     }
 
-    if let Some(model) = stage
-        .manager
-        .query_entity(&cube)
-        .unwrap()
-        .write()
-        .unwrap()
-        .get_mut_component::<Model>()
-    {
+    if let Some(mut model) = stage.manager.get_mut::<Model>(&cube) {
         let mut material = lumina_pbr::material::Material::default();
 
         material.ambient = glam::vec3(0.0, 0.0, 0.0);
@@ -292,14 +307,7 @@ fn main() {
     //let texture = tex_texture.create_texture();
     //let paving = tex_paving.create_texture();
 
-    if let Some(model) = stage
-        .manager
-        .query_entity(&low_poly)
-        .unwrap()
-        .write()
-        .unwrap()
-        .get_mut_component::<Model>()
-    {
+    if let Some(mut model) = stage.manager.get_mut::<Model>(&low_poly) {
         model
             .shader
             .descriptor_manager
@@ -318,14 +326,7 @@ fn main() {
     let normal = brickwall_normal.create_texture();
     let specular = brickwall_specular.create_texture();
 
-    if let Some(model) = stage
-        .manager
-        .query_entity(&cube)
-        .unwrap()
-        .write()
-        .unwrap()
-        .get_mut_component::<Model>()
-    {
+    if let Some(mut model) = stage.manager.get_mut::<Model>(&cube) {
         model
             .shader
             .descriptor_manager
@@ -342,14 +343,7 @@ fn main() {
    
     let light_2 = stage.manager.spawn();
 
-    if let Some(transform) = stage
-        .manager
-        .query_entity(&light_2)
-        .unwrap()
-        .write()
-        .unwrap()
-        .get_mut_component::<Transform>()
-    {
+    if let Some(mut transform) = stage.manager.get_mut::<Transform>(&light_2) {
         transform.translation = glam::Vec3::new(8.0, -0.0, 0.0);
         transform.rotation = glam::vec3(-0.6, 90.0, -5.9);
     }
@@ -367,14 +361,7 @@ fn main() {
 
     let light_1 = stage.manager.spawn();
 
-    if let Some(transform) = stage
-        .manager
-        .query_entity(&light_1)
-        .unwrap()
-        .write()
-        .unwrap()
-        .get_mut_component::<Transform>()
-    {
+    if let Some(mut transform) = stage.manager.get_mut::<Transform>(&light_1) {
         transform.translation = glam::Vec3::new(-8.0, -0.0, 0.0);
         transform.rotation = glam::vec3(-0.6, 90.0, -5.9);
     }
@@ -395,6 +382,12 @@ fn main() {
 
     camera.speed = 10.0;
 
+    stage.insert_resource(camera);
+    stage.insert_resource(keyboard_pool);
+    stage.insert_resource(mouse_pool);
+    stage.insert_resource(DeltaTime(0.0));
+    stage.add_system(SystemStage::Update, camera_controller());
+
     let mut fps = FPS::new();
     fps._fps = 300;
     let mut global_timer = Instant::now();
@@ -403,65 +396,61 @@ fn main() {
     fps.fps_limit = Duration::new(0, 1000000000u32 / fps._fps);
     let mut delta_time = 1.0 / fps._fps as f32;
 
+    stage.insert_resource(fps);
+
     thread::sleep(Duration::from_secs(1));
 
     'running: loop {
-        delta_time = 1.0 / ((fps.frame_count / fps.frame_elapsed) as f32);
+        delta_time = {
+            let fps = stage.resource::<FPS>().unwrap();
+            1.0 / ((fps.frame_count / fps.frame_elapsed) as f32)
+        };
 
         start_tick = Instant::now();
 
-        for event in event_pump.poll_iter() {
-            match event {
-                Event::Quit { .. } => break 'running,
-                Event::KeyDown {
-                    keycode: Some(keycode),
-                    ..
-                } => {
-                    keyboard_pool.change_key_down(keycode as u32);
-                }
-                Event::KeyUp {
-                    keycode: Some(keycode),
-                    ..
-                } => {
-                    keyboard_pool.change_key_up(keycode as u32);
+        let take_screenshot = {
+            let mut keyboard_pool = stage.resource_mut::<Keyboard>().unwrap();
+            let mut mouse_pool = stage.resource_mut::<Mouse>().unwrap();
+
+            for event in event_pump.poll_iter() {
+                match event {
+                    Event::Quit { .. } => break 'running,
+                    Event::KeyDown {
+                        keycode: Some(keycode),
+                        ..
+                    } => {
+                        keyboard_pool.change_key_down(keycode as u32);
+                    }
+                    Event::KeyUp {
+                        keycode: Some(keycode),
+                        ..
+                    } => {
+                        keyboard_pool.change_key_up(keycode as u32);
+                    }
+                    Event::MouseButtonDown { mouse_btn, .. } => {
+                        mouse_pool.change_button(mouse_btn as u32);
+                    }
+                    Event::MouseMotion {
+                        x, y, xrel, yrel, ..
+                    } => {
+                        mouse_pool.change_motion(x, y, xrel, yrel);
+                    }
+                    _ => {}
                 }
-                Event::MouseButtonDown { mouse_btn, .. } => {
-                    mouse_pool.change_button(mouse_btn as u32);
-                }
-                Event::MouseMotion {
-                    x, y, xrel, yrel, ..
-                } => {
-                    mouse_pool.change_motion(x, y, xrel, yrel);
-                }
-                _ => {}
             }
-        }
 
-        if keyboard_pool.get_key(Keycode::Escape) {
-            break 'running;
-        }
+            if keyboard_pool.get_key(Keycode::Escape) {
+                break 'running;
+            }
 
-        if keyboard_pool.get_key(Keycode::W) {
-            camera.update_position(CameraDirection::FORWARD, delta_time);
-        }
-        if keyboard_pool.get_key(Keycode::S) {
-            camera.update_position(CameraDirection::BACKWARD, delta_time);
-        }
-        if keyboard_pool.get_key(Keycode::D) {
-            camera.update_position(CameraDirection::RIGHT, delta_time);
-        }
-        if keyboard_pool.get_key(Keycode::A) {
-            camera.update_position(CameraDirection::LEFT, delta_time);
-        }
-        if keyboard_pool.get_key(Keycode::Space) {
-            camera.update_position(CameraDirection::UP, delta_time);
-        }
-        if keyboard_pool.get_key(Keycode::LCtrl) {
-            camera.update_position(CameraDirection::DOWN, delta_time);
-        }
+            keyboard_pool.get_key(Keycode::F12)
+        };
 
-        camera.update_direction(mouse_pool.get_dx(), mouse_pool.get_dy(), delta_time);
+        stage.resource_mut::<DeltaTime>().unwrap().0 = delta_time;
 
+        stage.update();
+
+        let camera = *stage.resource::<Camera>().unwrap();
 
         let command_buffer = app
             .renderer
@@ -487,7 +476,7 @@ fn main() {
             camera,
         );
 
-        if keyboard_pool.get_key(Keycode::F12) {
+        if take_screenshot {
             save_color_image_as_png(
                 app.get_device(),
                 (
@@ -520,6 +509,8 @@ fn main() {
             .unwrap()
             .end_frame(&app.device, &mut app.window);
 
+        let mut fps = stage.resource_mut::<FPS>().unwrap();
+
         let title = String::from("Lumina Dev App ")
             + format!("[FPS: {:.0}]", fps.frame_count / fps.frame_elapsed).as_str();
         app.window.get_window().set_title(title.as_str()).unwrap();